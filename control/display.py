@@ -8,7 +8,7 @@ import WIFI_CONFIG
 import pngdec
 from network_manager import NetworkManager
 
-from gfx_pack import GfxPack
+from gfx_pack import GfxPack, SWITCH_E
 
 print("Starting display")
 
@@ -58,6 +58,9 @@ read_buf = ""
 sprite_valid = False
 data = None
 
+# Button E pauses and resumes the hunt
+pause_pressed = False
+
 def read_input():
     global read_mode, read_buf, encounters, prob, data, sprite_valid
     if poll_obj.poll(0):
@@ -107,3 +110,7 @@ while True:
     display.update()
     time.sleep(1.0 / 60)
     read_input()
+    pressed = gp.switch_pressed(SWITCH_E)
+    if pressed and not pause_pressed:
+        print("P")
+    pause_pressed = pressed
@@ -1,6 +1,10 @@
 from machine import Pin, UART
 import time
 
+PROTOCOL_VERSION = 3
+SEQ_MOD = 65536
+MAX_LINE = 256
+
 # Button mapping
 
 # Setup buttons to high impedance output (not-pressed)
@@ -22,7 +26,7 @@ pins = {
 }
 
 led = Pin(25, mode=Pin.OUT, value=0)
-uart = UART(0, baudrate=115200, bits=8, parity=None, tx=Pin(16), rx=Pin(17))
+uart = UART(0, baudrate=115200, bits=8, parity=None, tx=Pin(16), rx=Pin(17), rxbuf=1024)
 
 delay_times = {
   b'm': 0.05, # micro-pause
@@ -31,6 +35,96 @@ delay_times = {
   b'M': 1     # mega-pause
 }
 
+# Counters reported in reply to a ping
+received = 0
+malformed = 0
+dropped = 0
+expected_seq = None
+
+def reply(seq, *args):
+  uart.write(' '.join([str(seq)] + [str(a) for a in args]) + '\n')
+
+# Parse a 'q' command string into a list of actions, so a malformed command
+# is rejected before any buttons are changed
+def parse_buttons(data):
+  actions = []
+  i = 0
+  while i < len(data):
+    if data[i:i+1] != b'q' or i + 1 >= len(data):
+      return None, 'expected q at %d' % i
+    cmd = data[i+1:i+2]
+    if cmd in delay_times:
+      actions.append((None, int(delay_times[cmd] * 1000)))
+      i += 2
+    elif cmd == b'w':
+      # Wait in milliseconds, terminated by ';'
+      end = data.find(b';', i + 2)
+      try:
+        if end < 0:
+          raise ValueError
+        actions.append((None, int(data[i+2:end])))
+      except ValueError:
+        return None, 'bad wait at %d' % i
+      i = end + 1
+    elif cmd in pins:
+      val = data[i+2:i+3]
+      if val == b'1':
+        actions.append((cmd, 0)) # Pressed (active-low)
+      elif val == b'0':
+        actions.append((cmd, 1))
+      else:
+        return None, 'bad value for %s' % cmd.decode()
+      i += 3
+    else:
+      return None, 'unknown command %s' % cmd.decode()
+  return actions, None
+
+# Waits are measured from the start of the command so time spent changing
+# pins doesn't accumulate
+def run(actions):
+  deadline = time.ticks_ms()
+  for (cmd, val) in actions:
+    if cmd is None:
+      deadline = time.ticks_add(deadline, val)
+      remaining = time.ticks_diff(deadline, time.ticks_ms())
+      if remaining > 0:
+        time.sleep_ms(remaining)
+    else:
+      pins[cmd].value(val)
+
+def handle(line):
+  global received, malformed, dropped, expected_seq
+  parts = line.split(b' ', 2)
+  try:
+    seq = int(parts[0])
+  except ValueError:
+    malformed += 1
+    reply('-', 'E', 'bad sequence number')
+    return
+  op = parts[1] if len(parts) > 1 else b''
+  received += 1
+  # Version query starts a new session, otherwise a gap in sequence numbers
+  # means commands were lost
+  if op != b'V' and expected_seq is not None and seq != expected_seq:
+    dropped += (seq - expected_seq) % SEQ_MOD
+  expected_seq = (seq + 1) % SEQ_MOD
+
+  if op == b'V':
+    reply(seq, 'V', PROTOCOL_VERSION)
+  elif op == b'P':
+    reply(seq, 'P', received, malformed, dropped)
+  elif op == b'C' and len(parts) > 2:
+    actions, err = parse_buttons(parts[2])
+    if actions is None:
+      malformed += 1
+      reply(seq, 'E', err)
+    else:
+      run(actions)
+      reply(seq, 'K')
+  else:
+    malformed += 1
+    reply(seq, 'E', 'unknown operation')
+
 # Indicate setup complete
 led.value(1)
 time.sleep(0.5)
@@ -38,29 +132,23 @@ led.value(0)
 print('Shaoooh control board initialised...')
 uart.write('Shaoooh control board initialised...\n')
 
-# Start monitoring UART
-use_next_char = False
-current_cmd = None
+# Start monitoring UART, one command per line
+line = bytearray()
+overflow = False
 while True:
   if uart.any():
     byte = uart.read(1)
-    # 'q' used as delimiter to indicate next char is a valid command
-    if byte == b'q':
-        use_next_char = True
-    elif use_next_char:
-        # 'p' indicates pause, else use as indication of button to switch
-        if byte in delay_times:
-          time.sleep(delay_times[byte])
-        else:
-          current_cmd = byte
-        use_next_char = False
-    elif current_cmd is not None:
-        val = 1 # Not pressed (active-low)
-        if byte == b'1':
-            val = 0
-        if current_cmd in pins:
-          pins[current_cmd].value(val)
-        current_cmd = None
+    if byte == b'\n':
+      if overflow:
+        malformed += 1
+        reply('-', 'E', 'line too long')
+      elif len(line) > 0:
+        handle(bytes(line).strip())
+      line = bytearray()
+      overflow = False
+    elif len(line) < MAX_LINE:
+      line.extend(byte)
+    else:
+      overflow = True
   else:
-    time.sleep(0.01)
-
+    time.sleep(0.001)
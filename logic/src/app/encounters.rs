@@ -0,0 +1,54 @@
+use axum::{
+    Json,
+    extract::{Path as UrlPath, Query},
+};
+use serde::Deserialize;
+
+use super::Game;
+use crate::context::{
+    PkContext,
+    encounters::{Encounter, EncountersProvider},
+};
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct EncounterQuery {
+    // Nothing is found without a species
+    species: Option<u32>,
+    game: Option<Game>,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct LocationQuery {
+    game: Option<Game>,
+}
+
+// The encounter data is parsed on the first lookup, which takes a while, so
+// lookups are kept off the runtime's worker threads
+async fn lookup(
+    f: impl FnOnce(&EncountersProvider) -> Vec<Encounter> + Send + 'static,
+) -> Json<Vec<Encounter>> {
+    match tokio::task::spawn_blocking(move || f(PkContext::get().encounters())).await {
+        Ok(list) => Json(list),
+        Err(e) => {
+            log::error!("Encounter lookup failed: {}", e);
+            Json(vec![])
+        }
+    }
+}
+
+// Where a species can be found, for choosing a hunt
+#[axum::debug_handler]
+pub(crate) async fn get_encounters(Query(query): Query<EncounterQuery>) -> Json<Vec<Encounter>> {
+    let Some(species) = query.species else {
+        return Json(vec![]);
+    };
+    lookup(move |e| e.get_encounters(species, query.game)).await
+}
+
+#[axum::debug_handler]
+pub(crate) async fn get_location_encounters(
+    UrlPath(location): UrlPath<u32>,
+    Query(query): Query<LocationQuery>,
+) -> Json<Vec<Encounter>> {
+    lookup(move |e| e.get_location_encounters(location, query.game)).await
+}
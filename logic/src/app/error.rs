@@ -5,15 +5,26 @@ pub enum ShaooohError {
     UnexpectedEndOfLoop,
     CommunicationError,
     ProcessingError,
+    Stalled(String),
+    DatabaseError(String),
+}
+
+impl ShaooohError {
+    // Fatal errors shut down the application, others are only reported.
+    // Communication errors are recovered from by reconnecting.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, Self::Stalled(_) | Self::CommunicationError)
+    }
 }
 
 impl Display for ShaooohError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let str = match self {
-            Self::CommunicationError => "Communication Error",
-            Self::UnexpectedEndOfLoop => "Unexpected End of Loop",
-            Self::ProcessingError => "Processing Error",
-        };
-        write!(f, "{}", str)
+        match self {
+            Self::CommunicationError => write!(f, "Communication Error"),
+            Self::UnexpectedEndOfLoop => write!(f, "Unexpected End of Loop"),
+            Self::ProcessingError => write!(f, "Processing Error"),
+            Self::Stalled(reason) => write!(f, "Hunt Stalled: {}", reason),
+            Self::DatabaseError(e) => write!(f, "Database Error: {}", e),
+        }
     }
 }
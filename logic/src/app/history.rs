@@ -0,0 +1,337 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use axum::{
+    Json,
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::{ApiResponse, ApiState, HuntFile, HuntInformation, HuntState, HuntStore};
+
+const HUNT_DIR: &str = "hunts";
+const ARCHIVE_DIR: &str = "hunts/archive";
+
+// Short form of a hunt for listing, phases are only returned for a single hunt
+#[derive(Clone, Serialize)]
+pub(crate) struct HuntSummary {
+    name: String,
+    species: u32,
+    game: super::Game,
+    method: super::Method,
+    encounters: u64,
+    phases: usize,
+    unknown_phases: usize,
+    complete: bool,
+    archived: bool,
+    active: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct RequestPhaseSpecies {
+    species: u32,
+}
+
+#[derive(Clone, Deserialize)]
+pub(crate) struct RequestRename {
+    name: String,
+}
+
+// Management of the hunt files written by the main thread, the currently
+// active hunt can't be modified as the main thread would overwrite any changes
+pub(crate) struct HuntHistory {}
+
+impl HuntHistory {
+    fn path(archived: bool, name: &str) -> PathBuf {
+        let dir = if archived { ARCHIVE_DIR } else { HUNT_DIR };
+        Path::new(dir).join(format!("hunt_{}.json", name))
+    }
+
+    // Names are used to build file paths, so can't leave the hunt directory.
+    // Anything the start form accepts otherwise is allowed
+    pub(crate) fn valid_name(name: &str) -> bool {
+        !name.is_empty() && !name.contains(['/', '\\', '\0']) && !name.contains("..")
+    }
+
+    fn save(path: &Path, hunt: &HuntInformation) -> Result<(), String> {
+        HuntFile::write(path, hunt).map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+    }
+
+    fn store() -> Result<HuntStore, String> {
+        HuntStore::open_default().map_err(|e| format!("Couldn't open hunt database: {}", e))
+    }
+
+    // Hunts from the database, which has the current encounter counts, and any
+    // JSON files that haven't been imported
+    fn list(archived: bool) -> Vec<HuntInformation> {
+        let (mut hunts, known) = match Self::store().and_then(|store| {
+            let hunts = store.get_hunts(archived).map_err(|e| e.to_string())?;
+            let known: HashSet<String> = store
+                .get_all_hunts()
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .map(|h| h.name)
+                .collect();
+            Ok((hunts, known))
+        }) {
+            Ok(res) => res,
+            Err(e) => {
+                log::error!("{}", e);
+                (Vec::new(), HashSet::new())
+            }
+        };
+        hunts.extend(
+            Self::list_files(archived)
+                .into_iter()
+                .filter(|h| !known.contains(&h.name)),
+        );
+        hunts.sort_by(|a, b| a.name.cmp(&b.name));
+        hunts
+    }
+
+    fn list_files(archived: bool) -> Vec<HuntInformation> {
+        let dir = if archived { ARCHIVE_DIR } else { HUNT_DIR };
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|p| {
+                if let Ok(d) = p
+                    && let Ok(f) = d.file_type()
+                    && f.is_file()
+                    && d.path().extension().is_some_and(|x| x == "json")
+                {
+                    match HuntFile::load(&d.path()) {
+                        Ok(h) => return h,
+                        Err(e) => log::warn!("Skipping hunt file: {}", e),
+                    }
+                }
+                None
+            })
+            .collect()
+    }
+
+    // Find a hunt by name, returning whether it is archived. The JSON files
+    // are only written every so often, so the database is checked first
+    fn find(name: &str) -> Result<(bool, HuntInformation), String> {
+        if !Self::valid_name(name) {
+            return Err(format!("Invalid hunt name '{}'", name));
+        }
+        let store = Self::store()?;
+        if let Some(hunt) = store.get_hunt(name).map_err(|e| e.to_string())? {
+            let archived = store
+                .is_archived(name)
+                .map_err(|e| e.to_string())?
+                .unwrap_or(false);
+            return Ok((archived, hunt));
+        }
+        for archived in [false, true] {
+            if let Some(h) = HuntFile::load(&Self::path(archived, name))? {
+                return Ok((archived, h));
+            }
+        }
+        Err(format!("No hunt named '{}'", name))
+    }
+
+    // Keep the database in step with changes made to the files
+    fn mirror<F>(f: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut HuntStore) -> rusqlite::Result<()>,
+    {
+        let mut store = Self::store()?;
+        f(&mut store).map_err(|e| format!("Failed to update database: {}", e))
+    }
+
+    fn is_active(state: &ApiState, name: &str) -> bool {
+        let app = state.rx.borrow();
+        app.state != HuntState::Idle && app.arg.as_ref().is_some_and(|a| a.name == name)
+    }
+
+    // Load a hunt that isn't currently active, apply an update and write it back
+    fn modify<F>(state: &ApiState, name: &str, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut HuntInformation) -> Result<(), String>,
+    {
+        if Self::is_active(state, name) {
+            return Err(format!("Hunt '{}' is active, stop it first", name));
+        }
+        let (archived, mut hunt) = Self::find(name)?;
+        f(&mut hunt)?;
+        Self::save(&Self::path(archived, name), &hunt)?;
+        Self::mirror(|store| {
+            store.save_hunt(&hunt)?;
+            store.set_archived(name, archived)
+        })
+    }
+
+    fn set_phase_species(
+        state: &ApiState,
+        name: &str,
+        index: usize,
+        species: u32,
+    ) -> Result<(), String> {
+        Self::modify(state, name, |hunt| {
+            let phase = hunt
+                .phases
+                .get_mut(index)
+                .ok_or(format!("No phase {} in hunt '{}'", index, name))?;
+            log::info!(
+                "Updating phase {} of '{}' from #{} to #{}",
+                index,
+                name,
+                phase.species,
+                species
+            );
+            phase.species = species;
+            Ok(())
+        })
+    }
+
+    fn complete(state: &ApiState, name: &str) -> Result<(), String> {
+        Self::modify(state, name, |hunt| {
+            log::info!("Marking '{}' as complete", name);
+            hunt.complete = true;
+            if hunt.date.is_none() {
+                hunt.date = Some(Utc::now());
+            }
+            Ok(())
+        })
+    }
+
+    fn rename(state: &ApiState, name: &str, new_name: &str) -> Result<(), String> {
+        if !Self::valid_name(new_name) {
+            return Err(format!("Invalid hunt name '{}'", new_name));
+        }
+        if Self::find(new_name).is_ok() {
+            return Err(format!("Hunt '{}' already exists", new_name));
+        }
+        if Self::is_active(state, name) {
+            return Err(format!("Hunt '{}' is active, stop it first", name));
+        }
+        let (archived, mut hunt) = Self::find(name)?;
+        log::info!("Renaming '{}' to '{}'", name, new_name);
+        hunt.name = new_name.to_string();
+        let path = Self::path(archived, name);
+        if std::fs::exists(&path).unwrap_or(false) {
+            HuntFile::rename(&path, &Self::path(archived, new_name)).map_err(|e| e.to_string())?;
+        }
+        Self::save(&Self::path(archived, new_name), &hunt)?;
+        Self::mirror(|store| store.rename_hunt(name, new_name))
+    }
+
+    fn archive(state: &ApiState, name: &str) -> Result<(), String> {
+        if Self::is_active(state, name) {
+            return Err(format!("Hunt '{}' is active, stop it first", name));
+        }
+        let (archived, hunt) = Self::find(name)?;
+        if archived {
+            return Err(format!("Hunt '{}' is already archived", name));
+        }
+        log::info!("Archiving '{}'", name);
+        std::fs::create_dir_all(ARCHIVE_DIR).map_err(|e| e.to_string())?;
+        let path = Self::path(false, name);
+        if std::fs::exists(&path).unwrap_or(false) {
+            HuntFile::rename(&path, &Self::path(true, name)).map_err(|e| e.to_string())?;
+        }
+        // The export may be behind the database
+        Self::save(&Self::path(true, name), &hunt)?;
+        Self::mirror(|store| store.set_archived(name, true))
+    }
+
+    fn summary(state: &ApiState, hunt: &HuntInformation, archived: bool) -> HuntSummary {
+        HuntSummary {
+            name: hunt.name.clone(),
+            species: hunt.species,
+            game: hunt.game.clone(),
+            method: hunt.method.clone(),
+            encounters: hunt.encounters,
+            phases: hunt.phases.len(),
+            unknown_phases: hunt.phases.iter().filter(|p| p.species == 0).count(),
+            complete: hunt.complete,
+            archived,
+            active: Self::is_active(state, &hunt.name),
+        }
+    }
+}
+
+fn to_response(res: Result<(), String>) -> Json<ApiResponse> {
+    match res {
+        Ok(_) => Json(ApiResponse {
+            ok: true,
+            error: "".to_string(),
+        }),
+        Err(e) => {
+            log::warn!("Hunt history request failed: {}", e);
+            Json(ApiResponse {
+                ok: false,
+                error: e,
+            })
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub(crate) async fn get_hunts(State(state): State<ApiState>) -> Json<Vec<HuntSummary>> {
+    let mut hunts: Vec<HuntSummary> = HuntHistory::list(false)
+        .iter()
+        .map(|h| HuntHistory::summary(&state, h, false))
+        .collect();
+    hunts.extend(
+        HuntHistory::list(true)
+            .iter()
+            .map(|h| HuntHistory::summary(&state, h, true)),
+    );
+    Json(hunts)
+}
+
+#[axum::debug_handler]
+pub(crate) async fn get_hunt(
+    UrlPath(name): UrlPath<String>,
+) -> Result<Json<HuntInformation>, StatusCode> {
+    HuntHistory::find(&name)
+        .map(|(_, h)| Json(h))
+        .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+#[axum::debug_handler]
+pub(crate) async fn post_hunt_phase(
+    State(state): State<ApiState>,
+    UrlPath((name, index)): UrlPath<(String, usize)>,
+    Json(payload): Json<RequestPhaseSpecies>,
+) -> Json<ApiResponse> {
+    to_response(HuntHistory::set_phase_species(
+        &state,
+        &name,
+        index,
+        payload.species,
+    ))
+}
+
+#[axum::debug_handler]
+pub(crate) async fn post_hunt_complete(
+    State(state): State<ApiState>,
+    UrlPath(name): UrlPath<String>,
+) -> Json<ApiResponse> {
+    to_response(HuntHistory::complete(&state, &name))
+}
+
+#[axum::debug_handler]
+pub(crate) async fn post_hunt_rename(
+    State(state): State<ApiState>,
+    UrlPath(name): UrlPath<String>,
+    Json(payload): Json<RequestRename>,
+) -> Json<ApiResponse> {
+    to_response(HuntHistory::rename(&state, &name, &payload.name))
+}
+
+#[axum::debug_handler]
+pub(crate) async fn post_hunt_archive(
+    State(state): State<ApiState>,
+    UrlPath(name): UrlPath<String>,
+) -> Json<ApiResponse> {
+    to_response(HuntHistory::archive(&state, &name))
+}
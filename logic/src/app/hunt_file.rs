@@ -0,0 +1,130 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use super::HuntInformation;
+
+// Number of previous versions of each hunt file to keep
+const BACKUPS: usize = 3;
+
+// Crash safe persistence of hunt files. Files are written to a temporary file
+// and renamed over the original so a power cut never leaves a truncated file,
+// and a few previous versions are kept to recover from if a file is corrupt.
+pub(crate) struct HuntFile {}
+
+impl HuntFile {
+    fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut p = path.as_os_str().to_owned();
+        p.push(suffix);
+        PathBuf::from(p)
+    }
+
+    fn temp_path(path: &Path) -> PathBuf {
+        Self::with_suffix(path, ".tmp")
+    }
+
+    // Backups are named <file>.json.1 (newest) to <file>.json.N (oldest) so
+    // they aren't picked up as hunts
+    fn backup_path(path: &Path, n: usize) -> PathBuf {
+        Self::with_suffix(path, &format!(".{}", n))
+    }
+
+    fn sync_dir(path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn rotate_backups(path: &Path) -> std::io::Result<()> {
+        if !std::fs::exists(path)? {
+            return Ok(());
+        }
+        for n in (1..BACKUPS).rev() {
+            let from = Self::backup_path(path, n);
+            if std::fs::exists(&from)? {
+                std::fs::rename(&from, Self::backup_path(path, n + 1))?;
+            }
+        }
+        // Copy rather than rename so the live file always exists
+        std::fs::copy(path, Self::backup_path(path, 1))?;
+        Ok(())
+    }
+
+    pub(crate) fn write(path: &Path, hunt: &HuntInformation) -> std::io::Result<()> {
+        let temp = Self::temp_path(path);
+        let file = File::create(&temp)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, hunt)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        // Only keep backups of files that parse, otherwise a corrupt file could
+        // push out the last good copy
+        if Self::read(path).is_ok() {
+            Self::rotate_backups(path)?;
+        }
+        std::fs::rename(&temp, path)?;
+        Self::sync_dir(path)
+    }
+
+    fn read(path: &Path) -> Result<HuntInformation, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        serde_json::from_str(&data).map_err(|e| format!("Couldn't parse {}: {}", path.display(), e))
+    }
+
+    // Load a hunt file, falling back to the newest backup that parses.
+    // Returns Ok(None) if there is no hunt file at all, and an error if
+    // there are files but none of them could be read.
+    pub(crate) fn load(path: &Path) -> Result<Option<HuntInformation>, String> {
+        let candidates: Vec<PathBuf> = std::iter::once(path.to_path_buf())
+            .chain((1..=BACKUPS).map(|n| Self::backup_path(path, n)))
+            .filter(|p| std::fs::exists(p).unwrap_or(false))
+            .collect();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        let mut errors = Vec::new();
+        for p in candidates {
+            match Self::read(&p) {
+                Ok(hunt) => {
+                    if !errors.is_empty() {
+                        for e in &errors {
+                            log::warn!("{}", e);
+                        }
+                        log::warn!(
+                            "Recovered {} from backup {}, {} encounters",
+                            path.display(),
+                            p.display(),
+                            hunt.encounters
+                        );
+                    }
+                    return Ok(Some(hunt));
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        Err(format!(
+            "No readable version of {}: {}",
+            path.display(),
+            errors.join(", ")
+        ))
+    }
+
+    // Move a hunt file and its backups
+    pub(crate) fn rename(from: &Path, to: &Path) -> std::io::Result<()> {
+        for n in 1..=BACKUPS {
+            let backup = Self::backup_path(from, n);
+            if std::fs::exists(&backup)? {
+                std::fs::rename(&backup, Self::backup_path(to, n))?;
+            }
+        }
+        std::fs::rename(from, to)?;
+        Self::sync_dir(to)
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Exponential backoff between reconnection attempts
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    delay: Duration,
+    attempts: u32,
+    next: Instant,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(60))
+    }
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            delay: initial,
+            attempts: 0,
+            next: Instant::now(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.delay = self.initial;
+        self.attempts = 0;
+        self.next = Instant::now();
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    // Delay before the next attempt, doubling each time up to the maximum
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        self.attempts += 1;
+        self.next = Instant::now() + delay;
+        delay
+    }
+
+    // For polling from the main thread, whether enough time has passed since
+    // the last failed attempt
+    pub fn due(&self) -> bool {
+        Instant::now() >= self.next
+    }
+
+    pub async fn wait(&mut self) {
+        let delay = self.next_delay();
+        log::info!(
+            "Reconnecting in {}s (attempt {})",
+            delay.as_secs(),
+            self.attempts
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Shared record of which links to the console are currently down, the hunt
+// is held while any of them are reconnecting
+#[derive(Clone, Default)]
+pub struct Links {
+    down: Arc<Mutex<HashSet<&'static str>>>,
+}
+
+impl Links {
+    pub fn set_down(&self, name: &'static str) {
+        if let Ok(mut d) = self.down.lock()
+            && d.insert(name)
+        {
+            log::error!("Lost connection: {}", name);
+        }
+    }
+
+    pub fn set_up(&self, name: &'static str) {
+        if let Ok(mut d) = self.down.lock()
+            && d.remove(name)
+        {
+            log::info!("Reconnected: {}", name);
+        }
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.down.lock().map(|d| d.is_empty()).unwrap_or(true)
+    }
+
+    pub fn down(&self) -> Vec<&'static str> {
+        self.down
+            .lock()
+            .map(|d| d.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
@@ -19,6 +19,20 @@ struct Args {
     /// Skip connection test
     #[arg(short, long, default_value_t = false)]
     skip_conn: bool,
+    /// Don't open windows, debug images are served by the web API instead
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+}
+
+// Without a display highgui can't open windows
+#[cfg(target_os = "linux")]
+fn has_display() -> bool {
+    std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn has_display() -> bool {
+    true
 }
 
 pub fn main(cfg_fn: &dyn Fn() -> crate::app::Config, default_arg: TransitionArg) {
@@ -47,10 +61,15 @@ pub fn main(cfg_fn: &dyn Fn() -> crate::app::Config, default_arg: TransitionArg)
     } else {
         log::info!("Starting Shaoooh Bot");
 
-        let app = Shaoooh::new(config, default_arg);
-
-        match app.serve(args.skip_conn) {
-            Ok(_) => log::info!("Shaoooh done"),
+        let headless = args.headless || !has_display();
+        if headless {
+            log::info!("Running headless, debug images are at /api/debug");
+        }
+        match Shaoooh::new(config, default_arg, headless) {
+            Ok(app) => match app.serve(args.skip_conn) {
+                Ok(_) => log::info!("Shaoooh done"),
+                Err(e) => log::error!("{}", e),
+            },
             Err(e) => log::error!("{}", e),
         }
 
@@ -1,6 +1,5 @@
 use std::{
-    io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex, atomic::AtomicBool},
     thread::JoinHandle,
     time::Duration,
@@ -8,7 +7,7 @@ use std::{
 
 use axum::{
     Json, Router,
-    extract::State,
+    extract::{Path as UrlPath, State},
     http::{StatusCode, header},
     response::IntoResponse,
     routing::{get, post},
@@ -20,19 +19,36 @@ use tokio::sync::{broadcast, mpsc, watch};
 use tokio_util::sync::CancellationToken;
 use tower_http::services::ServeDir;
 use uuid::{Uuid, uuid};
+pub(crate) mod encounters;
 pub(crate) mod error;
+pub(crate) mod history;
+pub(crate) mod hunt_file;
+pub(crate) mod link;
 pub(crate) mod main;
+pub(crate) mod queue;
+pub(crate) mod schedule;
 pub(crate) mod states;
+pub(crate) mod store;
 use crate::{
     control::{
-        BishaanControl, BishaanControlSocket, BotControl, Button, Delay, NopControl, ShaooohControl,
+        BishaanControl, BishaanControlSocket, BotControl, Button, Delay, InputSequence, NopControl,
+        ShaooohControl,
     },
     displays::{DisplayWrapper, GfxDisplay, Webhook},
-    hunt::{HuntBuild, HuntFSM},
-    vision::{BishaanVision, BishaanVisionSocket, BotVision, NopVision, Vision},
+    fsm::{StateGraph, StateTimings, Trace},
+    hunt::{HuntBuild, HuntFSM, VariableValue, Variables},
+    vision::{
+        BishaanVision, BishaanVisionSocket, BotVision, DebugWindows, NTRMemoryReader,
+        NTRStreamSettings, NopVision, StreamStats, Vision,
+    },
 };
 pub use error::*;
+use hunt_file::HuntFile;
+pub use link::{Backoff, Links};
+use queue::HuntQueue;
+use schedule::Scheduler;
 pub use states::*;
+pub use store::*;
 use tokio::signal;
 
 pub use main::*;
@@ -61,9 +77,23 @@ struct ApiState {
     tx: mpsc::Sender<RequestTransition>,
     tx_conn: watch::Sender<bool>,
     button_tx: mpsc::Sender<(Button, Delay)>,
+    reload_tx: mpsc::Sender<()>,
     image: Arc<Mutex<Vec<u8>>>,
     image2: Arc<Mutex<Vec<u8>>>,
     found: Arc<Mutex<crate::vision::found::FoundToggle>>,
+    stream_stats: Arc<Mutex<StreamStats>>,
+    // Graph of the hunt and its current state, None while not hunting
+    fsm_graph: Arc<Mutex<Option<(StateGraph, usize)>>>,
+    // States visited since the hunt started
+    fsm_trace: Arc<Mutex<Trace>>,
+    // Variables declared by the hunt as of their last change, None while not
+    // hunting
+    fsm_vars: Arc<Mutex<Option<Variables>>>,
+    schedule: Arc<Mutex<Scheduler>>,
+    queue: Arc<Mutex<HuntQueue>>,
+    // Only for Bishaan
+    ntr_settings: Option<Arc<watch::Sender<NTRStreamSettings>>>,
+    windows: DebugWindows,
     mode: ResponseMode,
     default_arg: TransitionArg,
 }
@@ -75,14 +105,39 @@ pub struct Shaoooh {
     rx: mpsc::Receiver<RequestTransition>,
     rx_conn: watch::Receiver<bool>,
     button_rx: mpsc::Receiver<(Button, Delay)>,
+    // Rebuild the hunt, e.g. after editing hunt_overrides.json
+    reload_rx: mpsc::Receiver<()>,
     error_tx: Arc<broadcast::Sender<ShaooohError>>,
     image: Arc<Mutex<Vec<u8>>>,
     image2: Arc<Mutex<Vec<u8>>>,
     found: Arc<Mutex<crate::vision::found::FoundToggle>>,
+    stream_stats: Arc<Mutex<StreamStats>>,
+    fsm_graph: Arc<Mutex<Option<(StateGraph, usize)>>>,
+    fsm_trace: Arc<Mutex<Trace>>,
+    fsm_vars: Arc<Mutex<Option<Variables>>>,
+    // Starts and pauses hunts at set times
+    schedule: Arc<Mutex<Scheduler>>,
+    // Hunts to start after each catch
+    queue: Arc<Mutex<HuntQueue>>,
+    // Hunts caught, reported to the queue as they happen
+    complete_tx: mpsc::Sender<TransitionArg>,
+    complete_rx: Option<mpsc::Receiver<TransitionArg>>,
+    ntr_settings: Option<Arc<watch::Sender<NTRStreamSettings>>>,
+    windows: DebugWindows,
     config: Config,
     atomic: Arc<AtomicBool>,
+    store: HuntStore,
+    stalls: u32,
+    links: Links,
 }
 
+// How often to write the JSON export of the hunt while hunting, every
+// encounter is recorded in the database
+const JSON_EXPORT_INTERVAL: u64 = 64;
+
+// Number of times to try recovering a stalled hunt before pausing it
+const MAX_RECOVERIES: u32 = 3;
+
 // Struct to load/save from disc
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct HuntInformation {
@@ -97,7 +152,13 @@ pub struct HuntInformation {
 }
 
 impl Shaoooh {
-    pub fn new(config: Config, default_arg: TransitionArg) -> Self {
+    pub fn new(
+        config: Config,
+        default_arg: TransitionArg,
+        headless: bool,
+    ) -> Result<Self, ShaooohError> {
+        let store = HuntStore::open_default()
+            .map_err(|e| ShaooohError::DatabaseError(format!("{}: {}", HUNT_DATABASE, e)))?;
         let app = AppState {
             state: HuntState::Idle,
             arg: None,
@@ -108,10 +169,23 @@ impl Shaoooh {
         let (state_tx, state_rx) = watch::channel(app.clone());
         let (transition_tx, transition_rx) = mpsc::channel(1);
         let (button_tx, button_rx) = mpsc::channel(8);
+        let (reload_tx, reload_rx) = mpsc::channel(1);
         let (conn_tx, conn_rx) = watch::channel(false);
+        let (complete_tx, complete_rx) = mpsc::channel(4);
         let image_mutex = Arc::new(Mutex::new(Vec::new()));
         let image_mutex2 = Arc::new(Mutex::new(Vec::new()));
         let found_mutex = Arc::new(Mutex::new(crate::vision::found::FoundToggle::new()));
+        let stream_stats = Arc::new(Mutex::new(StreamStats::default()));
+        let fsm_graph = Arc::new(Mutex::new(None));
+        let fsm_trace = Arc::new(Mutex::new(Trace::default()));
+        let fsm_vars = Arc::new(Mutex::new(None));
+        let schedule = Arc::new(Mutex::new(Scheduler::load()));
+        let queue = Arc::new(Mutex::new(HuntQueue::load()));
+        let ntr_settings = match &config {
+            Config::Bishaan(opts) => Some(Arc::new(watch::Sender::new(opts.stream().clone()))),
+            _ => None,
+        };
+        let windows = DebugWindows::new(headless);
         let atomic = Arc::new(AtomicBool::new(true));
         // RX will subscribe later from TX reference
         let (error_tx_chnl, _error_rx) = broadcast::channel(32);
@@ -134,26 +208,49 @@ impl Shaoooh {
             tx: transition_tx,
             tx_conn: conn_tx,
             button_tx,
+            reload_tx,
             image: image_mutex.clone(),
             image2: image_mutex2.clone(),
             found: found_mutex.clone(),
+            stream_stats: stream_stats.clone(),
+            fsm_graph: fsm_graph.clone(),
+            fsm_trace: fsm_trace.clone(),
+            fsm_vars: fsm_vars.clone(),
+            schedule: schedule.clone(),
+            queue: queue.clone(),
+            ntr_settings: ntr_settings.clone(),
+            windows: windows.clone(),
             mode,
             default_arg,
         };
-        Self {
+        Ok(Self {
             api: Some(api),
             app,
             tx: state_tx,
             rx: transition_rx,
             rx_conn: conn_rx,
             button_rx,
+            reload_rx,
             error_tx,
             image: image_mutex,
             image2: image_mutex2,
             found: found_mutex,
+            stream_stats,
+            fsm_graph,
+            fsm_trace,
+            fsm_vars,
+            schedule,
+            queue,
+            complete_tx,
+            complete_rx: Some(complete_rx),
+            ntr_settings,
+            windows,
             config,
             atomic,
-        }
+            store,
+            stalls: 0,
+            links: Links::default(),
+        })
     }
 
     fn routes(state: ApiState) -> Router {
@@ -161,6 +258,7 @@ impl Shaoooh {
 
         Router::new()
             .route("/", get(get_index))
+            .route("/history", get(get_history))
             .nest_service("/static", static_dir)
             .route("/api/state", get(get_state).post(post_state))
             .route("/api/button", post(post_button))
@@ -170,6 +268,43 @@ impl Shaoooh {
             .route("/api/found-last", get(get_found_last))
             .route("/api/mode", get(get_mode))
             .route("/api/default", get(get_default_arg))
+            .route("/api/stream", get(get_stream_stats))
+            .route("/api/ntr", get(get_ntr_settings).post(post_ntr_settings))
+            .route("/api/fsm.svg", get(get_fsm_svg))
+            .route("/api/trace", get(get_trace))
+            .route("/api/trace/chrome", get(get_trace_chrome))
+            .route("/api/trace/timings", get(get_trace_timings))
+            .route("/api/variables", get(get_variables))
+            .route("/api/reload", post(post_reload))
+            .route(
+                "/api/schedule",
+                get(schedule::get_schedule).post(schedule::post_schedule),
+            )
+            .route("/api/queue", get(queue::get_queue).post(queue::post_queue))
+            .route("/api/queue/add", post(queue::post_queue_add))
+            .route("/api/queue/confirm", post(queue::post_queue_confirm))
+            .route("/api/encounters", get(encounters::get_encounters))
+            .route(
+                "/api/locations/{id}/encounters",
+                get(encounters::get_location_encounters),
+            )
+            .route("/api/debug", get(get_debug_windows))
+            .route("/api/debug/{name}", get(get_debug_window))
+            .route("/api/hunts", get(history::get_hunts))
+            .route("/api/hunts/{name}", get(history::get_hunt))
+            .route(
+                "/api/hunts/{name}/phases/{index}",
+                post(history::post_hunt_phase),
+            )
+            .route(
+                "/api/hunts/{name}/complete",
+                post(history::post_hunt_complete),
+            )
+            .route("/api/hunts/{name}/rename", post(history::post_hunt_rename))
+            .route(
+                "/api/hunts/{name}/archive",
+                post(history::post_hunt_archive),
+            )
             .with_state(state)
     }
 
@@ -198,69 +333,132 @@ impl Shaoooh {
         let mut res = Vec::new();
 
         for f in files {
-            let data = std::fs::read_to_string(f).expect("Couldn't read file");
-            let hunt: HuntInformation = serde_json::from_str(&data).expect("Failed to parse json");
-            res.push(hunt);
+            match HuntFile::load(&f) {
+                Ok(Some(hunt)) => res.push(hunt),
+                Ok(None) => {}
+                Err(e) => log::error!("{}", e),
+            }
         }
 
         res
     }
 
-    fn try_get_encounters(name: &str) -> (Vec<Phase>, u64) {
-        if std::fs::exists(Self::filename_from_name(name)).unwrap_or(false) {
-            // TODO error check information? And check if already complete?
-            let data = std::fs::read_to_string(Self::filename_from_name(name))
-                .expect("Couldn't read file");
-            let hunt: HuntInformation = serde_json::from_str(&data).expect("Failed to parse json");
-            (hunt.phases, hunt.encounters)
-        } else {
-            (Vec::new(), 0)
+    // Fails rather than starting from zero if the hunt exists but can't be read
+    fn try_get_encounters(&self, name: &str) -> Result<(Vec<Phase>, u64), String> {
+        match self.store.get_hunt(name) {
+            Ok(Some(hunt)) => return Ok((hunt.phases, hunt.encounters)),
+            Ok(None) => {}
+            Err(e) => log::error!("Failed to read hunt from database: {}", e),
+        }
+        // Fall back to JSON for hunts that haven't been imported
+        // TODO check if already complete?
+        match HuntFile::load(Path::new(&Self::filename_from_name(name)))? {
+            Some(hunt) => Ok((hunt.phases, hunt.encounters)),
+            None => Ok((Vec::new(), 0)),
+        }
+    }
+
+    fn hunt_information(&self) -> HuntInformation {
+        HuntInformation {
+            name: self.app.arg.as_ref().unwrap().name.clone(),
+            species: self.app.arg.as_ref().unwrap().species,
+            game: self.app.arg.as_ref().unwrap().game.clone(),
+            method: self.app.arg.as_ref().unwrap().method.clone(),
+            encounters: self.app.encounters,
+            phases: self.app.phases.clone(),
+            complete: self.app.state == HuntState::FoundTarget,
+            date: if self.app.state == HuntState::FoundTarget {
+                Some(Utc::now())
+            } else {
+                None
+            },
+        }
+    }
+
+    fn write_json(&self) {
+        let name = self.app.arg.as_ref().unwrap().name.clone();
+        let state = self.hunt_information();
+        if let Err(e) = HuntFile::write(Path::new(&Self::filename_from_name(&name)), &state) {
+            log::error!("Failed to write hunt file for '{}': {}", name, e);
         }
     }
 
     fn update_state(&mut self) {
-        if self.app.state != HuntState::Idle {
-            let name = self.app.arg.as_ref().unwrap().name.clone();
-            let state = HuntInformation {
-                name: self.app.arg.as_ref().unwrap().name.clone(),
-                species: self.app.arg.as_ref().unwrap().species,
-                game: self.app.arg.as_ref().unwrap().game.clone(),
-                method: self.app.arg.as_ref().unwrap().method.clone(),
-                encounters: self.app.encounters,
-                phases: self.app.phases.clone(),
-                complete: self.app.state == HuntState::FoundTarget,
-                date: if self.app.state == HuntState::FoundTarget {
-                    Some(Utc::now())
-                } else {
-                    None
-                },
-            };
-            let file = std::fs::File::create(Self::filename_from_name(&name)).unwrap();
-            let mut writer = BufWriter::new(file);
-            serde_json::to_writer_pretty(&mut writer, &state).expect("Failed to serialise state");
-            writer.flush().expect("Failed to flush to file");
+        // Also written when a hunt stops, so the JSON export isn't left behind
+        // the database
+        if let Some(arg) = &self.app.arg {
+            let mut hunt = self.hunt_information();
+            if self.app.state == HuntState::Idle
+                && let Ok(Some(saved)) = self.store.get_hunt(&arg.name)
+                && saved.complete
+            {
+                // Keep the completion recorded when the target was found
+                hunt.complete = true;
+                hunt.date = saved.date;
+            }
+            if let Err(e) = self.store.save_hunt(&hunt) {
+                log::error!("Failed to save hunt to database: {}", e);
+            }
+            if let Err(e) = HuntFile::write(Path::new(&Self::filename_from_name(&hunt.name)), &hunt)
+            {
+                log::error!("Failed to write hunt file for '{}': {}", hunt.name, e);
+            }
+        }
+        self.tx
+            .send(self.app.clone())
+            .expect("Couldn't update state");
+    }
+
+    // Lightweight update for a single encounter, only the database is written
+    // each time
+    fn update_encounters(&mut self) {
+        // Hunt is progressing, so any earlier recovery worked
+        self.stalls = 0;
+        let name = self.app.arg.as_ref().unwrap().name.clone();
+        if let Err(e) =
+            self.store
+                .record_encounter(&name, self.app.phases.len(), self.app.encounters)
+        {
+            log::error!("Failed to record encounter: {}", e);
+        }
+        if self.app.encounters % JSON_EXPORT_INTERVAL == 0 {
+            self.write_json();
         }
         self.tx
             .send(self.app.clone())
             .expect("Couldn't update state");
     }
 
+    fn build_hunt(&self) -> Option<HuntFSM> {
+        let arg = self.app.arg.as_ref()?;
+        HuntBuild::build(
+            arg.species,
+            arg.game.clone(),
+            arg.method.clone(),
+            self.atomic.clone(),
+        )
+    }
+
     fn transition_logic(
         &mut self,
         from: HuntState,
         hunt: &mut Option<HuntFSM>,
         transition: &Transition,
     ) -> bool {
-        if self.app.state == HuntState::Hunt && from != HuntState::Hunt {
+        if self.app.state == HuntState::Hunt && from == HuntState::Paused {
+            if let Some(h) = hunt {
+                h.resume();
+            }
+        } else if self.app.state == HuntState::Hunt && from != HuntState::Hunt {
             // Build hunt object
-            let target = self.app.arg.as_ref().unwrap().species;
-            let game = self.app.arg.as_ref().unwrap().game.clone();
-            let method = self.app.arg.as_ref().unwrap().method.clone();
-            let new_hunt = HuntBuild::build(target, game, method, self.atomic.clone());
+            let new_hunt = self.build_hunt();
+            self.stalls = 0;
+            self.fsm_trace.lock().unwrap().clear();
             match new_hunt {
                 Some(h) => *hunt = Some(h),
                 None => return false,
             };
+            self.publish_hunt(hunt);
         }
         let phased = (self.app.state != HuntState::FoundNonTarget
             && from == HuntState::FoundNonTarget
@@ -287,15 +485,30 @@ impl Shaoooh {
             // Reset the encounters after a phase
             self.app.encounters = 0;
         }
+        if self.app.state == HuntState::Paused
+            && let Some(h) = hunt
+        {
+            h.pause();
+        }
         if self.app.state == HuntState::Idle {
             if let Some(h) = hunt {
                 h.cleanup();
             }
             *hunt = None;
+            self.publish_hunt(hunt);
         }
         true
     }
 
+    // The graph is only laid out when the hunt is built, the web page draws
+    // it with the current state. The variables are then only copied when they
+    // change
+    fn publish_hunt(&self, hunt: &Option<HuntFSM>) {
+        *self.fsm_graph.lock().unwrap() =
+            hunt.as_ref().map(|h| (h.state_graph(), h.current_index()));
+        *self.fsm_vars.lock().unwrap() = hunt.as_ref().map(|h| h.variables().clone());
+    }
+
     fn do_transition(
         &mut self,
         transition_req: RequestTransition,
@@ -312,19 +525,40 @@ impl Shaoooh {
                 let arg = transition_req.arg;
                 if arg.is_some() == transition.needs_arg && (automatic || !transition.automatic) {
                     let prev_state = self.app.state.clone();
+                    // Caught from FoundTarget is the only way a hunt completes
+                    let complete = prev_state == HuntState::FoundTarget
+                        && transition.transition == Transition::Caught;
                     self.app.state = transition.next_state.clone();
                     if transition.needs_arg {
                         if transition.next_state == HuntState::FoundNonTarget {
                             self.app.last_phase = arg.unwrap().species;
                         } else {
-                            self.app.arg = Some(arg.unwrap());
-                            (self.app.phases, self.app.encounters) =
-                                Self::try_get_encounters(&self.app.arg.as_ref().unwrap().name);
+                            let arg = arg.unwrap();
+                            if !history::HuntHistory::valid_name(&arg.name) {
+                                log::error!("Not starting hunt, invalid name '{}'", arg.name);
+                                self.app.state = prev_state;
+                                return;
+                            }
+                            match self.try_get_encounters(&arg.name) {
+                                Ok(loaded) => (self.app.phases, self.app.encounters) = loaded,
+                                Err(e) => {
+                                    log::error!("Not starting hunt, couldn't load it: {}", e);
+                                    self.app.state = prev_state;
+                                    return;
+                                }
+                            }
+                            self.app.arg = Some(arg);
                             log::info!("Got argument: {:?}", self.app.arg);
                         }
                     }
                     if self.transition_logic(prev_state, hunt, &transition.transition) {
                         self.update_state();
+                        if complete && let Some(arg) = &self.app.arg {
+                            // Only full if the queue has stopped taking them
+                            if let Err(e) = self.complete_tx.try_send(arg.clone()) {
+                                log::warn!("Couldn't report hunt complete to the queue: {}", e);
+                            }
+                        }
                     } else {
                         log::error!("Failed to change state, resetting to idle");
                         self.app.state = HuntState::Idle;
@@ -348,51 +582,151 @@ impl Shaoooh {
         }
     }
 
+    fn save_snapshot(&self, frame: &[u8]) {
+        let name = self.app.arg.as_ref().unwrap().name.clone();
+        let path = format!(
+            "hunts/stall_{}_{}.png",
+            name,
+            Utc::now().format("%Y%m%d-%H%M%S")
+        );
+        match std::fs::write(&path, frame) {
+            Ok(_) => log::info!("Saved frame to {}", path),
+            Err(e) => log::error!("Failed to save frame to {}: {}", path, e),
+        }
+    }
+
+    fn check_watchdog(
+        &mut self,
+        hunt: &mut Option<HuntFSM>,
+        control: &mut Box<dyn BotControl>,
+        frame: &[u8],
+    ) {
+        if self.app.state != HuntState::Hunt {
+            return;
+        }
+        let Some(h) = hunt else {
+            return;
+        };
+        let Some(stall) = h.stalled() else {
+            return;
+        };
+        log::error!("Watchdog fired: {}", stall);
+        self.save_snapshot(frame);
+        let _ = self.error_tx.send(ShaooohError::Stalled(stall));
+        self.stalls += 1;
+        if self.stalls <= MAX_RECOVERIES && h.recover(control) {
+            log::warn!("Attempting recovery ({}/{})", self.stalls, MAX_RECOVERIES);
+        } else {
+            log::error!("Couldn't recover hunt, stopping");
+            let stop = RequestTransition {
+                transition: Transition::StopHunt,
+                arg: None,
+            };
+            self.do_transition(stop, hunt, true);
+        }
+    }
+
     fn main_thread(
         mut self,
         top_frame_rx: watch::Receiver<Mat>,
         bottom_frame_rx: watch::Receiver<Mat>,
-        button_tx: mpsc::Sender<(Vec<Button>, Delay)>,
+        button_tx: mpsc::Sender<InputSequence>,
         shutdown_token: CancellationToken,
         raw_frame_mutex: Arc<Mutex<Mat>>,
+        ntr_memory: NTRMemoryReader,
     ) {
         let (mut control, mut vision): (Box<dyn BotControl>, Box<dyn BotVision>) = match self.config
         {
             Config::Shaoooh(ref cfg) => (
-                Box::new(ShaooohControl::new(cfg.control())),
-                Box::new(Vision::new(cfg.video(), raw_frame_mutex)),
+                Box::new(ShaooohControl::new(
+                    cfg.control(),
+                    self.links.clone(),
+                    self.error_tx.clone(),
+                )),
+                Box::new(Vision::new(
+                    cfg.video(),
+                    raw_frame_mutex,
+                    self.links.clone(),
+                    self.windows.clone(),
+                )),
             ),
             Config::Bishaan(_) => (
                 Box::new(BishaanControl::new(button_tx)),
-                Box::new(BishaanVision::new(top_frame_rx, bottom_frame_rx)),
+                Box::new(BishaanVision::new(
+                    top_frame_rx,
+                    bottom_frame_rx,
+                    ntr_memory,
+                    self.windows.clone(),
+                )),
+            ),
+            Config::Ditto => (
+                Box::new(NopControl::new()),
+                Box::new(NopVision::new(self.windows.clone())),
             ),
-            Config::Ditto => (Box::new(NopControl::new()), Box::new(NopVision::new())),
         };
         let mut hunt: Option<HuntFSM> = None;
+        let mut held = false;
+        let mut reload = false;
 
         while !shutdown_token.is_cancelled() {
-            // What processing is needed
-            let processing = if let Some(h) = &mut hunt {
+            // Hold the hunt where it is while any link is reconnecting
+            let control_up = control.reconnect();
+            let vision_up = vision.reconnect();
+            let connected = control_up && vision_up && self.links.is_up();
+            if !connected && !held {
+                log::warn!(
+                    "Holding hunt until reconnected ({})",
+                    self.links.down().join(", ")
+                );
+                held = true;
+            } else if connected && held {
+                log::info!("Reconnected, resuming hunt");
+                if let Some(h) = &mut hunt {
+                    h.restart_timer();
+                }
+                held = false;
+            }
+
+            // What processing is needed, nothing while paused
+            let paused = self.app.state == HuntState::Paused;
+            let processing = if let Some(h) = &mut hunt
+                && !paused
+            {
                 h.processing()
             } else {
                 &Vec::new()
             };
             // Frame processing
-            if let Some(results) = vision.process_next_frame(processing) {
+            if held {
+                std::thread::sleep(Duration::from_millis(100));
+            } else if let Some(results) = vision.process_next_frame(processing) {
                 // Step state machines
-                if let Some(h) = &mut hunt {
+                if let Some(h) = &mut hunt
+                    && !paused
+                {
                     let result = h.step(&mut control, results);
-                    h.display();
+                    h.display(&self.windows);
+                    let spans = h.take_trace();
+                    if !spans.is_empty() {
+                        self.fsm_trace.lock().unwrap().extend(spans);
+                    }
+                    if let Ok(mut vars) = self.fsm_vars.try_lock()
+                        && let Some(vars) = vars.as_mut()
+                        && vars.changes() != h.variables().changes()
+                    {
+                        *vars = h.variables().clone();
+                    }
                     // Automatic transition requests
                     if result.incr_encounters {
                         self.app.encounters += 1;
                         log::info!("Current encounters: {}", self.app.encounters);
-                        self.update_state();
+                        self.update_encounters();
                     }
                     if let Some(transition_req) = result.transition {
                         self.do_transition(transition_req, &mut hunt, true);
                     }
                 }
+                self.check_watchdog(&mut hunt, &mut control, vision.read_frame());
 
                 if let Ok(mut img_wr) = self.image.try_lock() {
                     img_wr.clear();
@@ -407,6 +741,12 @@ impl Shaoooh {
                         found_guard.update(vision.read_found());
                     }
                 }
+                if let Some(h) = &hunt
+                    && let Ok(mut graph) = self.fsm_graph.try_lock()
+                    && let Some((_, current)) = graph.as_mut()
+                {
+                    *current = h.current_index();
+                }
             } else if !self.rx.is_closed() {
                 log::warn!("Failed to process frame");
             }
@@ -424,6 +764,33 @@ impl Shaoooh {
                 control.press_delay(&button, &delay);
             }
 
+            if !self.reload_rx.is_empty() && self.reload_rx.blocking_recv().is_some() {
+                log::info!("Reloading hunt at the start of its next cycle");
+                reload = true;
+            }
+            if reload && !matches!(self.app.state, HuntState::Hunt | HuntState::Paused) {
+                log::info!("Not hunting, reload cancelled");
+                reload = false;
+            }
+            // Swap in the rebuilt hunt between cycles, so the game and the new
+            // state machine agree on where they are
+            if reload
+                && self.app.state == HuntState::Hunt
+                && let Some(h) = &hunt
+                && h.at_start()
+            {
+                match self.build_hunt() {
+                    Some(h) => {
+                        log::info!("Reloaded hunt");
+                        hunt = Some(h);
+                        self.publish_hunt(&hunt);
+                        self.stalls = 0;
+                    }
+                    None => log::error!("Failed to rebuild hunt, keeping the old one"),
+                }
+                reload = false;
+            }
+
             if self.rx.is_closed() {
                 break;
             }
@@ -490,6 +857,15 @@ impl Shaoooh {
         let state = self.api.take().expect("Couldn't get API state");
         let rx_clone_hook = state.rx.clone();
         let rx_clone_disp = state.rx.clone();
+        let tx_clone_gfx = state.tx.clone();
+        let tx_clone_sched = state.tx.clone();
+        let rx_clone_sched = state.rx.clone();
+        let tx_clone_queue = state.tx.clone();
+        let rx_clone_queue = state.rx.clone();
+        let complete_rx = self
+            .complete_rx
+            .take()
+            .expect("Couldn't get hunt completions");
         let runtime_hndl = runtime.handle().clone();
         let error_rx_shutdown = self.error_tx.subscribe();
 
@@ -543,6 +919,7 @@ impl Shaoooh {
         let (t_frame_tx, t_frame_rx) = watch::channel(Mat::default());
         let (b_frame_tx, b_frame_rx) = watch::channel(Mat::default());
         let (button_tx, button_rx) = mpsc::channel(16);
+        let (ntr_memory, memory_rx) = NTRMemoryReader::channel();
         let error_rx_webhook = self.error_tx.subscribe();
 
         runtime.spawn(Webhook::call(
@@ -550,11 +927,27 @@ impl Shaoooh {
             error_rx_webhook,
             self.config.name(),
         ));
+        runtime.spawn(Scheduler::run(
+            self.schedule.clone(),
+            rx_clone_sched,
+            tx_clone_sched,
+            shutdown_token.clone(),
+        ));
+        runtime.spawn(HuntQueue::run(
+            self.queue.clone(),
+            rx_clone_queue,
+            complete_rx,
+            tx_clone_queue,
+            shutdown_token.clone(),
+        ));
 
         let mut displays: Vec<DisplayWrapper> = Vec::new();
         let mut handles: Vec<(String, JoinHandle<()>)> = Vec::new();
         let atomic_clone = self.atomic.clone();
         let error_tx_clone = self.error_tx.clone();
+        let links_clone = self.links.clone();
+        let stream_stats_clone = self.stream_stats.clone();
+        let ntr_settings_rx = self.ntr_settings.as_ref().map(|tx| tx.subscribe());
 
         log::info!("Adding state listeners and communication threads");
         match self.config {
@@ -564,10 +957,12 @@ impl Shaoooh {
                 log::info!("- Counter screen");
                 displays.push(DisplayWrapper::new(
                     "Gfx Screen".to_string(),
-                    Box::new(|| Box::new(GfxDisplay::default())),
+                    Box::new(|| Box::new(GfxDisplay::new(tx_clone_gfx))),
                 ));
             }
-            Config::Bishaan(ip) => {
+            Config::Bishaan(ref opts) => {
+                let ip = opts.ip();
+                let ntr_settings_rx = ntr_settings_rx.expect("Bishaan has NTR settings");
                 runtime.spawn(async move {
                     log::info!("- Frame stream Rx thread");
                     let vision = BishaanVisionSocket::new(
@@ -576,14 +971,23 @@ impl Shaoooh {
                         b_frame_tx,
                         atomic_clone,
                         error_tx_clone,
+                        links_clone.clone(),
+                        stream_stats_clone,
+                        memory_rx,
+                        ntr_settings_rx,
                     )
                     .await
                     .expect("Error creating vision thread");
                     let vision_handle = tokio::spawn(vision.task());
                     log::info!("- Control Tx thread");
-                    let control = BishaanControlSocket::new(ip, button_rx)
-                        .await
-                        .expect("Error creating control thread");
+                    let control = BishaanControlSocket::new(
+                        ip,
+                        BishaanControlSocket::PORT,
+                        button_rx,
+                        links_clone,
+                    )
+                    .await
+                    .expect("Error creating control thread");
                     let control_handle = tokio::spawn(control.task());
 
                     tokio::select! {
@@ -629,6 +1033,7 @@ impl Shaoooh {
             button_tx,
             shutdown_token.clone(),
             raw_frame_mutex,
+            ntr_memory,
         );
         log::info!("Main thread complete");
 
@@ -675,6 +1080,15 @@ async fn get_index(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
+#[axum::debug_handler]
+async fn get_history() -> impl IntoResponse {
+    let headers = [(header::CONTENT_TYPE, "text/html")];
+
+    std::fs::read("history.html")
+        .map(|b| (StatusCode::OK, headers, b))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
 #[axum::debug_handler]
 async fn get_state(State(state): State<ApiState>) -> Json<ResponseAppState> {
     let state = (*state.rx.borrow()).clone();
@@ -807,6 +1221,150 @@ async fn get_default_arg(State(state): State<ApiState>) -> Json<TransitionArg> {
     Json(state.default_arg)
 }
 
+#[axum::debug_handler]
+async fn get_stream_stats(State(state): State<ApiState>) -> impl IntoResponse {
+    if let Ok(stats) = state.stream_stats.lock() {
+        Ok(Json((*stats).clone()))
+    } else {
+        Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+#[axum::debug_handler]
+async fn get_ntr_settings(State(state): State<ApiState>) -> impl IntoResponse {
+    match &state.ntr_settings {
+        Some(tx) => Ok(Json(tx.borrow().clone())),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[axum::debug_handler]
+async fn post_ntr_settings(
+    State(state): State<ApiState>,
+    Json(payload): Json<NTRStreamSettings>,
+) -> Json<ApiResponse> {
+    let res = match (&state.ntr_settings, payload.validate()) {
+        (None, _) => Err("No NTR stream for this config".to_string()),
+        (_, Err(e)) => Err(e),
+        (Some(tx), Ok(())) => tx.send(payload).map_err(|e| e.to_string()),
+    };
+    match res {
+        Ok(_) => Json(ApiResponse {
+            ok: true,
+            error: "".to_string(),
+        }),
+        Err(e) => Json(ApiResponse {
+            ok: false,
+            error: e,
+        }),
+    }
+}
+
+#[axum::debug_handler]
+async fn get_fsm_svg(State(state): State<ApiState>) -> impl IntoResponse {
+    let headers = [
+        (header::CONTENT_TYPE, "image/svg+xml"),
+        (
+            header::CACHE_CONTROL,
+            "no-cache, must-revalidate, max-age=0, no-store",
+        ),
+    ];
+
+    let svg = match state.fsm_graph.lock() {
+        Ok(graph) => graph.as_ref().map(|(g, current)| g.svg(*current)),
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, headers, String::new()),
+    };
+    match svg {
+        Some(svg) => (StatusCode::OK, headers, svg),
+        None => (StatusCode::NOT_FOUND, headers, String::new()),
+    }
+}
+
+#[axum::debug_handler]
+async fn get_trace(State(state): State<ApiState>) -> impl IntoResponse {
+    let headers = [(header::CONTENT_TYPE, "application/json")];
+    (headers, state.fsm_trace.lock().unwrap().json())
+}
+
+// Chrome trace event format, for Perfetto
+#[axum::debug_handler]
+async fn get_trace_chrome(State(state): State<ApiState>) -> impl IntoResponse {
+    let headers = [
+        (header::CONTENT_TYPE, "application/json"),
+        (
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"shaoooh_trace.json\"",
+        ),
+    ];
+    (headers, state.fsm_trace.lock().unwrap().chrome())
+}
+
+#[axum::debug_handler]
+async fn get_trace_timings(State(state): State<ApiState>) -> Json<Vec<StateTimings>> {
+    Json(state.fsm_trace.lock().unwrap().timings())
+}
+
+// Rebuild the running hunt once it's back at its first state, encounters and
+// the rest of the app state are kept
+#[axum::debug_handler]
+async fn post_reload(State(state): State<ApiState>) -> Json<ApiResponse> {
+    let res = if !matches!(state.rx.borrow().state, HuntState::Hunt | HuntState::Paused) {
+        Err("Not hunting".to_string())
+    } else {
+        match state.reload_tx.try_send(()) {
+            // Already waiting to reload
+            Ok(_) | Err(mpsc::error::TrySendError::Full(_)) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        }
+    };
+    match res {
+        Ok(_) => Json(ApiResponse {
+            ok: true,
+            error: "".to_string(),
+        }),
+        Err(e) => Json(ApiResponse {
+            ok: false,
+            error: e,
+        }),
+    }
+}
+
+#[axum::debug_handler]
+async fn get_variables(State(state): State<ApiState>) -> Json<Vec<VariableValue>> {
+    Json(
+        state
+            .fsm_vars
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(Vec::new(), |v| v.values()),
+    )
+}
+
+#[axum::debug_handler]
+async fn get_debug_windows(State(state): State<ApiState>) -> Json<Vec<&'static str>> {
+    Json(state.windows.names())
+}
+
+#[axum::debug_handler]
+async fn get_debug_window(
+    State(state): State<ApiState>,
+    UrlPath(name): UrlPath<String>,
+) -> impl IntoResponse {
+    let headers = [
+        (header::CONTENT_TYPE, "image/png"),
+        (
+            header::CACHE_CONTROL,
+            "no-cache, must-revalidate, max-age=0, no-store",
+        ),
+    ];
+
+    match state.windows.image(&name) {
+        Some(img) => (StatusCode::OK, headers, img),
+        None => (StatusCode::NOT_FOUND, headers, Vec::new()),
+    }
+}
+
 async fn shutdown(
     shutdown_token: CancellationToken,
     mut error_rx: broadcast::Receiver<ShaooohError>,
@@ -824,14 +1382,26 @@ async fn shutdown(
             .await;
     };
 
+    let fatal_error = async {
+        loop {
+            match error_rx.recv().await {
+                Ok(e) if !e.is_fatal() => log::warn!("Got Error [{}], continuing", e),
+                Ok(e) => {
+                    log::error!("Got Error [{}], shutting down", e);
+                    break;
+                }
+                Err(_) => {
+                    log::error!("Error channel dropped");
+                    break;
+                }
+            }
+        }
+    };
+
     tokio::select! {
         _ = ctrl_c => {},
         _ = terminate => {},
-        err = error_rx.recv() => {
-            match err {
-                Ok(e) => log::error!("Got Error [{}], shutting down", e),
-                Err(_) => log::error!("Error channel dropped")
-            }
+        _ = fatal_error => {
             tokio::time::sleep(Duration::from_secs(5)).await;
         }
     }
@@ -0,0 +1,297 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    ApiResponse, ApiState, AppState, Game, HuntState, RequestTransition, Transition, TransitionArg,
+    history::HuntHistory,
+};
+use crate::hunt::HuntBuild;
+
+const QUEUE_FILE: &str = "queue.json";
+
+// How often the queue checks whether to start the next hunt
+const QUEUE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Hunts to run one after another, read from queue.json and set through
+// /api/queue
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct QueueFile {
+    // Wait after a catch before starting the next hunt, to catch or save by hand
+    grace_secs: u64,
+    // If set, the next hunt is only started if confirmed within this long
+    // after the grace period
+    confirm_secs: Option<u64>,
+    hunts: Vec<TransitionArg>,
+}
+
+impl QueueFile {
+    // Empty if there is no file, or it can't be read. Hunts that can't be
+    // built are left out
+    fn load() -> Self {
+        if !std::fs::exists(QUEUE_FILE).unwrap_or(false) {
+            return Self::default();
+        }
+        let mut queue: Self = match std::fs::read_to_string(QUEUE_FILE)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_str(&data).map_err(|e| e.to_string()))
+        {
+            Ok(queue) => queue,
+            Err(e) => {
+                log::warn!("Ignoring {}: {}", QUEUE_FILE, e);
+                return Self::default();
+            }
+        };
+        queue.hunts.retain(|arg| match Self::validate(arg) {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("Ignoring queued hunt in {}: {}", QUEUE_FILE, e);
+                false
+            }
+        });
+        queue
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(QUEUE_FILE, data)
+            .map_err(|e| format!("Couldn't write {}: {}", QUEUE_FILE, e))
+    }
+
+    // Only hunts that can be built are queued, so a bad entry doesn't stop the queue
+    fn validate(arg: &TransitionArg) -> Result<(), String> {
+        if !HuntHistory::valid_name(&arg.name) {
+            return Err(format!("Invalid hunt name {:?}", arg.name));
+        }
+        if HuntBuild::builder(arg.species, arg.game.clone(), arg.method.clone()).is_none() {
+            return Err(format!(
+                "No {:?} hunt for {} in {:?}",
+                arg.method, arg.species, arg.game
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Next hunt waiting to start after a catch
+#[derive(Debug)]
+struct Pending {
+    // Only hunts in the same game as the one completed are started
+    game: Game,
+    since: Instant,
+    confirmed: bool,
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub(crate) struct QueueStatus {
+    queue: QueueFile,
+    // Next hunt to start, once the remaining grace period is over
+    next: Option<TransitionArg>,
+    grace_remaining_secs: Option<u64>,
+    // Time left to confirm the next hunt, once the grace period is over
+    confirm_remaining_secs: Option<u64>,
+    confirmed: bool,
+}
+
+// Starts the next compatible queued hunt when one is caught, by sending the
+// same transitions as the web page
+#[derive(Debug)]
+pub struct HuntQueue {
+    queue: QueueFile,
+    pending: Option<Pending>,
+}
+
+impl HuntQueue {
+    pub fn load() -> Self {
+        let queue = QueueFile::load();
+        if !queue.hunts.is_empty() {
+            log::info!("{} hunts queued", queue.hunts.len());
+        }
+        Self {
+            queue,
+            pending: None,
+        }
+    }
+
+    // Index of the first queued hunt in the game
+    fn next(&self, game: &Game) -> Option<usize> {
+        self.queue.hunts.iter().position(|a| a.game == *game)
+    }
+
+    fn grace(&self) -> Duration {
+        Duration::from_secs(self.queue.grace_secs)
+    }
+
+    fn status(&self) -> QueueStatus {
+        let now = Instant::now();
+        let pending = self.pending.as_ref();
+        let grace_end = pending.map(|p| p.since + self.grace());
+        QueueStatus {
+            queue: self.queue.clone(),
+            next: pending
+                .and_then(|p| self.next(&p.game))
+                .map(|i| self.queue.hunts[i].clone()),
+            grace_remaining_secs: grace_end.map(|t| t.saturating_duration_since(now).as_secs()),
+            confirm_remaining_secs: grace_end.zip(self.queue.confirm_secs).map(|(t, c)| {
+                (t + Duration::from_secs(c))
+                    .saturating_duration_since(now)
+                    .as_secs()
+            }),
+            confirmed: pending.is_some_and(|p| p.confirmed),
+        }
+    }
+
+    fn confirm(&mut self) -> Result<(), String> {
+        match &mut self.pending {
+            Some(p) => {
+                p.confirmed = true;
+                Ok(())
+            }
+            None => Err("No hunt waiting to start".to_string()),
+        }
+    }
+
+    // Reported by the main loop as the hunt is caught
+    fn complete(&mut self, arg: &TransitionArg, now: Instant) {
+        log::info!(
+            "Hunt {} complete, next queued hunt in {}s",
+            arg.name,
+            self.queue.grace_secs
+        );
+        self.pending = Some(Pending {
+            game: arg.game.clone(),
+            since: now,
+            confirmed: false,
+        });
+    }
+
+    // Hunt to start now, if any
+    fn tick(&mut self, state: &AppState, now: Instant) -> Option<TransitionArg> {
+        if state.state != HuntState::Idle {
+            // Started by hand
+            self.pending = None;
+            return None;
+        }
+
+        let pending = self.pending.as_ref()?;
+        let grace_end = pending.since + self.grace();
+        if now < grace_end {
+            return None;
+        }
+        let Some(index) = self.next(&pending.game) else {
+            log::info!("No more hunts queued for {:?}", pending.game);
+            self.pending = None;
+            return None;
+        };
+        if let Some(confirm) = self.queue.confirm_secs
+            && !pending.confirmed
+        {
+            if now >= grace_end + Duration::from_secs(confirm) {
+                log::info!("Next queued hunt wasn't confirmed, not starting it");
+                self.pending = None;
+            }
+            return None;
+        }
+
+        self.pending = None;
+        let arg = self.queue.hunts.remove(index);
+        if let Err(e) = self.queue.save() {
+            log::error!("{}", e);
+        }
+        log::info!("Starting queued hunt {}", arg.name);
+        Some(arg)
+    }
+
+    pub async fn run(
+        queue: Arc<Mutex<HuntQueue>>,
+        rx: watch::Receiver<AppState>,
+        mut complete_rx: mpsc::Receiver<TransitionArg>,
+        tx: mpsc::Sender<RequestTransition>,
+        shutdown_token: CancellationToken,
+    ) {
+        let mut interval = tokio::time::interval(QUEUE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown_token.cancelled() => break,
+                Some(arg) = complete_rx.recv() => {
+                    queue.lock().unwrap().complete(&arg, Instant::now());
+                }
+                _ = interval.tick() => {}
+            }
+            let state = rx.borrow().clone();
+            let arg = queue.lock().unwrap().tick(&state, Instant::now());
+            if let Some(arg) = arg {
+                let start = RequestTransition {
+                    transition: Transition::StartHunt,
+                    arg: Some(arg),
+                };
+                if tx.send(start).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn to_response(res: Result<(), String>) -> Json<ApiResponse> {
+    match res {
+        Ok(_) => Json(ApiResponse {
+            ok: true,
+            error: "".to_string(),
+        }),
+        Err(e) => {
+            log::warn!("Hunt queue request failed: {}", e);
+            Json(ApiResponse {
+                ok: false,
+                error: e,
+            })
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub(crate) async fn get_queue(State(state): State<ApiState>) -> Json<QueueStatus> {
+    Json(state.queue.lock().unwrap().status())
+}
+
+// Replaces the queue and its settings
+#[axum::debug_handler]
+pub(crate) async fn post_queue(
+    State(state): State<ApiState>,
+    Json(payload): Json<QueueFile>,
+) -> Json<ApiResponse> {
+    let res = payload
+        .hunts
+        .iter()
+        .try_for_each(QueueFile::validate)
+        .and_then(|_| payload.save());
+    if res.is_ok() {
+        state.queue.lock().unwrap().queue = payload;
+    }
+    to_response(res)
+}
+
+#[axum::debug_handler]
+pub(crate) async fn post_queue_add(
+    State(state): State<ApiState>,
+    Json(payload): Json<TransitionArg>,
+) -> Json<ApiResponse> {
+    let res = QueueFile::validate(&payload).and_then(|_| {
+        let mut queue = state.queue.lock().unwrap();
+        queue.queue.hunts.push(payload);
+        queue.queue.save()
+    });
+    to_response(res)
+}
+
+#[axum::debug_handler]
+pub(crate) async fn post_queue_confirm(State(state): State<ApiState>) -> Json<ApiResponse> {
+    to_response(state.queue.lock().unwrap().confirm())
+}
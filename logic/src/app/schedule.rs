@@ -0,0 +1,313 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{Json, extract::State};
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    ApiResponse, ApiState, AppState, HuntState, RequestTransition, Transition, TransitionArg,
+};
+
+const SCHEDULE_FILE: &str = "schedule.json";
+
+// How often the schedule is checked against the state
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HuntWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl HuntWindow {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            // Wraps past midnight
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+// When the bot should hunt, read from schedule.json and set through
+// /api/schedule
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Schedule {
+    enabled: bool,
+    // Only hunt between these local times, any time if not set
+    window: Option<HuntWindow>,
+    // Hunt to start when the window opens, if nothing is being hunted
+    start: Option<TransitionArg>,
+    // Pause for rest_mins after hunting for this long, e.g. to let a 3DS charge
+    max_hours: Option<f64>,
+    max_encounters: Option<u64>,
+    rest_mins: u64,
+}
+
+impl Schedule {
+    // Disabled if there is no file, or it can't be read
+    fn load() -> Self {
+        if !std::fs::exists(SCHEDULE_FILE).unwrap_or(false) {
+            return Self::default();
+        }
+        match std::fs::read_to_string(SCHEDULE_FILE)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_str(&data).map_err(|e| e.to_string()))
+        {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                log::warn!("Ignoring {}: {}", SCHEDULE_FILE, e);
+                Self::default()
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(SCHEDULE_FILE, data)
+            .map_err(|e| format!("Couldn't write {}: {}", SCHEDULE_FILE, e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if let Some(w) = &self.window
+            && w.start == w.end
+        {
+            return Err("Window start and end are the same".to_string());
+        }
+        if self.max_hours.is_some_and(|h| h <= 0.0) {
+            return Err("Max hours must be more than 0".to_string());
+        }
+        if self.max_encounters == Some(0) {
+            return Err("Max encounters must be more than 0".to_string());
+        }
+        if let Some(arg) = &self.start
+            && (arg.species == 0 || arg.species > arg.game.last_species())
+        {
+            return Err(format!("Species {} isn't in {:?}", arg.species, arg.game));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize, Debug)]
+pub(crate) struct ScheduleStatus {
+    schedule: Schedule,
+    // Whether the schedule currently allows hunting
+    active: bool,
+    // Paused by the schedule, and will be resumed by it
+    paused: bool,
+    rest_remaining_secs: Option<u64>,
+    // Current stretch of hunting, since the hunt was last started or resumed
+    hunting_secs: Option<u64>,
+    hunting_encounters: Option<u64>,
+}
+
+// Starts, pauses and resumes hunts to follow the schedule, by sending the
+// same transitions as the web page
+#[derive(Debug)]
+pub struct Scheduler {
+    schedule: Schedule,
+    in_window: Option<bool>,
+    active: bool,
+    // A hunt paused by hand is left alone
+    paused: bool,
+    rest_until: Option<Instant>,
+    // When the hunt was last started or resumed, and the encounters since
+    hunting_since: Option<Instant>,
+    hunting_encounters: u64,
+    // Hunt's encounters at the last tick
+    last_encounters: Option<u64>,
+}
+
+impl Scheduler {
+    pub fn load() -> Self {
+        let schedule = Schedule::load();
+        if schedule.enabled {
+            log::info!("Hunt schedule enabled");
+        }
+        Self {
+            schedule,
+            in_window: None,
+            active: false,
+            paused: false,
+            rest_until: None,
+            hunting_since: None,
+            hunting_encounters: 0,
+            last_encounters: None,
+        }
+    }
+
+    fn set(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+        // Treat the window as just opened, and forget any rest
+        self.in_window = None;
+        self.rest_until = None;
+    }
+
+    fn status(&self) -> ScheduleStatus {
+        let now = Instant::now();
+        ScheduleStatus {
+            schedule: self.schedule.clone(),
+            active: self.active,
+            paused: self.paused,
+            rest_remaining_secs: self
+                .rest_until
+                .map(|t| t.saturating_duration_since(now).as_secs()),
+            hunting_secs: self
+                .hunting_since
+                .map(|since| now.duration_since(since).as_secs()),
+            hunting_encounters: self.hunting_since.map(|_| self.hunting_encounters),
+        }
+    }
+
+    // Transition needed to follow the schedule, if any
+    fn tick(&mut self, state: &AppState, time: NaiveTime, now: Instant) -> Option<Transition> {
+        // Found states are part of hunting, they go back to Hunt
+        match state.state {
+            HuntState::Hunt if self.hunting_since.is_none() => {
+                self.hunting_since = Some(now);
+                self.hunting_encounters = 0;
+            }
+            HuntState::Idle | HuntState::Paused => self.hunting_since = None,
+            _ => {
+                // Counted as they are seen, as a phase resets the hunt's count
+                if let Some(last) = self.last_encounters {
+                    self.hunting_encounters += if state.encounters >= last {
+                        state.encounters - last
+                    } else {
+                        state.encounters
+                    };
+                }
+            }
+        }
+        self.last_encounters = Some(state.encounters);
+        if state.state != HuntState::Paused {
+            self.paused = false;
+        }
+        if !self.schedule.enabled {
+            self.in_window = None;
+            self.active = false;
+            return None;
+        }
+
+        if self.rest_until.is_some_and(|t| now >= t) {
+            log::info!("Rest over");
+            self.rest_until = None;
+        }
+        if self.rest_until.is_none()
+            && let Some(since) = self.hunting_since
+        {
+            let hours = now.duration_since(since).as_secs_f64() / 3600.0;
+            let encounters = self.hunting_encounters;
+            if self.schedule.max_hours.is_some_and(|h| hours >= h)
+                || self
+                    .schedule
+                    .max_encounters
+                    .is_some_and(|e| encounters >= e)
+            {
+                log::info!(
+                    "Resting for {} minutes after {:.1} hours and {} encounters",
+                    self.schedule.rest_mins,
+                    hours,
+                    encounters
+                );
+                self.rest_until = Some(now + Duration::from_secs(self.schedule.rest_mins * 60));
+            }
+        }
+
+        let in_window = self
+            .schedule
+            .window
+            .as_ref()
+            .is_none_or(|w| w.contains(time));
+        // Only start a hunt as the window opens, so stopping one by hand sticks
+        let opened = in_window && self.in_window != Some(true);
+        self.in_window = Some(in_window);
+        self.active = in_window && self.rest_until.is_none();
+
+        match state.state {
+            HuntState::Hunt if !self.active => {
+                log::info!("Pausing hunt for the schedule");
+                self.paused = true;
+                Some(Transition::PauseHunt)
+            }
+            HuntState::Paused if self.active && self.paused => {
+                log::info!("Resuming hunt for the schedule");
+                self.paused = false;
+                Some(Transition::ResumeHunt)
+            }
+            HuntState::Idle if opened && self.active && self.schedule.start.is_some() => {
+                log::info!("Starting hunt for the schedule");
+                Some(Transition::StartHunt)
+            }
+            _ => None,
+        }
+    }
+
+    pub async fn run(
+        scheduler: Arc<Mutex<Scheduler>>,
+        rx: watch::Receiver<AppState>,
+        tx: mpsc::Sender<RequestTransition>,
+        shutdown_token: CancellationToken,
+    ) {
+        let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown_token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let state = rx.borrow().clone();
+            let request = {
+                let mut s = scheduler.lock().unwrap();
+                s.tick(&state, Local::now().time(), Instant::now())
+                    .map(|transition| RequestTransition {
+                        arg: match transition {
+                            Transition::StartHunt => s.schedule.start.clone(),
+                            _ => None,
+                        },
+                        transition,
+                    })
+            };
+            if let Some(request) = request
+                && tx.send(request).await.is_err()
+            {
+                break;
+            }
+        }
+    }
+}
+
+#[axum::debug_handler]
+pub(crate) async fn get_schedule(State(state): State<ApiState>) -> Json<ScheduleStatus> {
+    Json(state.schedule.lock().unwrap().status())
+}
+
+// Replaces the schedule and saves it for the next run
+#[axum::debug_handler]
+pub(crate) async fn post_schedule(
+    State(state): State<ApiState>,
+    Json(payload): Json<Schedule>,
+) -> Json<ApiResponse> {
+    let res = payload.validate().and_then(|_| payload.save());
+    match res {
+        Ok(_) => {
+            log::info!("Hunt schedule updated, enabled = {}", payload.enabled);
+            state.schedule.lock().unwrap().set(payload);
+            Json(ApiResponse {
+                ok: true,
+                error: "".to_string(),
+            })
+        }
+        Err(e) => Json(ApiResponse {
+            ok: false,
+            error: e,
+        }),
+    }
+}
@@ -1,11 +1,16 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use strum_macros::{EnumCount, EnumString, IntoStaticStr};
+use strum_macros::{EnumCount, EnumIter, EnumString, IntoStaticStr};
+
+use crate::vision::NTRStreamSettings;
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum Transition {
     StartHunt,
+    // Hold the hunt where it is, until resumed
     PauseHunt,
+    ResumeHunt,
+    StopHunt,
     FoundNonTarget,
     FoundTarget,
     Fail,
@@ -18,6 +23,7 @@ pub enum Transition {
 pub enum HuntState {
     Idle,
     Hunt,
+    Paused,
     FoundNonTarget,
     FoundTarget,
 }
@@ -48,6 +54,12 @@ impl HuntState {
             Self::Hunt => vec![
                 StateTransition {
                     transition: Transition::PauseHunt,
+                    next_state: Self::Paused,
+                    needs_arg: false,
+                    automatic: false,
+                },
+                StateTransition {
+                    transition: Transition::StopHunt,
                     next_state: Self::Idle,
                     needs_arg: false,
                     automatic: false,
@@ -65,6 +77,20 @@ impl HuntState {
                     automatic: true,
                 },
             ],
+            Self::Paused => vec![
+                StateTransition {
+                    transition: Transition::ResumeHunt,
+                    next_state: Self::Hunt,
+                    needs_arg: false,
+                    automatic: false,
+                },
+                StateTransition {
+                    transition: Transition::StopHunt,
+                    next_state: Self::Idle,
+                    needs_arg: false,
+                    automatic: false,
+                },
+            ],
             Self::FoundNonTarget => vec![
                 StateTransition {
                     transition: Transition::Caught,
@@ -143,6 +169,7 @@ impl TransitionArg {
     EnumString,
     IntoStaticStr,
     EnumCount,
+    EnumIter,
     Hash,
     std::cmp::Eq,
 )]
@@ -159,7 +186,20 @@ pub enum Game {
     UltraSunUltraMoon,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+impl Game {
+    // Last national dex number in the game
+    pub fn last_species(&self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::RubySapphire | Self::Emerald | Self::FireRedLeafGreen => 386,
+            Self::DiamondPearl | Self::Platinum | Self::HeartGoldSoulSilver => 493,
+            Self::BlackWhite | Self::Black2White2 => 649,
+            Self::UltraSunUltraMoon => 807,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, EnumString, EnumIter, IntoStaticStr)]
 pub enum Method {
     RandomEncounter,
     SoftResetEncounter,
@@ -206,12 +246,40 @@ impl CaptureControlPaths {
     }
 }
 
+#[derive(Debug)]
+pub struct BishaanOptions {
+    ip: core::net::Ipv4Addr,
+    stream: NTRStreamSettings,
+}
+
+impl BishaanOptions {
+    pub fn new(ip: core::net::Ipv4Addr) -> Self {
+        Self {
+            ip,
+            stream: NTRStreamSettings::default(),
+        }
+    }
+
+    pub fn with_stream(mut self, stream: NTRStreamSettings) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    pub fn ip(&self) -> core::net::Ipv4Addr {
+        self.ip
+    }
+
+    pub fn stream(&self) -> &NTRStreamSettings {
+        &self.stream
+    }
+}
+
 #[derive(Debug)]
 pub enum Config {
     // RaspberryPi - DS Lite - V4L2 Capture, Serial Control (Pico)
     Shaoooh(CaptureControlPaths),
     // Any - 3DS - NTR Stream, InputRedirection
-    Bishaan(core::net::Ipv4Addr),
+    Bishaan(BishaanOptions),
     // Dummy config for testing
     Ditto, // TODO
            // Any? - DS Lite - V4L2 Capture, Serial Control (Wireless)
@@ -246,8 +314,13 @@ impl Config {
                     cfg.control()
                 )
             }
-            Self::Bishaan(ip) => {
-                format!("Bishaan ビシアーン : IP({})", ip)
+            Self::Bishaan(opts) => {
+                format!(
+                    "Bishaan ビシアーン : IP({}) Quality({}) QoS({})",
+                    opts.ip(),
+                    opts.stream().quality,
+                    opts.stream().qos
+                )
             }
             Self::Ditto => "Ditto メタモン : Metamon".to_string(),
         }
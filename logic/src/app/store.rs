@@ -0,0 +1,263 @@
+use std::{path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Row, params, types::Type};
+
+use super::{Game, HuntInformation, Method, Phase};
+
+pub const HUNT_DATABASE: &str = "hunts/shaoooh.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS hunts (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    species INTEGER NOT NULL,
+    game TEXT NOT NULL,
+    method TEXT NOT NULL,
+    encounters INTEGER NOT NULL,
+    complete INTEGER NOT NULL,
+    date TEXT,
+    archived INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS phases (
+    id INTEGER PRIMARY KEY,
+    hunt_id INTEGER NOT NULL REFERENCES hunts(id) ON DELETE CASCADE,
+    idx INTEGER NOT NULL,
+    species INTEGER NOT NULL,
+    encounters INTEGER NOT NULL,
+    caught INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    UNIQUE(hunt_id, idx)
+);
+CREATE TABLE IF NOT EXISTS encounters (
+    id INTEGER PRIMARY KEY,
+    hunt_id INTEGER NOT NULL REFERENCES hunts(id) ON DELETE CASCADE,
+    phase INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    date TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS encounters_hunt ON encounters(hunt_id, date);
+";
+
+// Embedded database of hunts, phases and individual encounters.
+// The JSON files in hunts/ are still written as a readable export, but the
+// database is the source of truth for encounter counts.
+pub struct HuntStore {
+    conn: Connection,
+}
+
+fn parse_column<T: FromStr>(row: &Row, idx: usize) -> rusqlite::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let s: String = row.get(idx)?;
+    T::from_str(&s)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, e.into()))
+}
+
+impl HuntStore {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        // WAL reduces the amount written per encounter and survives power loss
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    pub fn open_default() -> rusqlite::Result<Self> {
+        Self::open(HUNT_DATABASE)
+    }
+
+    // Insert or update a hunt, replacing its phases. Resuming an archived hunt
+    // unarchives it
+    pub fn save_hunt(&mut self, hunt: &HuntInformation) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        let game: &'static str = (&hunt.game).into();
+        let method: &'static str = (&hunt.method).into();
+        let id: i64 = tx.query_row(
+            "INSERT INTO hunts (name, species, game, method, encounters, complete, date)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT(name) DO UPDATE SET
+               species = excluded.species,
+               game = excluded.game,
+               method = excluded.method,
+               encounters = excluded.encounters,
+               complete = excluded.complete,
+               date = excluded.date,
+               archived = 0
+             RETURNING id",
+            params![
+                hunt.name,
+                hunt.species,
+                game,
+                method,
+                hunt.encounters,
+                hunt.complete,
+                hunt.date
+            ],
+            |r| r.get(0),
+        )?;
+        tx.execute("DELETE FROM phases WHERE hunt_id = ?1", params![id])?;
+        for (idx, phase) in hunt.phases.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO phases (hunt_id, idx, species, encounters, caught, date)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    id,
+                    idx,
+                    phase.species,
+                    phase.encounters,
+                    phase.caught,
+                    phase.date
+                ],
+            )?;
+        }
+        tx.commit()
+    }
+
+    // Record a single encounter, only touches the encounter count of the hunt
+    pub fn record_encounter(
+        &mut self,
+        name: &str,
+        phase: usize,
+        count: u64,
+    ) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        let id: i64 = tx.query_row(
+            "UPDATE hunts SET encounters = ?2 WHERE name = ?1 RETURNING id",
+            params![name, count],
+            |r| r.get(0),
+        )?;
+        tx.execute(
+            "INSERT INTO encounters (hunt_id, phase, count, date) VALUES (?1, ?2, ?3, ?4)",
+            params![id, phase, count, Utc::now()],
+        )?;
+        tx.commit()
+    }
+
+    pub fn rename_hunt(&mut self, name: &str, new_name: &str) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE hunts SET name = ?2 WHERE name = ?1",
+            params![name, new_name],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_archived(&mut self, name: &str, archived: bool) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE hunts SET archived = ?2 WHERE name = ?1",
+            params![name, archived],
+        )?;
+        Ok(())
+    }
+
+    fn phases(&self, id: i64) -> rusqlite::Result<Vec<Phase>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT species, encounters, caught, date FROM phases WHERE hunt_id = ?1 ORDER BY idx",
+        )?;
+        stmt.query_map(params![id], |r| {
+            Ok(Phase {
+                species: r.get(0)?,
+                encounters: r.get(1)?,
+                caught: r.get(2)?,
+                date: r.get(3)?,
+            })
+        })?
+        .collect()
+    }
+
+    fn hunt_from_row(&self, r: &Row) -> rusqlite::Result<HuntInformation> {
+        let id: i64 = r.get(0)?;
+        Ok(HuntInformation {
+            name: r.get(1)?,
+            species: r.get(2)?,
+            game: parse_column::<Game>(r, 3)?,
+            method: parse_column::<Method>(r, 4)?,
+            encounters: r.get(5)?,
+            phases: self.phases(id)?,
+            complete: r.get(6)?,
+            date: r.get(7)?,
+        })
+    }
+
+    pub fn get_hunt(&self, name: &str) -> rusqlite::Result<Option<HuntInformation>> {
+        self.conn
+            .query_row(
+                "SELECT id, name, species, game, method, encounters, complete, date
+                 FROM hunts WHERE name = ?1",
+                params![name],
+                |r| self.hunt_from_row(r),
+            )
+            .optional()
+    }
+
+    pub fn get_all_hunts(&self) -> rusqlite::Result<Vec<HuntInformation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, species, game, method, encounters, complete, date
+             FROM hunts ORDER BY name",
+        )?;
+        stmt.query_map([], |r| self.hunt_from_row(r))?.collect()
+    }
+
+    pub fn get_hunts(&self, archived: bool) -> rusqlite::Result<Vec<HuntInformation>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, species, game, method, encounters, complete, date
+             FROM hunts WHERE archived = ?1 ORDER BY name",
+        )?;
+        stmt.query_map(params![archived], |r| self.hunt_from_row(r))?
+            .collect()
+    }
+
+    // None if the hunt isn't in the database
+    pub fn is_archived(&self, name: &str) -> rusqlite::Result<Option<bool>> {
+        self.conn
+            .query_row(
+                "SELECT archived FROM hunts WHERE name = ?1",
+                params![name],
+                |r| r.get(0),
+            )
+            .optional()
+    }
+
+    // Encounter timestamps for a hunt, for querying rates across hunts
+    pub fn get_encounter_times(&self, name: &str) -> rusqlite::Result<Vec<DateTime<Utc>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT e.date FROM encounters e JOIN hunts h ON e.hunt_id = h.id
+             WHERE h.name = ?1 ORDER BY e.date",
+        )?;
+        stmt.query_map(params![name], |r| r.get(0))?.collect()
+    }
+
+    // One-time import of the existing JSON hunt files, hunts already in the
+    // database are left untouched. Returns the number of hunts imported.
+    pub fn import_json_files(&mut self, files: &[PathBuf]) -> rusqlite::Result<usize> {
+        let mut imported = 0;
+        for f in files {
+            let hunt: HuntInformation = match std::fs::read_to_string(f)
+                .map_err(|e| e.to_string())
+                .and_then(|d| serde_json::from_str(&d).map_err(|e| e.to_string()))
+            {
+                Ok(h) => h,
+                Err(e) => {
+                    log::warn!("Skipping {}: {}", f.display(), e);
+                    continue;
+                }
+            };
+            let exists: bool = self.conn.query_row(
+                "SELECT EXISTS(SELECT 1 FROM hunts WHERE name = ?1)",
+                params![hunt.name],
+                |r| r.get(0),
+            )?;
+            if exists {
+                log::info!("'{}' already in database, skipping", hunt.name);
+            } else {
+                log::info!("Importing '{}' from {}", hunt.name, f.display());
+                self.save_hunt(&hunt)?;
+                imported += 1;
+            }
+        }
+        Ok(imported)
+    }
+}
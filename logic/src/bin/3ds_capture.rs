@@ -1,9 +1,12 @@
 use std::net::Ipv4Addr;
 
 use opencv::prelude::*;
-use shaoooh::vision::{BishaanVision, BishaanVisionSocket};
-use std::sync::Arc;
+use shaoooh::app::Links;
+use shaoooh::vision::{
+    BishaanVision, BishaanVisionSocket, NTRMemoryReader, NTRStreamSettings, StreamStats,
+};
 use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 use tokio::sync::{broadcast, watch};
 
 use simple_logger::SimpleLogger;
@@ -27,9 +30,19 @@ async fn main() {
     let ip = Ipv4Addr::new(192, 168, 68, 4);
 
     tokio::spawn(async move {
-        let vision = BishaanVisionSocket::new(ip, t_frame_tx, b_frame_tx, atomic, error_tx)
-            .await
-            .expect("Error creating vision thread");
+        let vision = BishaanVisionSocket::new(
+            ip,
+            t_frame_tx,
+            b_frame_tx,
+            atomic,
+            error_tx,
+            Links::default(),
+            Arc::new(Mutex::new(StreamStats::default())),
+            NTRMemoryReader::channel().1,
+            watch::channel(NTRStreamSettings::default()).1,
+        )
+        .await
+        .expect("Error creating vision thread");
         let vision_handle = tokio::spawn(vision.task());
     });
 
@@ -2,7 +2,9 @@
 fn get_config() -> shaoooh::app::Config {
     use std::net::Ipv4Addr;
 
-    shaoooh::app::Config::Bishaan(Ipv4Addr::new(10, 42, 0, 146))
+    shaoooh::app::Config::Bishaan(shaoooh::app::BishaanOptions::new(Ipv4Addr::new(
+        10, 42, 0, 146,
+    )))
 }
 
 #[cfg(not(any(all(target_arch = "aarch64", target_os = "linux"))))]
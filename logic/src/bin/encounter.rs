@@ -59,7 +59,24 @@ fn main() {
     match species {
         Some(s) => {
             log::info!("Finding encounters for #{} - {}", s, ctx.species().name(s));
-            ctx.encounters().get_encounters(s, args.game);
+            for e in ctx.encounters().get_encounters(s, args.game) {
+                let condition = if !e.condition.is_empty() {
+                    format!(" ({})", e.condition)
+                } else {
+                    "".to_string()
+                };
+                log::trace!(
+                    "{} can be encountered in {} at {} @ lvl. {}-{} by {} at {}%{}",
+                    e.species,
+                    e.version,
+                    e.location,
+                    e.min_lvl,
+                    e.max_lvl,
+                    e.method,
+                    e.rarity,
+                    condition
+                );
+            }
         }
         None => {
             log::error!("Couldn't find species")
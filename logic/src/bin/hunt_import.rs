@@ -0,0 +1,21 @@
+use simple_logger::SimpleLogger;
+
+use shaoooh::app::{HuntStore, Shaoooh};
+
+fn main() {
+    SimpleLogger::new()
+        .with_level(log::Level::Info.to_level_filter())
+        .with_utc_timestamps()
+        .init()
+        .unwrap();
+
+    log::info!("Starting Shaoooh : Import hunts into database");
+
+    let mut store = HuntStore::open_default().expect("Failed to open hunt database");
+    let files = Shaoooh::get_all_hunt_files();
+    let imported = store
+        .import_json_files(&files)
+        .expect("Failed to import hunts");
+
+    log::info!("Imported {} of {} hunt files", imported, files.len());
+}
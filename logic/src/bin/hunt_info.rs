@@ -1,16 +1,44 @@
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
 use std::time::Duration;
 
 use chrono::Datelike;
+use clap::{Parser, Subcommand};
 use shaoooh::context::PkContext;
 
 use simple_logger::SimpleLogger;
 
 use shaoooh::app::{Game, Method, Shaoooh};
-use shaoooh::hunt::HuntBuild;
+use shaoooh::hunt::{HuntBuild, ValidationIssue, panic_message};
+use strum::IntoEnumIterator;
+
+/// Shaoooh - Information about hunts and their state machines
+#[derive(Parser, Debug)]
+#[command(version, about, long_about=None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Encounters for every shiny caught (default)
+    Stats,
+    /// Check the state machine of every supported game/method/target
+    Validate {
+        #[arg(short, long)]
+        game: Option<Game>,
+        #[arg(short, long)]
+        method: Option<Method>,
+        #[arg(short, long)]
+        target: Option<u32>,
+    },
+}
 
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
     SimpleLogger::new()
         .with_level(log::Level::Debug.to_level_filter())
         .with_utc_timestamps()
@@ -19,6 +47,126 @@ async fn main() {
 
     log::info!("Starting Shaoooh Test : Hunt Information");
 
+    match args.command {
+        Some(Command::Validate {
+            game,
+            method,
+            target,
+        }) => {
+            if !validate(game, method, target) {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Stats) | None => stats(),
+    }
+}
+
+// Turns off logging and panic messages until dropped. Building logs every
+// state added and unsupported hunts as errors, and probing logs as checks
+// match and panics on purpose
+struct Quiet {
+    level: log::LevelFilter,
+    hook: Option<Box<dyn Fn(&panic::PanicHookInfo) + Sync + Send>>,
+}
+
+impl Quiet {
+    fn new() -> Self {
+        let level = log::max_level();
+        log::set_max_level(log::LevelFilter::Off);
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        Self {
+            level,
+            hook: Some(hook),
+        }
+    }
+}
+
+impl Drop for Quiet {
+    fn drop(&mut self) {
+        // Can't change the hook while unwinding
+        if let Some(hook) = self.hook.take()
+            && !thread::panicking()
+        {
+            panic::set_hook(hook);
+        }
+        log::set_max_level(self.level);
+    }
+}
+
+// Validate every matching hunt, machines with the same description are only
+// probed once. Returns false if any have issues
+fn validate(game: Option<Game>, method: Option<Method>, target: Option<u32>) -> bool {
+    let mut combinations = 0;
+    let mut panicked = 0;
+    let mut machines: HashMap<String, (Vec<(Game, Method, u32)>, Vec<ValidationIssue>)> =
+        HashMap::new();
+    let mut order = Vec::new();
+    for g in Game::iter().filter(|g| game.as_ref().is_none_or(|game| game == g)) {
+        for m in Method::iter().filter(|m| method.as_ref().is_none_or(|method| method == m)) {
+            let targets = match target {
+                Some(t) => t..=t,
+                None => 1..=g.last_species(),
+            };
+            for t in targets {
+                let quiet = Quiet::new();
+                let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let builder = HuntBuild::builder(t, g.clone(), m.clone())?;
+                    let key = format!("{:?}/{:?} {}", g, m, builder.describe().join(";"));
+                    let entry = machines.entry(key.clone()).or_insert_with(|| {
+                        let issues = builder.validate();
+                        order.push(key);
+                        (Vec::new(), issues)
+                    });
+                    entry.0.push((g.clone(), m.clone(), t));
+                    Some(())
+                }));
+                drop(quiet);
+
+                match res {
+                    Ok(Some(())) => combinations += 1,
+                    Ok(None) => {}
+                    Err(err) => {
+                        combinations += 1;
+                        panicked += 1;
+                        log::warn!(
+                            "{:?}/{:?} target {} panicked while building: {}",
+                            g,
+                            m,
+                            t,
+                            panic_message(err)
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    let mut failed = panicked;
+    for key in &order {
+        let (hunts, issues) = &machines[key];
+        if issues.is_empty() {
+            continue;
+        }
+        failed += 1;
+        let (g, m, _) = &hunts[0];
+        let targets: Vec<String> = hunts.iter().map(|h| h.2.to_string()).collect();
+        log::warn!("{:?}/{:?} targets {}", g, m, targets.join(","));
+        for issue in issues {
+            log::warn!("    {}", issue);
+        }
+    }
+    log::info!(
+        "Validated {} hunts with {} different state machines, {} with issues",
+        combinations,
+        machines.len() + panicked,
+        failed
+    );
+
+    failed == 0
+}
+
+fn stats() {
     // if let Some(mut fsm) = HuntBuild::build(19, Game::FireRedLeafGreen, Method::RandomEncounter) {
     //     log::info!("Created state machine");
     //     // TODO needs to be a temp file
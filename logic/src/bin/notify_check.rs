@@ -0,0 +1,308 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, Method, StatusCode, Uri},
+};
+use clap::{Parser, Subcommand};
+use shaoooh::notify::{
+    DiscordNotifier, JsonNotifier, Notification, NotificationKind, Notifier, NtfyNotifier,
+    PushoverNotifier, SmtpNotifier,
+};
+use simple_logger::SimpleLogger;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+const ATTACHMENT_NAME: &str = "shaoooh_notify_check.png";
+const MESSAGE: &str = "Stand-in check message";
+
+/// Shaoooh - Stand-in server for testing the notifier backends
+#[derive(Parser, Debug)]
+#[command(version, about, long_about=None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Log everything sent to the stand-in, to point user_config.json at
+    Serve {
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        #[arg(long, default_value_t = 2525)]
+        smtp_port: u16,
+    },
+    /// Send through every backend to the stand-in, with and without the
+    /// attachment, and check what arrives
+    Check,
+}
+
+// A request received by the stand-in, SMTP messages have no headers
+#[derive(Debug)]
+struct Received {
+    from: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Received {
+    fn contains(&self, text: &str) -> bool {
+        String::from_utf8_lossy(&self.body).contains(text)
+            || self.headers.values().any(|v| v == text)
+    }
+}
+
+type Inbox = Arc<Mutex<Vec<Received>>>;
+
+// Accepts anything, except paths under /fail which always get an error
+async fn record(
+    State(inbox): State<Inbox>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let from = format!("{} {}", method, uri.path());
+    log::info!("{} ({} bytes)", from, body.len());
+    for (name, value) in &headers {
+        log::debug!("  {}: {:?}", name, value);
+    }
+    let status = if uri.path().starts_with("/fail") {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
+    };
+    inbox.lock().unwrap().push(Received {
+        from,
+        headers,
+        body,
+    });
+    status
+}
+
+// Just enough SMTP for a client without TLS or authentication
+async fn smtp_session(stream: TcpStream, inbox: Inbox) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    write.write_all(b"220 localhost stand-in\r\n").await?;
+    while let Some(line) = lines.next_line().await? {
+        let command = line.to_ascii_uppercase();
+        if command.starts_with("DATA") {
+            write.write_all(b"354 End data with .\r\n").await?;
+            let mut data = String::new();
+            while let Some(line) = lines.next_line().await? {
+                if line == "." {
+                    break;
+                }
+                data.push_str(&line);
+                data.push('\n');
+            }
+            log::info!("SMTP message ({} bytes)", data.len());
+            log::debug!("{}", data);
+            inbox.lock().unwrap().push(Received {
+                from: "SMTP".to_string(),
+                headers: HeaderMap::new(),
+                body: data.into(),
+            });
+            write.write_all(b"250 OK\r\n").await?;
+        } else if command.starts_with("QUIT") {
+            write.write_all(b"221 Bye\r\n").await?;
+            break;
+        } else {
+            write.write_all(b"250 OK\r\n").await?;
+        }
+    }
+    Ok(())
+}
+
+// Starts the stand-in servers, returning the HTTP and SMTP ports
+async fn start(port: u16, smtp_port: u16, inbox: Inbox) -> std::io::Result<(u16, u16)> {
+    let http = TcpListener::bind(("127.0.0.1", port)).await?;
+    let smtp = TcpListener::bind(("127.0.0.1", smtp_port)).await?;
+    let ports = (http.local_addr()?.port(), smtp.local_addr()?.port());
+
+    let app = Router::new().fallback(record).with_state(inbox.clone());
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(http, app).await {
+            log::error!("Stand-in HTTP server failed: {}", e);
+        }
+    });
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = smtp.accept().await {
+            let inbox = inbox.clone();
+            tokio::spawn(async move {
+                if let Err(e) = smtp_session(stream, inbox).await {
+                    log::warn!("SMTP session failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(ports)
+}
+
+#[derive(Default)]
+struct Checker {
+    passed: u32,
+    failed: u32,
+}
+
+impl Checker {
+    fn expect(&mut self, ok: bool, what: &str) {
+        if ok {
+            self.passed += 1;
+            log::info!("PASS {}", what);
+        } else {
+            self.failed += 1;
+            log::error!("FAIL {}", what);
+        }
+    }
+
+    // Sends the notification and checks exactly one request arrived with the
+    // message, and the attachment if expected
+    async fn send(
+        &mut self,
+        notifier: &dyn Notifier,
+        notification: &Notification,
+        inbox: &Inbox,
+        attached: bool,
+    ) {
+        inbox.lock().unwrap().clear();
+        let what = format!(
+            "{} {} attachment",
+            notifier.name(),
+            if attached { "with" } else { "without" }
+        );
+        let res = notifier.send(notification).await;
+        if let Err(e) = &res {
+            log::error!("{}", e);
+        }
+        self.expect(res.is_ok(), &format!("{} sent", what));
+
+        let received = inbox.lock().unwrap();
+        self.expect(received.len() == 1, &format!("{} received once", what));
+        if let Some(r) = received.first() {
+            log::debug!("Received by {}", r.from);
+            self.expect(r.contains(MESSAGE), &format!("{} has message", what));
+            self.expect(
+                r.contains(ATTACHMENT_NAME) == attached,
+                &format!("{} attachment as expected", what),
+            );
+        }
+    }
+}
+
+async fn check() -> bool {
+    let inbox = Inbox::default();
+    let (port, smtp_port) = match start(0, 0, inbox.clone()).await {
+        Ok(ports) => ports,
+        Err(e) => {
+            log::error!("Couldn't start stand-in: {}", e);
+            return false;
+        }
+    };
+    let base = format!("http://127.0.0.1:{}", port);
+
+    let attachment = std::env::temp_dir().join(ATTACHMENT_NAME);
+    if let Err(e) = std::fs::write(&attachment, b"\x89PNG stand-in") {
+        log::error!("Couldn't write {}: {}", attachment.display(), e);
+        return false;
+    }
+    let message = || {
+        Notification::new(
+            NotificationKind::Status,
+            "Shaoooh check".to_string(),
+            MESSAGE.to_string(),
+        )
+    };
+    let with_attachment = message().with_attachment(attachment.to_string_lossy().to_string());
+    // Should still be sent, without the attachment
+    let missing_attachment = message().with_attachment(
+        std::env::temp_dir()
+            .join("shaoooh_missing.png")
+            .to_string_lossy()
+            .to_string(),
+    );
+
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![
+        Box::new(JsonNotifier::new(format!("{}/json", base))),
+        Box::new(DiscordNotifier::new(format!("{}/discord", base))),
+        Box::new(NtfyNotifier::new(
+            base.clone(),
+            "shaoooh".to_string(),
+            Some("token".to_string()),
+        )),
+        Box::new(PushoverNotifier::new(
+            "key".to_string(),
+            "user".to_string(),
+            Some(format!("{}/pushover", base)),
+        )),
+    ];
+    match SmtpNotifier::new(
+        "127.0.0.1",
+        Some(smtp_port),
+        false,
+        None,
+        "Shaoooh <shaoooh@localhost>",
+        "check@localhost",
+    ) {
+        Ok(n) => notifiers.push(Box::new(n)),
+        Err(e) => log::error!("Couldn't create SMTP notifier: {}", e),
+    }
+
+    let mut checker = Checker::default();
+    checker.expect(notifiers.len() == 5, "All notifiers created");
+    for n in &notifiers {
+        checker
+            .send(n.as_ref(), &with_attachment, &inbox, true)
+            .await;
+        checker
+            .send(n.as_ref(), &missing_attachment, &inbox, false)
+            .await;
+    }
+
+    let failing = JsonNotifier::new(format!("{}/fail", base));
+    checker.expect(
+        failing.send(&message()).await.is_err(),
+        "Error response reported",
+    );
+
+    let _ = std::fs::remove_file(&attachment);
+    log::info!("{} passed, {} failed", checker.passed, checker.failed);
+    checker.failed == 0
+}
+
+fn main() {
+    let args = Args::parse();
+    SimpleLogger::new()
+        .with_level(log::Level::Info.to_level_filter())
+        .with_utc_timestamps()
+        .init()
+        .unwrap();
+
+    log::info!("Starting Shaoooh Test : Notify");
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+    match args.command {
+        Command::Serve { port, smtp_port } => runtime.block_on(async {
+            let (port, smtp_port) = start(port, smtp_port, Inbox::default())
+                .await
+                .expect("Failed to start");
+            log::info!(
+                "Listening on HTTP port {} and SMTP port {}",
+                port,
+                smtp_port
+            );
+            let _ = tokio::signal::ctrl_c().await;
+        }),
+        Command::Check => {
+            if !runtime.block_on(check()) {
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use std::{
+    net::Ipv4Addr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU32},
+    },
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use opencv::{
+    core::{CV_8UC3, Scalar, Vector},
+    prelude::*,
+};
+use shaoooh::{
+    app::{Links, ShaooohError},
+    control::{
+        BishaanControl, BishaanControlSocket, BotControl, Button, Delay, InputSequence,
+        ShaooohControl,
+    },
+    virtual_console::{
+        NTRFrames, NTROptions, SerialOptions, Timeline, VirtualInputRedirection, VirtualNTR,
+        VirtualSerial,
+    },
+    vision::{
+        BishaanVisionSocket, NTRDebugClient, NTRMemoryReader, NTRStreamSettings, StreamStats,
+    },
+};
+use simple_logger::SimpleLogger;
+use tokio::{
+    net::TcpStream,
+    sync::{broadcast, mpsc, watch},
+};
+
+// Allowed difference between the requested and recorded hold times
+const TOLERANCE: Duration = Duration::from_millis(40);
+
+/// Shaoooh - Virtual console for testing the control backends
+#[derive(Parser, Debug)]
+#[command(version, about, long_about=None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Emulate the control board on a pseudo-terminal
+    Serial {
+        /// Protocol version to report
+        #[arg(long, default_value_t = 3)]
+        version: u32,
+        /// Stop replying after this many commands
+        #[arg(long)]
+        reply_limit: Option<u32>,
+    },
+    /// Decode InputRedirection packets
+    InputRedirection {
+        #[arg(long, default_value = "0.0.0.0:4950")]
+        bind: String,
+    },
+    /// Stream images like NTR remote play
+    Ntr {
+        /// Directory with 'top' and 'bottom' subdirectories of JPEG images
+        images: String,
+        #[arg(long, default_value_t = 30)]
+        fps: u32,
+        /// Chance of dropping each fragment
+        #[arg(long, default_value_t = 0.0)]
+        loss: f64,
+        /// Chance of swapping each fragment with the next
+        #[arg(long, default_value_t = 0.0)]
+        reorder: f64,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Check the encoding and timing of every button on both control
+    /// backends, and frame delivery and memory access with NTR
+    Check,
+}
+
+fn all_buttons() -> Vec<Button> {
+    vec![
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+        Button::Start,
+        Button::Select,
+        Button::L,
+        Button::R,
+        Button::Left,
+        Button::Right,
+        Button::Up,
+        Button::Down,
+        Button::Home,
+        Button::ZL,
+        Button::ZR,
+        Button::Circle(0x40, 0xc0),
+        Button::Touch(160, 120),
+        Button::Touch(1, 239),
+    ]
+}
+
+#[derive(Default)]
+struct Checker {
+    passed: u32,
+    failed: u32,
+}
+
+impl Checker {
+    fn expect(&mut self, ok: bool, what: &str) {
+        if ok {
+            self.passed += 1;
+            log::info!("PASS {}", what);
+        } else {
+            self.failed += 1;
+            log::error!("FAIL {}", what);
+        }
+    }
+
+    fn expect_holds(&mut self, timeline: &Timeline, expected: &[(Button, Duration)], what: &str) {
+        let holds = timeline.holds();
+        let ok = holds.len() == expected.len()
+            && holds
+                .iter()
+                .zip(expected)
+                .all(|(h, e)| h.0 == e.0 && h.1 + TOLERANCE >= e.1 && h.1 <= e.1 + TOLERANCE);
+        if !ok {
+            log::error!("Expected {:?}, got {:?}", expected, holds);
+        }
+        self.expect(ok, what);
+    }
+}
+
+// Give the backend time to process replies
+fn settle(control: &mut dyn BotControl, time: Duration) -> bool {
+    let start = std::time::Instant::now();
+    let mut connected = true;
+    while start.elapsed() < time {
+        connected = control.reconnect();
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    connected
+}
+
+fn check_serial(checker: &mut Checker) {
+    let board = VirtualSerial::start(SerialOptions::default()).expect("Failed to start board");
+    let (error_tx, _) = broadcast::channel(8);
+    let mut control = ShaooohControl::new(board.path(), Links::default(), Arc::new(error_tx));
+    let timeline = board.timeline();
+
+    for b in all_buttons() {
+        timeline.clear();
+        control.press_delay(&b, &Delay::Tenth);
+        let supported = !matches!(
+            b,
+            Button::Home | Button::ZL | Button::ZR | Button::Circle(..) | Button::Touch(..)
+        );
+        if supported {
+            timeline.wait_for(2, Duration::from_secs(1));
+            checker.expect_holds(
+                timeline,
+                &[(b.clone(), Duration::from_millis(100))],
+                &format!("serial {:?}", b),
+            );
+        } else {
+            settle(&mut control, Duration::from_millis(200));
+            checker.expect(
+                timeline.events().is_empty(),
+                &format!("serial ignores {:?}", b),
+            );
+        }
+        settle(&mut control, Duration::from_millis(20));
+    }
+
+    timeline.clear();
+    control.hold(&[&Button::A, &Button::B], 275);
+    timeline.wait_for(4, Duration::from_secs(1));
+    checker.expect_holds(
+        timeline,
+        &[
+            (Button::A, Duration::from_millis(275)),
+            (Button::B, Duration::from_millis(275)),
+        ],
+        "serial hold",
+    );
+
+    timeline.clear();
+    control.run(&InputSequence::new().tap(&Button::Up, 120, 80).repeat(3));
+    timeline.wait_for(6, Duration::from_secs(2));
+    checker.expect_holds(
+        timeline,
+        &std::iter::repeat_n((Button::Up, Duration::from_millis(120)), 3).collect::<Vec<_>>(),
+        "serial sequence",
+    );
+    let events = timeline.events();
+    checker.expect(
+        events.len() == 6 && events[5].at + TOLERANCE >= Duration::from_millis(520),
+        "serial sequence timing",
+    );
+    settle(&mut control, Duration::from_millis(200));
+
+    // A board that stops replying is reported as a communication error
+    let board = VirtualSerial::start(SerialOptions {
+        reply_limit: Some(1),
+        ..Default::default()
+    })
+    .expect("Failed to start board");
+    let (error_tx, mut error_rx) = broadcast::channel(8);
+    let mut control = ShaooohControl::new(board.path(), Links::default(), Arc::new(error_tx));
+    control.press(&Button::A);
+    let connected = settle(&mut control, Duration::from_secs(1));
+    checker.expect(
+        !connected && matches!(error_rx.try_recv(), Ok(ShaooohError::CommunicationError)),
+        "serial timeout",
+    );
+}
+
+fn check_input_redirection(checker: &mut Checker) {
+    // Any free port, so a real InputRedirection listener doesn't get in the way
+    let console = VirtualInputRedirection::start("127.0.0.1:0").expect("Failed to start listener");
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+    let (tx, rx) = mpsc::channel(16);
+    let socket = runtime
+        .block_on(BishaanControlSocket::new(
+            Ipv4Addr::LOCALHOST,
+            console.addr().port(),
+            rx,
+            Links::default(),
+        ))
+        .expect("Failed to create socket");
+    runtime.spawn(socket.task());
+    let mut control = BishaanControl::new(tx);
+    let timeline = console.timeline();
+
+    for b in all_buttons() {
+        timeline.clear();
+        control.press_delay(&b, &Delay::Tenth);
+        timeline.wait_for(2, Duration::from_secs(1));
+        checker.expect_holds(
+            timeline,
+            &[(b.clone(), Duration::from_millis(200))],
+            &format!("InputRedirection {:?}", b),
+        );
+        // Wait for the gap after the release
+        std::thread::sleep(Duration::from_millis(250));
+    }
+
+    timeline.clear();
+    control.run(
+        &InputSequence::new()
+            .press(&Button::ZL)
+            .wait(100)
+            .press(&Button::Touch(300, 20))
+            .wait(150)
+            .release(&Button::Touch(300, 20))
+            .release(&Button::ZL),
+    );
+    timeline.wait_for(4, Duration::from_secs(1));
+    checker.expect_holds(
+        timeline,
+        &[
+            (Button::ZL, Duration::from_millis(250)),
+            (Button::Touch(300, 20), Duration::from_millis(150)),
+        ],
+        "InputRedirection overlapping holds",
+    );
+}
+
+// Noise images, so each frame is split into several fragments like a real
+// capture at the default NTR quality
+fn test_frames(count: usize) -> NTRFrames {
+    let frame = |rows, cols| {
+        let mut m = Mat::new_rows_cols_with_default(rows, cols, CV_8UC3, Scalar::all(0.0))
+            .expect("Failed to create frame");
+        opencv::core::randu(
+            &mut m,
+            &Vector::<f64>::from_slice(&[0.0, 0.0, 0.0]),
+            &Vector::<f64>::from_slice(&[255.0, 255.0, 255.0]),
+        )
+        .expect("Failed to fill frame");
+        let mut encoded = Vector::new();
+        opencv::imgcodecs::imencode(
+            ".jpg",
+            &m,
+            &mut encoded,
+            &Vector::from_slice(&[opencv::imgcodecs::IMWRITE_JPEG_QUALITY, 20]),
+        )
+        .expect("Failed to encode frame");
+        encoded.to_vec()
+    };
+    NTRFrames {
+        top: (0..count).map(|_| frame(400, 240)).collect(),
+        bottom: (0..count).map(|_| frame(320, 240)).collect(),
+    }
+}
+
+// Count the frames BishaanVisionSocket delivers over a few seconds of streaming
+fn receive_frames(options: NTROptions, duration: Duration) -> (u64, u64, StreamStats) {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+    let ntr = VirtualNTR::start(options, test_frames(4)).expect("Failed to start NTR");
+    let (tx_top, mut rx_top) = watch::channel(Mat::default());
+    let (tx_bottom, mut rx_bottom) = watch::channel(Mat::default());
+    let (error_tx, _error_rx) = broadcast::channel(8);
+    let stats = Arc::new(Mutex::new(StreamStats::default()));
+
+    let (top, bottom) = runtime.block_on(async {
+        let socket = BishaanVisionSocket::new(
+            Ipv4Addr::LOCALHOST,
+            tx_top,
+            tx_bottom,
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(error_tx),
+            Links::default(),
+            stats.clone(),
+            NTRMemoryReader::channel().1,
+            watch::channel(NTRStreamSettings::default()).1,
+        )
+        .await
+        .expect("Failed to create socket");
+        tokio::spawn(socket.task());
+
+        let (mut top, mut bottom) = (0, 0);
+        let _ = tokio::time::timeout(duration, async {
+            loop {
+                tokio::select! {
+                    _ = rx_top.changed() => top += 1,
+                    _ = rx_bottom.changed() => bottom += 1,
+                }
+            }
+        })
+        .await;
+        (top, bottom)
+    });
+    // Stop the client before the server goes away
+    drop(runtime);
+
+    let stats = stats.lock().unwrap().clone();
+    log::info!("Sent {:?}", ntr.stats());
+    log::info!("Received {} top and {} bottom, {:?}", top, bottom, stats);
+    (top, bottom, stats)
+}
+
+fn check_ntr(checker: &mut Checker) {
+    // 30 FPS for 3 seconds, allowing for scheduling jitter
+    let options = NTROptions {
+        bind: Ipv4Addr::LOCALHOST.into(),
+        ..Default::default()
+    };
+    let (top, bottom, stats) = receive_frames(options.clone(), Duration::from_secs(3));
+    checker.expect(top >= 80 && bottom >= 80, "NTR frame delivery");
+    checker.expect(
+        stats.top.dropped == 0 && stats.top.fps > 25.0,
+        "NTR frame rate",
+    );
+
+    // Reordering within a frame shouldn't lose it
+    let (top, _, stats) = receive_frames(
+        NTROptions {
+            reorder: 0.1,
+            seed: 1,
+            ..options.clone()
+        },
+        Duration::from_secs(3),
+    );
+    checker.expect(
+        top >= 80 && stats.top.reordered > 0,
+        "NTR frame delivery with reordering",
+    );
+
+    let (top, _, stats) = receive_frames(
+        NTROptions {
+            loss: 0.02,
+            seed: 1,
+            ..options
+        },
+        Duration::from_secs(3),
+    );
+    checker.expect(
+        top > 0 && stats.top.dropped > 0,
+        "NTR frame delivery with loss",
+    );
+}
+
+fn check_ntr_debugger(checker: &mut Checker) {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+    let options = NTROptions {
+        bind: Ipv4Addr::LOCALHOST.into(),
+        ..Default::default()
+    };
+    let ntr = VirtualNTR::start(options.clone(), test_frames(4)).expect("Failed to start NTR");
+    ntr.set_memory(0x1000, &[0x78, 0x56, 0x34, 0x12]);
+
+    let (processes, pid, written, read, missing) = runtime.block_on(async {
+        let stream = TcpStream::connect(ntr.addr())
+            .await
+            .expect("Failed to connect");
+        let (client, _task) = NTRDebugClient::new(
+            stream,
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(AtomicU32::new(1)),
+        );
+        let processes = client.list_processes().await.unwrap_or_default();
+        let pid = client.find_process(&options.process).await.unwrap_or(0);
+        let written = client.write_memory(pid, 0x2000, &[1, 2, 3]).await;
+        let read = client.read_memory(pid, 0x1000, 4).await.ok();
+        let missing = client.read_memory(pid, 0x3000, 4).await;
+        (processes, pid, written, read, missing)
+    });
+    checker.expect(
+        processes.len() == 1 && processes[0].name == options.process && pid == processes[0].pid,
+        "NTR process list",
+    );
+    checker.expect(
+        read == Some(vec![0x78, 0x56, 0x34, 0x12]),
+        "NTR memory read",
+    );
+    checker.expect(
+        written.is_ok() && ntr.memory(0x2000, 3) == Some(vec![1, 2, 3]),
+        "NTR memory write",
+    );
+    checker.expect(
+        missing.is_err_and(|e| e.kind() == std::io::ErrorKind::TimedOut),
+        "NTR memory read without a reply",
+    );
+
+    // Reads from outside the runtime go through the vision socket's debug
+    // connection
+    let (mut reader, memory_rx) = NTRMemoryReader::channel();
+    let (tx_top, _rx_top) = watch::channel(Mat::default());
+    let (tx_bottom, _rx_bottom) = watch::channel(Mat::default());
+    let (error_tx, _error_rx) = broadcast::channel(8);
+    let socket = runtime
+        .block_on(BishaanVisionSocket::new(
+            Ipv4Addr::LOCALHOST,
+            tx_top,
+            tx_bottom,
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(error_tx),
+            Links::default(),
+            Arc::new(Mutex::new(StreamStats::default())),
+            memory_rx,
+            watch::channel(NTRStreamSettings::default()).1,
+        ))
+        .expect("Failed to create socket");
+    runtime.spawn(socket.task());
+    let mut result = None;
+    let start = std::time::Instant::now();
+    while result.is_none() && start.elapsed() < Duration::from_secs(3) {
+        result = reader.poll(&options.process, 0x1000, 4);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    checker.expect(
+        result.is_some_and(|r| r.is_ok_and(|d| d == [0x78, 0x56, 0x34, 0x12])),
+        "NTR memory read through the vision socket",
+    );
+    drop(runtime);
+}
+
+fn check_ntr_settings(checker: &mut Checker) {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to create runtime");
+    let options = NTROptions {
+        bind: Ipv4Addr::LOCALHOST.into(),
+        ..Default::default()
+    };
+    let ntr = VirtualNTR::start(options, test_frames(4)).expect("Failed to start NTR");
+    let bottom_only = NTRStreamSettings {
+        top_priority: false,
+        priority_factor: 4,
+        quality: 50,
+        qos: 8,
+    };
+
+    let (settings_tx, settings_rx) = watch::channel(NTRStreamSettings::default());
+    let (tx_top, _rx_top) = watch::channel(Mat::default());
+    let (tx_bottom, _rx_bottom) = watch::channel(Mat::default());
+    let (error_tx, _error_rx) = broadcast::channel(8);
+    let socket = runtime
+        .block_on(BishaanVisionSocket::new(
+            Ipv4Addr::LOCALHOST,
+            tx_top,
+            tx_bottom,
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(error_tx),
+            Links::default(),
+            Arc::new(Mutex::new(StreamStats::default())),
+            NTRMemoryReader::channel().1,
+            settings_rx,
+        ))
+        .expect("Failed to create socket");
+    runtime.spawn(socket.task());
+    checker.expect(
+        ntr.stats().settings == Some(NTRStreamSettings::default()),
+        "NTR init with default settings",
+    );
+
+    settings_tx
+        .send(bottom_only.clone())
+        .expect("Failed to send settings");
+    std::thread::sleep(Duration::from_millis(500));
+    let stats = ntr.stats();
+    checker.expect(
+        stats.inits == 2 && stats.settings == Some(bottom_only),
+        "NTR init resent with new settings",
+    );
+    drop(runtime);
+}
+
+fn main() {
+    let args = Args::parse();
+    SimpleLogger::new()
+        .with_level(log::Level::Info.to_level_filter())
+        .with_utc_timestamps()
+        .init()
+        .unwrap();
+
+    log::info!("Starting Shaoooh Test : Virtual Console");
+
+    match args.command {
+        Command::Serial {
+            version,
+            reply_limit,
+        } => {
+            let board = VirtualSerial::start(SerialOptions {
+                version,
+                reply_limit,
+            })
+            .expect("Failed to start board");
+            log::info!("Use {} as the control port", board.path());
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+        Command::InputRedirection { bind } => {
+            let _console = VirtualInputRedirection::start(&bind).expect("Failed to start");
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+        Command::Ntr {
+            images,
+            fps,
+            loss,
+            reorder,
+            seed,
+        } => {
+            let frames = NTRFrames::from_dir(&images).expect("Failed to load images");
+            let ntr = VirtualNTR::start(
+                NTROptions {
+                    fps,
+                    loss,
+                    reorder,
+                    seed,
+                    ..Default::default()
+                },
+                frames,
+            )
+            .expect("Failed to start");
+            loop {
+                std::thread::sleep(Duration::from_secs(5));
+                log::info!("{:?}", ntr.stats());
+            }
+        }
+        Command::Check => {
+            let mut checker = Checker::default();
+            check_serial(&mut checker);
+            check_input_redirection(&mut checker);
+            check_ntr(&mut checker);
+            check_ntr_debugger(&mut checker);
+            check_ntr_settings(&mut checker);
+            log::info!("{} passed, {} failed", checker.passed, checker.failed);
+            if checker.failed > 0 {
+                std::process::exit(1);
+            }
+        }
+    }
+}
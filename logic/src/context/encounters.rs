@@ -1,7 +1,12 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::OnceLock,
+};
+
+use serde::Serialize;
 
 use crate::{
-    app::Game,
+    app::{Game, Method},
     context::{self, PkContext},
 };
 
@@ -16,29 +21,39 @@ struct EncounterUnparsed {
     max_lvl: u32,
 }
 
-// TODO derive strings only on debug?
-#[derive(Debug)]
-struct EncounterParsed {
-    id: u32,
-    version_id: u32,
-    version: String,
-    location: String,
-    slot: u32,
-    method_id: u32,
-    method: String,
-    rarity: u32,
-    mon: u32,
-    min_lvl: u32,
-    max_lvl: u32,
-    condition: String,
-    condition_id: u32,
+#[derive(Clone, Debug, Serialize)]
+pub struct Encounter {
+    pub id: u32,
+    pub version_id: u32,
+    pub version: String,
+    pub game: Option<Game>,
+    // Location area, as used by /api/locations/<id>/encounters
+    pub location_id: u32,
+    pub location: String,
+    pub slot: u32,
+    pub method_id: u32,
+    pub method: String,
+    // Closest hunt method, if there is one
+    pub suggested_method: Option<Method>,
+    pub rarity: u32,
+    pub mon: u32,
+    pub species: String,
+    pub min_lvl: u32,
+    pub max_lvl: u32,
+    pub condition: String,
+    pub condition_id: u32,
 }
 
-pub struct EncountersProvider {}
+pub struct EncountersProvider {
+    // Every encounter in the supported games, parsed on first use
+    all: OnceLock<Vec<Encounter>>,
+}
 
 impl EncountersProvider {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            all: OnceLock::new(),
+        }
     }
 
     fn dedup(mut es: Vec<EncounterUnparsed>) -> Vec<EncounterUnparsed> {
@@ -54,30 +69,46 @@ impl EncountersProvider {
         es
     }
 
-    fn parse_encounters(es: Vec<EncounterUnparsed>) -> Vec<EncounterParsed> {
+    // From the PokeAPI encounter method identifier
+    fn suggest_method(identifier: &str, location: &str) -> Option<Method> {
+        if location.contains("Safari Zone") {
+            return Some(Method::SafariZone);
+        }
+        match identifier {
+            "gift" | "gift-egg" => Some(Method::SoftResetGift),
+            "only-one" | "pokeflute" | "squirt-bottle" | "wailmer-pail" | "devon-scope" => {
+                Some(Method::SoftResetEncounter)
+            }
+            "npc-trade" => None,
+            _ => Some(Method::RandomEncounter),
+        }
+    }
+
+    fn parse_encounters(es: Vec<EncounterUnparsed>) -> Vec<Encounter> {
         //let es = Self::dedup(es);
         let mut res = vec![];
-        let mut locations = vec![];
-        let mut encounter_slots = vec![];
-        let mut encounter_ids = vec![];
+        let mut locations = HashSet::new();
+        let mut encounter_slots = HashSet::new();
+        let mut encounter_ids = HashSet::new();
         let mut location_map = HashMap::new();
 
         let mut condition_map = HashMap::new();
         let mut condition_names = HashMap::new();
-        let mut conditions = vec![];
+        let mut conditions = HashSet::new();
 
-        let mut encounter_methods = vec![];
+        let mut encounter_methods = HashSet::new();
         // Slot to rarity
         let mut rarity_map = HashMap::new();
         // Slot to method id
         let mut method_map = HashMap::new();
         // Method id to name
         let mut method_names = HashMap::new();
+        let mut method_identifiers = HashMap::new();
 
         for e in &es {
-            locations.push(e.location);
-            encounter_slots.push(e.slot);
-            encounter_ids.push(e.id);
+            locations.insert(e.location);
+            encounter_slots.insert(e.slot);
+            encounter_ids.insert(e.id);
         }
 
         let mut csv_locs = csv::ReaderBuilder::new()
@@ -95,8 +126,8 @@ impl EncountersProvider {
         }
 
         if location_map.len() != locations.len() {
-            let mut needed_areas = vec![];
-            let mut needed_locs = vec![];
+            let mut needed_areas = HashSet::new();
+            let mut needed_locs = HashSet::new();
             let mut area_map = HashMap::new();
             let mut suffix_map = HashMap::new();
             let mut loc_map = HashMap::new();
@@ -104,7 +135,7 @@ impl EncountersProvider {
             // Wasn't in location names, construct from area
             for loc in &locations {
                 if !location_map.contains_key(&loc) {
-                    needed_areas.push(*loc);
+                    needed_areas.insert(*loc);
                 }
             }
 
@@ -121,7 +152,7 @@ impl EncountersProvider {
                     if suffix.len() > 0 {
                         suffix_map.insert(id, suffix.to_string());
                     }
-                    needed_locs.push(loc_id);
+                    needed_locs.insert(loc_id);
                     area_map.insert(id, loc_id);
                 }
             }
@@ -170,7 +201,7 @@ impl EncountersProvider {
 
                 method_map.insert(id, method);
                 rarity_map.insert(id, rarity);
-                encounter_methods.push(method);
+                encounter_methods.insert(method);
             }
         }
 
@@ -184,6 +215,7 @@ impl EncountersProvider {
 
             if encounter_methods.contains(&method) {
                 method_names.insert(method, r[1].to_string());
+                method_identifiers.insert(method, r[1].to_string());
             }
         }
 
@@ -210,7 +242,7 @@ impl EncountersProvider {
             if encounter_ids.contains(&encounter) {
                 let condition: u32 = str::parse(&r[1]).expect("Couldn't parse condition");
                 condition_map.insert(encounter, condition);
-                conditions.push(condition);
+                conditions.insert(condition);
             }
         }
 
@@ -253,18 +285,24 @@ impl EncountersProvider {
                     .clone()
             };
 
-            let new = EncounterParsed {
+            let suggested_method = Self::suggest_method(&method_identifiers[&method_id], &location);
+
+            let new = Encounter {
                 id: e.id,
                 version_id: e.version,
                 version: PkContext::get().versions().get_name(e.version),
+                game: PkContext::get().versions().get_game(e.version),
+                location_id: e.location,
                 location,
                 slot: e.slot,
                 mon: e.mon,
+                species: PkContext::get().species().name(e.mon),
                 min_lvl: e.min_lvl,
                 max_lvl: e.max_lvl,
                 method_id,
                 rarity,
                 method,
+                suggested_method,
                 condition,
                 condition_id,
             };
@@ -274,7 +312,7 @@ impl EncountersProvider {
         res
     }
 
-    fn get_encounter_list(species: u32, versions: Vec<u32>) -> Vec<EncounterUnparsed> {
+    fn get_encounter_list(versions: Vec<u32>) -> Vec<EncounterUnparsed> {
         let mut encounters = vec![];
         let mut csv_encounters = csv::ReaderBuilder::new()
             .has_headers(true)
@@ -289,63 +327,51 @@ impl EncountersProvider {
             let min_lvl: u32 = str::parse(&r[5]).expect("Couldn't parse min level");
             let max_lvl: u32 = str::parse(&r[6]).expect("Couldn't parse max level");
 
-            if mon == species {
-                if versions.contains(&version) {
-                    let e = EncounterUnparsed {
-                        id,
-                        version,
-                        location,
-                        slot,
-                        mon,
-                        min_lvl,
-                        max_lvl,
-                    };
+            let e = EncounterUnparsed {
+                id,
+                version,
+                location,
+                slot,
+                mon,
+                min_lvl,
+                max_lvl,
+            };
 
-                    encounters.push(e);
-                }
+            if versions.contains(&version) {
+                encounters.push(e);
             }
         }
 
         encounters
     }
 
-    pub fn get_encounters(&self, species: u32, game: Option<Game>) {
-        let game_str = match &game {
-            Some(g) => {
-                let s: &'static str = g.into();
-                format!(" in {}", s)
-            }
-            None => "".to_string(),
-        };
-
-        let versions = match &game {
-            None => PkContext::get().versions().all_ids(),
+    fn versions(game: Option<&Game>) -> Vec<u32> {
+        match game {
+            None | Some(Game::None) => PkContext::get().versions().all_ids(),
             Some(game) => PkContext::get().versions().get_ids(game),
-        };
+        }
+    }
 
-        log::trace!("Finding encounters for #{}{}", species, game_str);
-        let unparsed = Self::get_encounter_list(species, versions);
+    // Encounters in the game, or any game, matching the filter
+    fn find(&self, game: Option<&Game>, filter: impl Fn(&Encounter) -> bool) -> Vec<Encounter> {
+        let all = self
+            .all
+            .get_or_init(|| Self::parse_encounters(Self::get_encounter_list(Self::versions(None))));
+        let versions = Self::versions(game);
+        all.iter()
+            .filter(|e| versions.contains(&e.version_id) && filter(e))
+            .cloned()
+            .collect()
+    }
 
-        for e in Self::parse_encounters(unparsed) {
-            //log::trace!("{:?}", e);
-            let name = PkContext::get().species().name(e.mon);
-            let condition = if e.condition.len() > 0 {
-                format!(" ({})", e.condition)
-            } else {
-                "".to_string()
-            };
-            log::trace!(
-                "{} can be encountered in {} at {} @ lvl. {}-{} by {} at {}%{}",
-                name,
-                e.version,
-                e.location,
-                e.min_lvl,
-                e.max_lvl,
-                e.method,
-                e.rarity,
-                condition
-            );
-        }
+    // Everywhere the species can be encountered, in any game if not given
+    pub fn get_encounters(&self, species: u32, game: Option<Game>) -> Vec<Encounter> {
+        self.find(game.as_ref(), |e| e.mon == species)
+    }
+
+    // Everything that can be encountered in a location area
+    pub fn get_location_encounters(&self, location: u32, game: Option<Game>) -> Vec<Encounter> {
+        self.find(game.as_ref(), |e| e.location_id == location)
     }
     // pub fn new() -> Self {
     //     let mut mapping = HashMap::new();
@@ -8,6 +8,7 @@ pub struct VersionsProvider {
     game_mapping: HashMap<Game, u32>,
     group_mapping: HashMap<u32, Vec<u32>>,
     name_mapping: HashMap<u32, String>,
+    game_versions: HashMap<u32, Game>,
 }
 
 impl VersionsProvider {
@@ -30,6 +31,8 @@ impl VersionsProvider {
         let mut game_mapping = HashMap::new();
         let mut group_mapping = HashMap::new();
         let mut name_mapping = HashMap::new();
+        let mut group_games = HashMap::new();
+        let mut game_versions = HashMap::new();
 
         let mut csv_groups = csv::ReaderBuilder::new()
             .has_headers(true)
@@ -41,8 +44,9 @@ impl VersionsProvider {
 
             match game {
                 Some(g) => {
-                    game_mapping.insert(g, id);
+                    game_mapping.insert(g.clone(), id);
                     group_mapping.insert(id, vec![]);
+                    group_games.insert(id, g);
                 }
                 None => {}
             }
@@ -61,6 +65,7 @@ impl VersionsProvider {
                 Some(ids) => {
                     ids.push(id);
                     name_mapping.insert(id, name);
+                    game_versions.insert(id, group_games[&group].clone());
                 }
                 None => {}
             }
@@ -77,6 +82,7 @@ impl VersionsProvider {
             game_mapping,
             group_mapping,
             name_mapping,
+            game_versions,
         }
     }
 
@@ -96,6 +102,10 @@ impl VersionsProvider {
             .to_vec()
     }
 
+    pub fn get_game(&self, id: u32) -> Option<Game> {
+        self.game_versions.get(&id).cloned()
+    }
+
     pub fn get_name(&self, id: u32) -> String {
         self.name_mapping
             .get(&id)
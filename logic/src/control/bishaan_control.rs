@@ -1,85 +1,142 @@
-use crate::control::{BotControl, Button, Delay};
+use crate::{
+    app::{Backoff, Links},
+    control::{BotControl, Button, Delay, InputEvent, InputSequence},
+};
 
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
 
 pub struct BishaanControl {
-    tx: mpsc::Sender<(Vec<Button>, Delay)>,
+    tx: mpsc::Sender<InputSequence>,
 }
 
 pub struct BishaanControlSocket {
+    ip: core::net::Ipv4Addr,
+    port: u16,
     socket: UdpSocket,
-    rx: mpsc::Receiver<(Vec<Button>, Delay)>,
+    rx: mpsc::Receiver<InputSequence>,
+    links: Links,
+    // Buttons currently held down
+    held: Vec<Button>,
 }
 
 impl BotControl for BishaanControl {
     fn press(&mut self, button: &Button) {
-        let vec = vec![button.clone()];
-        self.tx
-            .blocking_send((vec, Delay::Tenth))
-            .expect("Failed to send button");
+        self.press_delay(button, &Delay::Tenth);
     }
 
     fn presses_delay(&mut self, buttons: &[&Button], delay: &Delay) {
-        self.tx
-            .blocking_send((
-                buttons.iter().map(|x| (*x).clone()).collect(),
-                delay.clone(),
-            ))
-            .expect("Failed to send buttons");
+        let ms = Self::get_delay_ms(delay);
+        self.run(&InputSequence::new().hold(buttons, ms).wait(ms));
     }
 
     fn press_delay(&mut self, button: &Button, delay: &Delay) {
-        let vec = vec![button.clone()];
+        self.presses_delay(&[button], delay);
+    }
+
+    fn run(&mut self, sequence: &InputSequence) {
         self.tx
-            .blocking_send((vec, delay.clone()))
-            .expect("Failed to send button");
+            .blocking_send(sequence.clone())
+            .expect("Failed to send inputs");
     }
 }
 
 impl BishaanControl {
-    pub fn new(tx: mpsc::Sender<(Vec<Button>, Delay)>) -> Self {
+    pub fn new(tx: mpsc::Sender<InputSequence>) -> Self {
         log::info!("Creating BishaanControl");
         Self { tx }
     }
+
+    fn get_delay_ms(delay: &Delay) -> u64 {
+        match delay {
+            Delay::Half => 500,
+            Delay::Sec => 1000,
+            // Unreliable if delay is too short
+            Delay::Tenth => 200,
+            Delay::Twentieth => 200,
+        }
+    }
 }
 
 impl BishaanControlSocket {
     const TOUCH_SCREEN_WIDTH: u32 = 320;
     const TOUCH_SCREEN_HEIGHT: u32 = 240;
+    const LINK: &str = "InputRedirection";
+    // Port InputRedirection listens on
+    pub const PORT: u16 = 4950;
 
-    async fn get_socket(ip: core::net::Ipv4Addr) -> std::io::Result<UdpSocket> {
+    async fn get_socket(ip: core::net::Ipv4Addr, port: u16) -> std::io::Result<UdpSocket> {
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
 
-        socket.connect((ip, 4950)).await?;
+        socket.connect((ip, port)).await?;
 
         Ok(socket)
     }
 
     pub async fn new(
         ip: core::net::Ipv4Addr,
-        rx: mpsc::Receiver<(Vec<Button>, Delay)>,
+        port: u16,
+        rx: mpsc::Receiver<InputSequence>,
+        links: Links,
     ) -> std::io::Result<BishaanControlSocket> {
         log::info!("Creating BishaanControlSocket");
 
-        let socket = Self::get_socket(ip).await?;
+        let socket = Self::get_socket(ip, port).await?;
 
-        Ok(BishaanControlSocket { socket, rx })
+        Ok(BishaanControlSocket {
+            ip,
+            port,
+            socket,
+            rx,
+            links,
+            held: Vec::new(),
+        })
+    }
+
+    // Send a packet, rebinding the socket until it can be sent
+    async fn send(&mut self, buf: &[u8]) {
+        let mut backoff = Backoff::default();
+        while let Err(e) = self.socket.send(buf).await {
+            log::error!("InputRedirection send error: {:?}", e);
+            self.links.set_down(Self::LINK);
+            backoff.wait().await;
+            match Self::get_socket(self.ip, self.port).await {
+                Ok(s) => self.socket = s,
+                Err(e) => log::error!("Failed to rebind InputRedirection socket: {:?}", e),
+            }
+        }
+        self.links.set_up(Self::LINK);
+    }
+
+    // InputRedirection sends the whole pad state, so track what is held and
+    // send the state after each change. Waits are measured from the start of
+    // the sequence so time spent sending doesn't accumulate.
+    async fn run(&mut self, sequence: &InputSequence) {
+        let mut deadline = Instant::now();
+        for event in sequence.events() {
+            match event {
+                InputEvent::Press(b) => {
+                    if !self.held.contains(b) {
+                        self.held.push(b.clone());
+                    }
+                    self.send(&Self::get_buf(&self.held)).await;
+                }
+                InputEvent::Release(b) => {
+                    self.held.retain(|h| h != b);
+                    self.send(&Self::get_buf(&self.held)).await;
+                }
+                InputEvent::Wait(ms) => {
+                    deadline += Duration::from_millis(*ms);
+                    tokio::time::sleep_until(deadline).await;
+                }
+            }
+        }
     }
 
     pub async fn task(mut self) -> std::io::Result<()> {
-        while let Some((buttons, delay)) = self.rx.recv().await {
-            self.socket.send(&Self::get_buf(buttons.as_ref())).await?;
-            let duration = tokio::time::Duration::from_millis(match delay {
-                Delay::Half => 500,
-                Delay::Sec => 1000,
-                // Unreliable if delay is too short
-                Delay::Tenth => 200,
-                Delay::Twentieth => 200,
-            });
-            tokio::time::sleep(duration).await;
-            self.socket.send(&Self::get_buf(&[])).await?;
-            tokio::time::sleep(duration).await;
+        while let Some(sequence) = self.rx.recv().await {
+            self.run(&sequence).await;
         }
         log::info!("Bishaan Control socket complete");
         Ok(())
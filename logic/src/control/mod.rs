@@ -1,8 +1,11 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 mod bishaan_control;
 mod nop_control;
 mod serial_control;
+mod serial_protocol;
 
 pub use bishaan_control::{BishaanControl, BishaanControlSocket};
 pub use nop_control::NopControl;
@@ -38,8 +41,122 @@ pub enum Delay {
     Sec,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    Press(Button),
+    Release(Button),
+    // Milliseconds
+    Wait(u64),
+}
+
+// A sequence of inputs with timings (a macro), run by the backend in one go
+// so the timing doesn't depend on how quickly the main loop runs
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputSequence {
+    events: Vec<InputEvent>,
+}
+
+impl InputSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(mut self, button: &Button) -> Self {
+        self.events.push(InputEvent::Press(button.clone()));
+        self
+    }
+
+    pub fn release(mut self, button: &Button) -> Self {
+        self.events.push(InputEvent::Release(button.clone()));
+        self
+    }
+
+    pub fn wait(mut self, ms: u64) -> Self {
+        if let Some(InputEvent::Wait(prev)) = self.events.last_mut() {
+            *prev += ms;
+        } else if ms > 0 {
+            self.events.push(InputEvent::Wait(ms));
+        }
+        self
+    }
+
+    // Press buttons together, hold them for the given time and release them
+    pub fn hold(mut self, buttons: &[&Button], ms: u64) -> Self {
+        for b in buttons {
+            self = self.press(b);
+        }
+        self = self.wait(ms);
+        for b in buttons {
+            self = self.release(b);
+        }
+        self
+    }
+
+    // Hold a button then wait before the next input, e.g. to walk one tile
+    pub fn tap(self, button: &Button, hold_ms: u64, gap_ms: u64) -> Self {
+        self.hold(&[button], hold_ms).wait(gap_ms)
+    }
+
+    pub fn then(mut self, other: &InputSequence) -> Self {
+        for e in &other.events {
+            self = match e {
+                InputEvent::Wait(ms) => self.wait(*ms),
+                _ => {
+                    self.events.push(e.clone());
+                    self
+                }
+            };
+        }
+        self
+    }
+
+    pub fn repeat(self, count: usize) -> Self {
+        let mut seq = InputSequence::new();
+        for _ in 0..count {
+            seq = seq.then(&self);
+        }
+        seq
+    }
+
+    pub fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(
+            self.events
+                .iter()
+                .map(|e| match e {
+                    InputEvent::Wait(ms) => *ms,
+                    _ => 0,
+                })
+                .sum(),
+        )
+    }
+}
+
 pub trait BotControl {
     fn press(&mut self, button: &Button);
     fn presses_delay(&mut self, buttons: &[&Button], delay: &Delay);
     fn press_delay(&mut self, button: &Button, delay: &Delay);
+    // Run a sequence of inputs, with the timing handled by the backend
+    fn run(&mut self, sequence: &InputSequence);
+    fn hold(&mut self, buttons: &[&Button], ms: u64) {
+        self.run(&InputSequence::new().hold(buttons, ms));
+    }
+    // Press a button and leave it held until released
+    fn press_down(&mut self, button: &Button) {
+        self.run(&InputSequence::new().press(button));
+    }
+    fn release(&mut self, button: &Button) {
+        self.run(&InputSequence::new().release(button));
+    }
+    // Try to restore a lost connection, returns true if connected
+    fn reconnect(&mut self) -> bool {
+        true
+    }
 }
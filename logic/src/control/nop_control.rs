@@ -1,4 +1,4 @@
-use crate::control::{BotControl, Button, Delay};
+use crate::control::{BotControl, Button, Delay, InputSequence};
 
 pub struct NopControl {}
 
@@ -14,6 +14,10 @@ impl BotControl for NopControl {
     fn press_delay(&mut self, button: &Button, delay: &Delay) {
         log::info!("Button press: {:?} {:?}", button, delay);
     }
+
+    fn run(&mut self, sequence: &InputSequence) {
+        log::info!("Input sequence: {:?}", sequence.events());
+    }
 }
 
 impl NopControl {
@@ -1,9 +1,22 @@
-use serialport::SerialPort;
+use std::{sync::Arc, time::Duration};
 
-use crate::control::{BotControl, Button, Delay};
+use tokio::sync::broadcast;
+
+use super::serial_protocol::{LinkError, MAX_COMMANDS, SerialLink, WAIT_VERSION};
+use crate::{
+    app::{Backoff, Links, ShaooohError},
+    control::{BotControl, Button, Delay, InputEvent, InputSequence},
+};
 
 pub struct ShaooohControl {
-    port: Box<dyn SerialPort>,
+    path: String,
+    // None while the port is being reopened
+    link: Option<SerialLink>,
+    // Firmware version seen when first connected
+    version: Option<u32>,
+    backoff: Backoff,
+    links: Links,
+    error_tx: Arc<broadcast::Sender<ShaooohError>>,
 }
 
 impl BotControl for ShaooohControl {
@@ -20,9 +33,7 @@ impl BotControl for ShaooohControl {
         for b in buttons {
             control_string += &Self::get_button_str(b, false);
         }
-        self.port
-            .write_all(control_string.as_bytes())
-            .expect("Couldn't write");
+        self.write(&control_string, Self::get_delay_duration(delay));
     }
 
     fn press_delay(&mut self, button: &Button, delay: &Delay) {
@@ -30,19 +41,124 @@ impl BotControl for ShaooohControl {
         let pause = Self::get_delay_str(delay);
         let up = Self::get_button_str(button, false);
         let control_string = format!("{}{}{}", down, pause, up);
-        self.port
-            .write_all(control_string.as_bytes())
-            .expect("Couldn't write");
+        self.write(&control_string, Self::get_delay_duration(delay));
+    }
+
+    // The board limits the length of a line, so long sequences are sent in
+    // parts that run one after another
+    fn run(&mut self, sequence: &InputSequence) {
+        let mut control_string = String::new();
+        let mut duration = Duration::ZERO;
+        for e in sequence.events() {
+            let (command, wait) = match e {
+                InputEvent::Press(b) => (Self::get_button_str(b, true), 0),
+                InputEvent::Release(b) => (Self::get_button_str(b, false), 0),
+                InputEvent::Wait(ms) => (self.get_wait_str(*ms), *ms),
+            };
+            if !control_string.is_empty() && control_string.len() + command.len() > MAX_COMMANDS {
+                self.write(&control_string, duration);
+                control_string.clear();
+                duration = Duration::ZERO;
+            }
+            control_string += &command;
+            duration += Duration::from_millis(wait);
+        }
+        if !control_string.is_empty() {
+            self.write(&control_string, duration);
+        }
+    }
+
+    // Also checks acknowledgements and heartbeats while connected
+    fn reconnect(&mut self) -> bool {
+        if let Some(link) = &mut self.link {
+            return match link.poll() {
+                Ok(_) => true,
+                Err(LinkError::Rejected(e)) => {
+                    log::error!("Control board {}", e);
+                    true
+                }
+                Err(e) => {
+                    self.lost(e);
+                    false
+                }
+            };
+        }
+        if !self.backoff.due() {
+            return false;
+        }
+        log::info!("Reopening serial port {}", self.path);
+        // A board that used to reply but now doesn't isn't working
+        let opened = SerialLink::open(&self.path).and_then(|link| {
+            if self.version.is_some() && link.version().is_none() {
+                Err(LinkError::Timeout(0))
+            } else {
+                Ok(link)
+            }
+        });
+        match opened {
+            Ok(link) => {
+                self.link = Some(link);
+                self.backoff.reset();
+                self.links.set_up(Self::LINK);
+                true
+            }
+            Err(e) => {
+                let delay = self.backoff.next_delay();
+                log::error!(
+                    "Failed to reopen serial port: {}, retrying in {}s",
+                    e,
+                    delay.as_secs()
+                );
+                false
+            }
+        }
     }
 }
 
 impl ShaooohControl {
-    pub fn new(path: &str) -> ShaooohControl {
+    const LINK: &str = "Serial";
+
+    pub fn new(
+        path: &str,
+        links: Links,
+        error_tx: Arc<broadcast::Sender<ShaooohError>>,
+    ) -> ShaooohControl {
         log::info!("Connecting to serial port");
+        let link = match SerialLink::open(path) {
+            Ok(l) => l,
+            Err(e) => panic!("Unable to open: {}", e),
+        };
+        if link.version().is_none() {
+            log::warn!("Control board firmware doesn't acknowledge commands, consider updating it");
+        }
         ShaooohControl {
-            port: serialport::new(path, 115200)
-                .open()
-                .expect("Unable to open"),
+            path: path.to_string(),
+            version: link.version(),
+            link: Some(link),
+            backoff: Backoff::default(),
+            links,
+            error_tx,
+        }
+    }
+
+    fn lost(&mut self, e: LinkError) {
+        log::error!("Lost control board: {}", e);
+        self.link = None;
+        self.links.set_down(Self::LINK);
+        let _ = self.error_tx.send(ShaooohError::CommunicationError);
+    }
+
+    // Presses while the port is down are dropped, the hunt is held until it
+    // has been reopened
+    fn write(&mut self, control_string: &str, duration: Duration) {
+        let Some(link) = &mut self.link else {
+            log::warn!("Serial port closed, dropping '{}'", control_string);
+            return;
+        };
+        match link.buttons(control_string, duration) {
+            Ok(_) => {}
+            Err(LinkError::Rejected(e)) => log::error!("Control board {}", e),
+            Err(e) => self.lost(e),
         }
     }
 
@@ -73,6 +189,41 @@ impl ShaooohControl {
         }
     }
 
+    // How long the board pauses for each delay
+    fn get_delay_duration(delay: &Delay) -> Duration {
+        Duration::from_millis(match delay {
+            Delay::Half => 500,
+            Delay::Sec => 1000,
+            Delay::Tenth => 100,
+            Delay::Twentieth => 50,
+        })
+    }
+
+    // Firmware that supports arbitrary waits takes the time in milliseconds,
+    // otherwise the wait is made up from the fixed pauses to the nearest 50ms
+    fn get_wait_str(&self, ms: u64) -> String {
+        if self
+            .link
+            .as_ref()
+            .and_then(|l| l.version())
+            .is_some_and(|v| v >= WAIT_VERSION)
+        {
+            return format!("qw{};", ms);
+        }
+        let mut remaining = (ms + 25) / 50 * 50;
+        if remaining != ms {
+            log::debug!("Rounding wait of {}ms to {}ms", ms, remaining);
+        }
+        let mut wait_string = String::new();
+        for (pchar, pms) in [('M', 1000), ('P', 500), ('p', 100), ('m', 50)] {
+            while remaining >= pms {
+                wait_string += &format!("q{}", pchar);
+                remaining -= pms;
+            }
+        }
+        wait_string
+    }
+
     fn get_delay_str(delay: &Delay) -> String {
         let pchar = match delay {
             Delay::Half => 'P',
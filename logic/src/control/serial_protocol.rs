@@ -0,0 +1,232 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use serialport::SerialPort;
+
+// Version of the protocol in control/main.py
+pub(crate) const PROTOCOL_VERSION: u32 = 3;
+// First version with waits in milliseconds
+pub(crate) const WAIT_VERSION: u32 = 3;
+// Time allowed for a reply on top of how long the command takes to run
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+// Longest line the board accepts, MAX_LINE in control/main.py
+const MAX_LINE: usize = 256;
+// Room left for button commands after the sequence number and command
+pub(crate) const MAX_COMMANDS: usize = MAX_LINE - "65535 C ".len();
+
+pub(crate) enum LinkError {
+    Timeout(u16),
+    Io(std::io::Error),
+    // The board couldn't parse a command, the connection is still working
+    Rejected(String),
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timeout(seq) => write!(f, "No reply to command {}", seq),
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Rejected(reason) => write!(f, "Command rejected: {}", reason),
+        }
+    }
+}
+
+impl From<std::io::Error> for LinkError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serialport::Error> for LinkError {
+    fn from(e: serialport::Error) -> Self {
+        Self::Io(e.into())
+    }
+}
+
+// Connection to the control board. Commands are numbered and acknowledged
+// once they have run, replies are checked without blocking so button presses
+// still run alongside frame processing.
+pub(crate) struct SerialLink {
+    port: Box<dyn SerialPort>,
+    buf: Vec<u8>,
+    seq: u16,
+    // Sequence number and deadline of commands waiting for a reply
+    pending: VecDeque<(u16, Instant)>,
+    // None for the original firmware, which doesn't reply to anything
+    version: Option<u32>,
+    last_reply: Instant,
+    malformed: u32,
+    dropped: u32,
+}
+
+impl SerialLink {
+    pub(crate) fn open(path: &str) -> Result<Self, LinkError> {
+        let port = serialport::new(path, 115200)
+            .timeout(Duration::from_millis(10))
+            .open()?;
+        let mut link = Self {
+            port,
+            buf: Vec::new(),
+            seq: 0,
+            pending: VecDeque::new(),
+            version: None,
+            last_reply: Instant::now(),
+            malformed: 0,
+            dropped: 0,
+        };
+        link.port.clear(serialport::ClearBuffer::All)?;
+
+        link.send("V", Duration::ZERO)?;
+        while link.version.is_none() && !link.pending.is_empty() {
+            match link.poll() {
+                Ok(_) => std::thread::sleep(Duration::from_millis(1)),
+                Err(LinkError::Timeout(_)) => {
+                    link.pending.clear();
+                    log::warn!("No reply to version query, assuming original firmware");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        match link.version {
+            Some(v) if v != PROTOCOL_VERSION => log::warn!(
+                "Control board protocol version {}, expected {}",
+                v,
+                PROTOCOL_VERSION
+            ),
+            Some(v) => log::info!("Control board protocol version {}", v),
+            None => {}
+        }
+        Ok(link)
+    }
+
+    pub(crate) fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    fn send(&mut self, command: &str, duration: Duration) -> Result<(), LinkError> {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        self.port
+            .write_all(format!("{} {}\n", seq, command).as_bytes())?;
+        // Commands run one after another, so the reply comes after any
+        // commands already queued
+        let start = self
+            .pending
+            .back()
+            .map(|p| p.1 - REPLY_TIMEOUT)
+            .filter(|t| *t > Instant::now())
+            .unwrap_or(Instant::now());
+        self.pending
+            .push_back((seq, start + duration + REPLY_TIMEOUT));
+        Ok(())
+    }
+
+    // Send a string of button commands that takes the given time to run
+    pub(crate) fn buttons(
+        &mut self,
+        control_string: &str,
+        duration: Duration,
+    ) -> Result<(), LinkError> {
+        if self.version.is_none() {
+            self.port.write_all(control_string.as_bytes())?;
+            Ok(())
+        } else {
+            self.send(&format!("C {}", control_string), duration)
+        }
+    }
+
+    fn handle_line(&mut self, line: &str) -> Result<(), LinkError> {
+        let mut parts = line.split_whitespace();
+        let Some(seq) = parts.next().and_then(|s| s.parse::<u16>().ok()) else {
+            if line.starts_with("- E") {
+                // The board couldn't read the sequence number, replies come
+                // in order so it was the oldest command still waiting
+                if let Some((seq, _)) = self.pending.pop_front() {
+                    self.last_reply = Instant::now();
+                    return Err(LinkError::Rejected(format!(
+                        "{} (command {})",
+                        line.trim_start_matches("- E").trim(),
+                        seq
+                    )));
+                }
+                log::error!("Control board error: {}", line);
+            } else if !line.is_empty() {
+                log::info!("[Control board] {}", line);
+            }
+            return Ok(());
+        };
+        let Some(pos) = self.pending.iter().position(|p| p.0 == seq) else {
+            log::warn!("Reply to unknown command: {}", line);
+            return Ok(());
+        };
+        // Replies come in order, so any earlier commands lost their reply
+        for (s, _) in self.pending.drain(..pos) {
+            log::warn!("No reply to command {}", s);
+        }
+        self.pending.pop_front();
+        self.last_reply = Instant::now();
+
+        match parts.next() {
+            Some("K") => Ok(()),
+            Some("V") => {
+                self.version = parts.next().and_then(|v| v.parse().ok());
+                Ok(())
+            }
+            Some("P") => {
+                let counts: Vec<u32> = parts.filter_map(|c| c.parse().ok()).collect();
+                if let [_, malformed, dropped] = counts[..] {
+                    if malformed > self.malformed || dropped > self.dropped {
+                        log::warn!(
+                            "Control board reports {} malformed and {} dropped commands",
+                            malformed,
+                            dropped
+                        );
+                    }
+                    self.malformed = malformed;
+                    self.dropped = dropped;
+                }
+                Ok(())
+            }
+            Some("E") => Err(LinkError::Rejected(parts.collect::<Vec<_>>().join(" "))),
+            _ => {
+                log::warn!("Unexpected reply: {}", line);
+                Ok(())
+            }
+        }
+    }
+
+    // Handle any replies that have arrived and check nothing has timed out,
+    // sending a heartbeat if the board has been quiet
+    pub(crate) fn poll(&mut self) -> Result<(), LinkError> {
+        if self.port.bytes_to_read()? > 0 {
+            let mut chunk = [0u8; 256];
+            match self.port.read(&mut chunk) {
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            self.handle_line(String::from_utf8_lossy(&line).trim())?;
+        }
+
+        if let Some((seq, deadline)) = self.pending.front()
+            && Instant::now() > *deadline
+        {
+            return Err(LinkError::Timeout(*seq));
+        }
+
+        if self.version.is_some()
+            && self.pending.is_empty()
+            && self.last_reply.elapsed() > HEARTBEAT_INTERVAL
+        {
+            self.send("P", Duration::ZERO)?;
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,82 @@
 use std::time::Duration;
 
 use serialport::SerialPort;
+use tokio::sync::mpsc;
 
-use crate::app::states::AppState;
+use crate::app::states::{AppState, HuntState, RequestTransition, Transition};
 
 pub struct GfxDisplay {
     last_target: u32,
     last_encounters: u64,
     serial_disp: Option<Box<dyn SerialPort>>,
+    // Button presses sent back by the display
+    tx: mpsc::Sender<RequestTransition>,
+    line: String,
+}
+
+impl GfxDisplay {
+    pub fn new(tx: mpsc::Sender<RequestTransition>) -> Self {
+        let mut serial_disp = serialport::new("/dev/ttyACM0", 115200).open().ok();
+        if let Some(serial) = &mut serial_disp {
+            serial
+                .clear(serialport::ClearBuffer::All)
+                .expect("Failed to clear buffers");
+            serial
+                .set_timeout(Duration::from_millis(100))
+                .expect("Failed to set timeout");
+        };
+        let last_target = 0;
+        let last_encounters = 0;
+        Self {
+            last_target,
+            last_encounters,
+            serial_disp,
+            tx,
+            line: String::new(),
+        }
+    }
+
+    // Lines sent by the display without blocking, "P" is the pause button
+    fn read_buttons(&mut self, state: &AppState) {
+        let Some(serial) = &mut self.serial_disp else {
+            return;
+        };
+        let available = serial.bytes_to_read().unwrap_or(0) as usize;
+        if available == 0 {
+            return;
+        }
+        let mut buf = vec![0; available];
+        let Ok(read) = serial.read(&mut buf) else {
+            return;
+        };
+        for c in String::from_utf8_lossy(&buf[..read]).chars() {
+            if c != '\n' {
+                self.line.push(c);
+                continue;
+            }
+            if self.line.trim() == "P" {
+                let transition = match state.state {
+                    HuntState::Hunt => Some(Transition::PauseHunt),
+                    HuntState::Paused => Some(Transition::ResumeHunt),
+                    _ => None,
+                };
+                if let Some(transition) = transition {
+                    log::info!("Pause button pressed, requesting {:?}", transition);
+                    let _ = self.tx.try_send(RequestTransition {
+                        transition,
+                        arg: None,
+                    });
+                }
+            }
+            self.line.clear();
+        }
+    }
 }
 
 impl super::StateReceiver for GfxDisplay {
     fn display(&mut self, state: AppState) {
+        self.read_buttons(&state);
+
         if state.encounters != self.last_encounters {
             if let Some(tx) = &mut self.serial_disp {
                 let phased = state.encounters;
@@ -43,25 +108,9 @@ impl super::StateReceiver for GfxDisplay {
                 .expect("Failed to clear buffers");
         }
     }
-}
 
-impl Default for GfxDisplay {
-    fn default() -> Self {
-        let mut serial_disp = serialport::new("/dev/ttyACM0", 115200).open().ok();
-        if let Some(serial) = &mut serial_disp {
-            serial
-                .clear(serialport::ClearBuffer::All)
-                .expect("Failed to clear buffers");
-            serial
-                .set_timeout(Duration::from_millis(100))
-                .expect("Failed to set timeout");
-        };
-        let last_target = 0;
-        let last_encounters = 0;
-        Self {
-            last_target,
-            last_encounters,
-            serial_disp,
-        }
+    // Keep polling for button presses when nothing has changed
+    fn always_run(&self) -> bool {
+        true
     }
 }
@@ -18,8 +18,10 @@ impl super::StateReceiver for LightsDisplay {
     fn display(&mut self, state: AppState) {
         let mut data = Vec::new();
 
-        let interesting_state =
-            (state.state != HuntState::Idle) && (state.state != HuntState::Hunt);
+        let interesting_state = matches!(
+            state.state,
+            HuntState::FoundNonTarget | HuntState::FoundTarget
+        );
 
         let num: u64 = Self::NUM_PIXELS.into();
         let num_circle = num - 1;
@@ -1,99 +1,167 @@
+use std::time::Duration;
+
 use serde::Deserialize;
 use tokio::sync::{broadcast, watch};
 
 use crate::{
     app::{AppState, HuntState, ShaooohError},
     context::PkContext,
+    notify::{
+        Notification, NotificationKind, NotificationRules, Notifier, NotifierConfig, NotifierEntry,
+        NotifyEvent, RuleEngine,
+    },
 };
 
+// How often to re-check rules that depend on time, such as the encounter rate
+const RULE_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Deserialize)]
 struct UserConfig {
+    // Pushover settings, kept at the top level for existing configurations
     api_key: Option<String>,
     user_id: Option<String>,
+    #[serde(default)]
+    notifiers: Vec<NotifierEntry>,
+    #[serde(default)]
+    rules: NotificationRules,
+}
+
+impl UserConfig {
+    fn notifiers(&mut self) -> Vec<Box<dyn Notifier>> {
+        let mut configs: Vec<NotifierConfig> = std::mem::take(&mut self.notifiers)
+            .into_iter()
+            .filter(|n| n.enabled)
+            .map(|n| n.config)
+            .collect();
+        if let (Some(api_key), Some(user_id)) = (self.api_key.take(), self.user_id.take()) {
+            configs.push(NotifierConfig::Pushover {
+                api_key,
+                user_id,
+                url: None,
+            });
+        }
+        configs
+            .iter()
+            .filter_map(|c| match c.build() {
+                Ok(n) => {
+                    log::info!("Adding {} notifier", n.name());
+                    Some(n)
+                }
+                Err(e) => {
+                    log::error!("Failed to create notifier {:?}: {}", c, e);
+                    None
+                }
+            })
+            .collect()
+    }
 }
 
 pub struct Webhook {}
 
 impl Webhook {
-    pub async fn error(err: ShaooohError, name: &String, api_key: String, user_id: String) {
-        let title = format!("{} Error", name);
-        let content = reqwest::multipart::Form::new()
-            .text("message", format!("ERROR = {}", err))
-            .text("token", api_key.clone())
-            .text("user", user_id.clone())
-            .text("title", title)
-            .text("priority", "1");
-        log::info!("Calling webhook with {:?}", content);
-        let client = reqwest::Client::new();
-        match client
-            .post("https://api.pushover.net/1/messages.json")
-            .multipart(content)
-            .send()
-            .await
-        {
-            Ok(_) => {}
-            Err(e) => {
-                log::error!("Failed to send webhook {:?}", e);
+    async fn send(notifiers: &[Box<dyn Notifier>], notification: Notification) {
+        log::info!("Sending notification {:?}", notification);
+        for n in notifiers {
+            if let Err(e) = n.send(&notification).await {
+                log::error!("Failed to send {} notification: {}", n.name(), e);
             }
-        };
+        }
+    }
+
+    pub async fn error(
+        err: ShaooohError,
+        name: &String,
+        notifiers: &[Box<dyn Notifier>],
+        rules: &NotificationRules,
+    ) {
+        let priority = rules.priority(NotifyEvent::Error);
+        if rules.allowed(priority) {
+            let title = format!("{} Error", name);
+            let notification =
+                Notification::new(NotificationKind::Error, title, format!("ERROR = {}", err))
+                    .with_priority(priority);
+            Self::send(notifiers, notification).await;
+        }
     }
 
     pub async fn status(
         state_copy: Option<AppState>,
         name: &String,
-        api_key: String,
-        user_id: String,
+        notifiers: &[Box<dyn Notifier>],
+        engine: &mut RuleEngine,
     ) {
-        if let Some(state) = state_copy
-            && let Some(arg) = state.arg
-        {
-            let interesting_state =
-                (state.state != HuntState::Idle) && (state.state != HuntState::Hunt);
-            // TODO last found result? rather than only phase
-            let species = if state.state == HuntState::FoundNonTarget {
-                state.last_phase
-            } else {
-                arg.species
-            };
-            let path_png = PkContext::get().sprite_path(&arg.game, species, interesting_state);
-            let path = if std::fs::exists(&path_png).unwrap() {
-                path_png
-            } else {
-                panic!("Couldn't get reference image {}", path_png)
-            };
-            let phased = state.encounters;
-            let interesting_encounter = (phased % 64 == 0) && (phased != 0);
-            let title = if interesting_state {
-                format!("{} Alert", name)
-            } else {
-                format!("{} Status", name)
+        if let Some(state) = state_copy {
+            let events = engine.events(&state);
+            let Some(arg) = &state.arg else {
+                return;
             };
-            let content = reqwest::multipart::Form::new()
-                .text(
-                    "message",
-                    format!("State = {:?}, No. encounters = {}", &state.state, phased),
-                )
-                .text("token", api_key.clone())
-                .text("user", user_id.clone())
-                .text("title", title)
-                .text("attachment_type", "image/png")
-                .file("attachment", path)
-                .await
-                .unwrap();
-            if interesting_encounter || interesting_state {
-                log::info!("Calling webhook with {:?}", content);
-                let client = reqwest::Client::new();
-                match client
-                    .post("https://api.pushover.net/1/messages.json")
-                    .multipart(content)
-                    .send()
-                    .await
-                {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::error!("Failed to send webhook {:?}", e);
-                    }
+            for event in events {
+                let priority = engine.rules().priority(event);
+                if !engine.rules().allowed(priority) {
+                    log::info!("Not sending {:?} notification in quiet hours", event);
+                    continue;
+                }
+                let interesting_state = matches!(
+                    state.state,
+                    HuntState::FoundNonTarget | HuntState::FoundTarget
+                );
+                // TODO last found result? rather than only phase
+                let (species, shiny) = match event {
+                    NotifyEvent::Phase => match state.phases.last() {
+                        Some(p) if p.species != 0 => (p.species, true),
+                        _ => (arg.species, true),
+                    },
+                    _ if state.state == HuntState::FoundNonTarget => (state.last_phase, true),
+                    _ => (arg.species, interesting_state),
+                };
+                // Sent without the sprite if it's missing
+                let path = PkContext::get().sprite_path(&arg.game, species, shiny);
+                let encounters = state.encounters;
+                let (kind, title, message) = match event {
+                    NotifyEvent::Found => (
+                        NotificationKind::Alert,
+                        format!("{} Alert", name),
+                        format!(
+                            "State = {:?}, No. encounters = {}",
+                            &state.state, encounters
+                        ),
+                    ),
+                    NotifyEvent::Phase => (
+                        NotificationKind::Alert,
+                        format!("{} Phase", name),
+                        format!(
+                            "Phased after {} encounters, {} phases so far",
+                            state.phases.last().map(|p| p.encounters).unwrap_or(0),
+                            state.phases.len()
+                        ),
+                    ),
+                    NotifyEvent::FalseDetect => (
+                        NotificationKind::Alert,
+                        format!("{} False Detections", name),
+                        "Repeated false detections, check the detection".to_string(),
+                    ),
+                    NotifyEvent::RateDrop => (
+                        NotificationKind::Alert,
+                        format!("{} Slow", name),
+                        format!(
+                            "Encounter rate dropped to {:.0}/hr, No. encounters = {}",
+                            engine.current_rate().unwrap_or(0.0),
+                            encounters
+                        ),
+                    ),
+                    _ => (
+                        NotificationKind::Status,
+                        format!("{} Status", name),
+                        format!(
+                            "State = {:?}, No. encounters = {}",
+                            &state.state, encounters
+                        ),
+                    ),
                 };
+                let notification = Notification::new(kind, title, message)
+                    .with_priority(priority)
+                    .with_attachment(path);
+                Self::send(notifiers, notification).await;
             }
         }
     }
@@ -106,19 +174,26 @@ impl Webhook {
         let path = "user_config.json";
         if std::fs::exists(path).unwrap_or(false) {
             let data = std::fs::read_to_string(path).expect("Couldn't read file");
-            if let Ok(cfg) = serde_json::from_str::<UserConfig>(&data) {
-                log::info!("Loaded user configuration");
+            match serde_json::from_str::<UserConfig>(&data) {
+                Ok(mut cfg) => {
+                    log::info!("Loaded user configuration");
 
-                if let (Some(api_key), Some(user_id)) = (cfg.api_key, cfg.user_id) {
+                    let notifiers = cfg.notifiers();
+                    if notifiers.is_empty() {
+                        return;
+                    }
+                    let mut engine = RuleEngine::new(cfg.rules.clone());
+                    let mut interval = tokio::time::interval(RULE_INTERVAL);
                     loop {
                         let end = tokio::select! {
                             err = error_rx.recv() => {
                                 match err {
                                     Ok(err) => {
-                                       Self::error(err, &name, api_key.clone(), user_id.clone()).await;
-                                       // Getting an error will shut everything down, so exit the
-                                       // loop to prevent sending multiple notifications
-                                       true
+                                       let fatal = err.is_fatal();
+                                       Self::error(err, &name, &notifiers, &cfg.rules).await;
+                                       // Getting a fatal error will shut everything down, so exit
+                                       // the loop to prevent sending multiple notifications
+                                       fatal
                                     }
                                     Err(_) => true
                                 }
@@ -127,18 +202,24 @@ impl Webhook {
                                 match rx_val {
                                     Ok(_) => {
                                         let state_copy = { Some((*rx.borrow_and_update()).clone()) };
-                                        Self::status(state_copy, &name, api_key.clone(), user_id.clone()).await;
+                                        Self::status(state_copy, &name, &notifiers, &mut engine).await;
                                         false
                                     },
                                     Err(_) => true
                                 }
                             }
+                            _ = interval.tick() => {
+                                let state_copy = { Some((*rx.borrow()).clone()) };
+                                Self::status(state_copy, &name, &notifiers, &mut engine).await;
+                                false
+                            }
                         };
                         if end {
                             break;
                         }
                     }
                 }
+                Err(e) => log::error!("Failed to parse user configuration: {}", e),
             }
         }
     }
@@ -1,50 +1,114 @@
 use opencv::{
-    core::{Mat, Rect, VecN},
-    imgcodecs::IMREAD_COLOR,
-    imgproc::LINE_8,
+    core::{CV_8UC3, Mat, Point, Rect, VecN},
+    imgproc::{FONT_HERSHEY_SIMPLEX, LINE_8, LINE_AA},
+    prelude::*,
 };
 
+use super::layout::{self, Layout, NodeBox};
+
 pub struct Graph {
     base: Mat,
-    x: Vec<i32>,
-    y: Vec<i32>,
-    widths: Vec<i32>,
-    heights: Vec<i32>,
+    layout: Layout,
 }
 
 impl Graph {
-    pub fn new(fname: &str, x: Vec<i32>, y: Vec<i32>, w: Vec<i32>, h: Vec<i32>) -> Self {
-        let mat = opencv::imgcodecs::imread(fname, IMREAD_COLOR).expect("Failed to read graph");
-        Self {
-            base: mat,
-            x,
-            y,
-            widths: w,
-            heights: h,
+    fn colour((r, g, b): (u8, u8, u8)) -> VecN<f64, 4> {
+        VecN([b.into(), g.into(), r.into(), 0.0])
+    }
+
+    fn rect(node: &NodeBox) -> Rect {
+        Rect {
+            x: node.x,
+            y: node.y,
+            width: node.w,
+            height: node.h,
+        }
+    }
+
+    pub fn new(layout: Layout) -> Self {
+        let mut mat = Mat::new_rows_cols_with_default(
+            layout.height,
+            layout.width,
+            CV_8UC3,
+            Self::colour(layout::BACKGROUND),
+        )
+        .expect("Failed to create graph");
+
+        let border = Self::colour(layout::BORDER);
+        for edge in &layout.edges {
+            for pair in edge.points.windows(2) {
+                let from = Point::new(pair[0].0, pair[0].1);
+                let to = Point::new(pair[1].0, pair[1].1);
+                if pair[1] == *edge.points.last().unwrap() {
+                    opencv::imgproc::arrowed_line(&mut mat, from, to, border, 1, LINE_AA, 0, 0.2)
+                        .expect("Failed to draw edge");
+                } else {
+                    opencv::imgproc::line(&mut mat, from, to, border, 1, LINE_AA, 0)
+                        .expect("Failed to draw edge");
+                }
+            }
         }
+
+        let text = Self::colour(layout::BACKGROUND);
+        for node in layout.nodes.values() {
+            let rect = Self::rect(&node.rect);
+            opencv::imgproc::rectangle(&mut mat, rect, Self::colour(layout::FILL), -1, LINE_8, 0)
+                .expect("Failed to draw state");
+            opencv::imgproc::rectangle(&mut mat, rect, border, 1, LINE_8, 0)
+                .expect("Failed to draw state");
+
+            // Shrink the label if the Hershey font is wider than the box
+            let base_scale = f64::from(layout::FONT_SIZE) / 30.0;
+            let mut baseline = 0;
+            let size = opencv::imgproc::get_text_size(
+                &node.label,
+                FONT_HERSHEY_SIMPLEX,
+                base_scale,
+                1,
+                &mut baseline,
+            )
+            .expect("Failed to measure label");
+            let fit = (f64::from(rect.width - 4) / f64::from(size.width)).min(1.0);
+            let scale = base_scale * fit;
+            let width = (f64::from(size.width) * fit).round() as i32;
+            let origin = Point::new(
+                rect.x + (rect.width - width) / 2,
+                rect.y + (rect.height + size.height) / 2,
+            );
+            opencv::imgproc::put_text(
+                &mut mat,
+                &node.label,
+                origin,
+                FONT_HERSHEY_SIMPLEX,
+                scale,
+                text,
+                1,
+                LINE_AA,
+                false,
+            )
+            .expect("Failed to draw label");
+        }
+
+        Self { base: mat, layout }
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
     }
 
     pub fn with_state(&self, state: usize) -> Mat {
         let mut with_highlight = self.base.clone();
-        let x = self.x[state];
-        let y = self.y[state];
-        let width = self.widths[state];
-        let height = self.heights[state];
-
-        let rect = Rect {
-            x,
-            y,
-            width,
-            height,
-        };
-
-        //let colour : VecN<f64, 4> = VecN([166.0, 207.0, 160.0, 0.0]);
-        let colour: VecN<f64, 4> = VecN([94.0, 124.0, 39.0, 0.0]);
-
-        opencv::imgproc::rectangle(&mut with_highlight, rect, colour, 5, LINE_8, 0)
+        if let Some(node) = self.layout.nodes.get(&state) {
+            opencv::imgproc::rectangle(
+                &mut with_highlight,
+                Self::rect(&node.rect),
+                Self::colour(layout::HIGHLIGHT),
+                3,
+                LINE_8,
+                0,
+            )
             .expect("Failed to draw rectangle");
-        // opencv::imgproc::rectangle(&mut with_highlight, rect, 0.0.into(), 5, LINE_8, 0)
-        //     .expect("Failed to draw rectangle");
+        }
 
         with_highlight
     }
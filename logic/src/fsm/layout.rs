@@ -0,0 +1,272 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+
+// Layered layout of a state machine graph, states are ranked by their
+// distance from the initial state and edges going back up are routed around
+// the right hand side
+
+pub(crate) const BACKGROUND: (u8, u8, u8) = (0x7A, 0x15, 0x1B);
+pub(crate) const BORDER: (u8, u8, u8) = (0xD4, 0x87, 0x35);
+pub(crate) const FILL: (u8, u8, u8) = (0xFB, 0xCF, 0x9D);
+pub(crate) const HIGHLIGHT: (u8, u8, u8) = (0x27, 0x7C, 0x5E);
+
+pub(crate) const FONT_SIZE: i32 = 9;
+const CHAR_W: i32 = 6;
+const PAD: i32 = 4;
+const NODE_H: i32 = 16;
+const NODE_GAP: i32 = 10;
+const RANK_GAP: i32 = 24;
+const MARGIN: i32 = 8;
+const LOOP_GAP: i32 = 6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeBox {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub label: String,
+    pub rect: NodeBox,
+}
+
+#[derive(Clone, Debug)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    // Polyline, the arrow head is at the last point
+    pub points: Vec<(i32, i32)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Layout {
+    pub width: i32,
+    pub height: i32,
+    pub nodes: HashMap<usize, Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Layout {
+    // Lay out the graph given each state's label and next states, the
+    // initial state is the lowest tag
+    pub fn new(states: &HashMap<usize, (String, Vec<usize>)>) -> Self {
+        let mut tags: Vec<usize> = states.keys().copied().collect();
+        tags.sort();
+
+        // Rank by shortest distance from the initial state, in the order
+        // states are found so children stay below their parents
+        let mut rank: HashMap<usize, usize> = HashMap::new();
+        let mut order: Vec<usize> = Vec::new();
+        let mut queue = VecDeque::new();
+        if let Some(first) = tags.first() {
+            rank.insert(*first, 0);
+            queue.push_back(*first);
+        }
+        while let Some(tag) = queue.pop_front() {
+            order.push(tag);
+            for next in &states[&tag].1 {
+                if states.contains_key(next) && !rank.contains_key(next) {
+                    rank.insert(*next, rank[&tag] + 1);
+                    queue.push_back(*next);
+                }
+            }
+        }
+        // Unreachable states go on their own row at the bottom
+        let last = rank.values().max().map_or(0, |r| r + 1);
+        for tag in &tags {
+            if !rank.contains_key(tag) {
+                rank.insert(*tag, last);
+                order.push(*tag);
+            }
+        }
+
+        let ranks = rank.values().max().map_or(0, |r| r + 1);
+        let mut rows: Vec<Vec<usize>> = vec![Vec::new(); ranks];
+        for tag in &order {
+            rows[rank[tag]].push(*tag);
+        }
+
+        // Order each row by the mean position of the states leading to it
+        let mut position: HashMap<usize, f32> = HashMap::new();
+        for row in rows.iter_mut() {
+            let mut keyed: Vec<(f32, usize)> = row
+                .iter()
+                .enumerate()
+                .map(|(i, tag)| {
+                    let parents: Vec<f32> = tags
+                        .iter()
+                        .filter(|p| states[p].1.contains(tag) && rank[p] < rank[tag])
+                        .filter_map(|p| position.get(p).copied())
+                        .collect();
+                    if parents.is_empty() {
+                        (i as f32, *tag)
+                    } else {
+                        (parents.iter().sum::<f32>() / parents.len() as f32, *tag)
+                    }
+                })
+                .collect();
+            keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+            *row = keyed.iter().map(|k| k.1).collect();
+            for (i, tag) in row.iter().enumerate() {
+                position.insert(*tag, i as f32);
+            }
+        }
+
+        let size = |tag: &usize| {
+            let len = states[tag].0.chars().count() as i32;
+            (len * CHAR_W + PAD * 2, NODE_H)
+        };
+        let row_width = |row: &Vec<usize>| {
+            row.iter().map(|t| size(t).0).sum::<i32>() + NODE_GAP * (row.len() as i32 - 1)
+        };
+        let inner = rows.iter().map(row_width).max().unwrap_or(0);
+
+        let mut nodes = HashMap::new();
+        for (r, row) in rows.iter().enumerate() {
+            let mut x = MARGIN + (inner - row_width(row)) / 2;
+            let y = MARGIN + r as i32 * (NODE_H + RANK_GAP);
+            for tag in row {
+                let (w, h) = size(tag);
+                nodes.insert(
+                    *tag,
+                    Node {
+                        label: states[tag].0.clone(),
+                        rect: NodeBox { x, y, w, h },
+                    },
+                );
+                x += w + NODE_GAP;
+            }
+        }
+
+        // Edges back up (or along a row) each get their own lane on the right
+        let mut edges = Vec::new();
+        let mut lane = MARGIN + inner;
+        for tag in &tags {
+            let mut next_states = states[tag].1.clone();
+            next_states.sort();
+            next_states.dedup();
+            for next in next_states {
+                let Some(to) = nodes.get(&next) else {
+                    continue;
+                };
+                let from = &nodes[tag].rect;
+                let to = &to.rect;
+                let points = if next == *tag {
+                    let (x, y) = (from.x + from.w, from.y + from.h / 2);
+                    vec![
+                        (x, y - 4),
+                        (x + LOOP_GAP, y - 4),
+                        (x + LOOP_GAP, y + 4),
+                        (x, y + 4),
+                    ]
+                } else if rank[&next] > rank[tag] {
+                    vec![
+                        (from.x + from.w / 2, from.y + from.h),
+                        (to.x + to.w / 2, to.y),
+                    ]
+                } else {
+                    lane += LOOP_GAP;
+                    vec![
+                        (from.x + from.w, from.y + from.h / 2),
+                        (lane, from.y + from.h / 2),
+                        (lane, to.y + to.h / 2),
+                        (to.x + to.w, to.y + to.h / 2),
+                    ]
+                };
+                edges.push(Edge {
+                    from: *tag,
+                    to: next,
+                    points,
+                });
+            }
+        }
+
+        Self {
+            width: lane + LOOP_GAP + MARGIN,
+            height: MARGIN * 2 + (ranks as i32 * (NODE_H + RANK_GAP) - RANK_GAP).max(0),
+            nodes,
+            edges,
+        }
+    }
+
+    fn colour((r, g, b): (u8, u8, u8)) -> String {
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    }
+
+    fn escape(label: &str) -> String {
+        label
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    // SVG of the graph with the current state highlighted
+    pub fn svg(&self, current: Option<usize>) -> String {
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+            w = self.width,
+            h = self.height
+        );
+        let _ = writeln!(
+            svg,
+            "<defs><marker id=\"arrow\" markerWidth=\"6\" markerHeight=\"6\" refX=\"6\" refY=\"3\" orient=\"auto\"><path d=\"M0,0 L6,3 L0,6 z\" fill=\"{}\"/></marker></defs>",
+            Self::colour(BORDER)
+        );
+        let _ = writeln!(
+            svg,
+            "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+            Self::colour(BACKGROUND)
+        );
+
+        for edge in &self.edges {
+            let points: Vec<String> = edge
+                .points
+                .iter()
+                .map(|(x, y)| format!("{},{}", x, y))
+                .collect();
+            let _ = writeln!(
+                svg,
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" marker-end=\"url(#arrow)\"/>",
+                points.join(" "),
+                Self::colour(BORDER)
+            );
+        }
+
+        let mut tags: Vec<&usize> = self.nodes.keys().collect();
+        tags.sort();
+        for tag in tags {
+            let node = &self.nodes[tag];
+            let NodeBox { x, y, w, h } = node.rect;
+            let (stroke, width) = if current == Some(*tag) {
+                (HIGHLIGHT, 3)
+            } else {
+                (BORDER, 1)
+            };
+            let _ = writeln!(
+                svg,
+                "<g id=\"shaoooh_{}\"><rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/><text x=\"{}\" y=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\" font-family=\"DejaVu Sans Mono, monospace\" font-size=\"{}\" fill=\"{}\">{}</text></g>",
+                tag,
+                x,
+                y,
+                w,
+                h,
+                Self::colour(FILL),
+                Self::colour(stroke),
+                width,
+                x + w / 2,
+                y + h / 2,
+                FONT_SIZE,
+                Self::colour(BACKGROUND),
+                Self::escape(&node.label)
+            );
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
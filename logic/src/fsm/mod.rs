@@ -7,9 +7,18 @@ use std::{
 };
 
 mod draw;
+mod layout;
+mod trace;
+pub use trace::{StateTimings, Trace, TraceDelay, TraceSpan};
 
 use rand::Rng;
 
+// Move a start time later by the time spent paused, but not past now in case
+// it was restarted while paused
+pub(crate) fn shift_start(start: &mut SystemTime, paused: Duration) {
+    *start += paused.min(start.elapsed().unwrap_or_default());
+}
+
 pub type BoxedStateCheck<InputValue, StateTransition, InternalState> =
     Box<dyn Fn(&Vec<InputValue>, &mut InternalState) -> Option<(usize, StateTransition)>>;
 
@@ -46,31 +55,57 @@ struct State<InputKind, InputValue, StateOutput, StateTransition, InternalState>
     check: StateCheck<InputKind, InputValue, StateTransition, InternalState>,
 }
 
+// Graph of the states, laid out once so it can be drawn with any state
+// highlighted
+#[derive(Clone, Debug)]
+pub struct StateGraph(layout::Layout);
+
+impl StateGraph {
+    pub fn svg(&self, current: usize) -> String {
+        self.0.svg(Some(current))
+    }
+}
+
 pub struct StateMachine<InputKind, InputValue, StateOutput, StateTransition, InternalState> {
     states:
         HashMap<usize, State<InputKind, InputValue, StateOutput, StateTransition, InternalState>>,
     current: usize,
     time: SystemTime,
+    // Time the current state was entered, unlike time not updated for self loops
+    entered: SystemTime,
     delay: Option<(Duration, usize)>,
+    // When paused, the time spent paused isn't counted towards any delay
+    paused: Option<SystemTime>,
     internal: InternalState,
     empty_input: Vec<InputKind>,
     empty_output: Vec<StateOutput>,
     graph: Option<draw::Graph>,
+    // Open span for the current state, and finished spans not yet taken
+    span: Option<TraceSpan>,
+    spans: Vec<TraceSpan>,
 }
 
-impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fmt::Debug>
+impl<InputKind, InputValue, StateOutput, StateTransition, InternalState>
     StateMachine<InputKind, InputValue, StateOutput, StateTransition, InternalState>
+where
+    InputKind: std::fmt::Debug,
+    StateOutput: std::fmt::Debug,
+    InternalState: std::fmt::Debug,
 {
     pub fn new(internal: InternalState) -> Self {
         Self {
             states: HashMap::new(),
             current: 0,
             time: SystemTime::now(),
+            entered: SystemTime::now(),
             delay: None,
+            paused: None,
             internal,
             empty_input: Vec::new(),
             empty_output: Vec::new(),
             graph: None,
+            span: None,
+            spans: Vec::new(),
         }
     }
 
@@ -82,6 +117,14 @@ impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fm
         }
     }
 
+    pub fn internal(&self) -> &InternalState {
+        &self.internal
+    }
+
+    pub fn internal_mut(&mut self) -> &mut InternalState {
+        &mut self.internal
+    }
+
     pub fn debug_name(&self) -> String {
         if let Some(delay) = self.delay {
             let next = self
@@ -154,23 +197,81 @@ impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fm
     }
 
     pub fn outputs(&self) -> &Vec<StateOutput> {
-        if self.delay.is_none() {
+        if self.delay.is_none() && self.paused.is_none() {
             &self.current_state().outputs
         } else {
             &self.empty_output
         }
     }
 
+    fn open_span(&mut self) {
+        let state = self.current_state();
+        let inputs = state
+            .check
+            .inputs
+            .iter()
+            .map(|i| format!("{:?}", i))
+            .collect();
+        self.span = Some(TraceSpan::new(self.current, state.name.clone(), inputs));
+    }
+
+    fn close_span(&mut self, next: Option<usize>) {
+        if let Some(mut span) = self.span.take() {
+            span.end_us = trace::now_us();
+            span.next = next;
+            self.spans.push(span);
+        }
+    }
+
+    // Leave the current state for another one
+    fn enter(&mut self, next: usize) {
+        self.close_span(Some(next));
+        self.entered = SystemTime::now();
+        self.current = next;
+        self.open_span();
+    }
+
+    // Spans of the states left since the last call
+    pub fn take_trace(&mut self) -> Vec<TraceSpan> {
+        std::mem::take(&mut self.spans)
+    }
+
     pub fn process(&mut self, inputs: Vec<InputValue>) -> Option<StateTransition> {
+        if self.paused.is_some() {
+            return None;
+        }
+        if self.span.is_none() {
+            self.open_span();
+        }
+
         if let Some(delay) = self.delay {
             let extra_delay = Duration::from_secs(0); // TODO for debug
-            if self.time.elapsed().expect("Couldn't get duration") > (delay.0 + extra_delay) {
-                self.current = delay.1;
+            if self.time.elapsed().unwrap_or_default() > (delay.0 + extra_delay) {
+                if self.current != delay.1 {
+                    self.enter(delay.1);
+                }
                 self.delay = None;
             }
 
             None
         } else {
+            // Outputs were emitted for this check by the caller, they are the
+            // same every time so only the first check records them
+            if self.span.as_ref().is_some_and(|s| s.checks == 0) {
+                let outputs: Vec<String> = self
+                    .current_state()
+                    .outputs
+                    .iter()
+                    .map(|o| format!("{:?}", o))
+                    .collect();
+                if let Some(span) = &mut self.span {
+                    span.outputs = outputs;
+                }
+            }
+            if let Some(span) = &mut self.span {
+                span.checks += 1;
+            }
+
             // Cannot use self.current_state() here as that would capture self immutably
             // and not allow the mutable reference to self.internal to be created
             let check = &self
@@ -202,7 +303,9 @@ impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fm
                         log::trace!("Internal State: {:?}", self.internal);
                     }
                     // No delay
-                    self.current = next_state.0;
+                    if changing_state {
+                        self.enter(next_state.0);
+                    }
                 } else if self.current_state().delay_msec.is_empty() {
                     // Delay (Fixed)
                     let duration = Duration::from_millis(self.current_state().delay_msec.start);
@@ -232,6 +335,12 @@ impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fm
                     }
                     self.delay = Some((duration, next_state.0))
                 }
+                if let (Some(span), Some(delay)) = (&mut self.span, self.delay) {
+                    span.delays.push(TraceDelay {
+                        start_us: trace::now_us(),
+                        msecs: delay.0.as_millis() as u64,
+                    });
+                }
 
                 Some(next_state.1)
             } else {
@@ -240,6 +349,55 @@ impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fm
         }
     }
 
+    // How long the machine has been in the current state, including any delay
+    // before moving to the next state
+    pub fn time_in_state(&self) -> Duration {
+        self.entered.elapsed().unwrap_or_default()
+    }
+
+    // Restart timing of the current state, for when the machine has been held
+    // and time spent waiting shouldn't count towards the dwell time
+    pub fn restart_timer(&mut self) {
+        self.time = SystemTime::now();
+        self.entered = SystemTime::now();
+    }
+
+    // Freeze the machine where it is, including any pending delay
+    pub fn pause(&mut self) {
+        if self.paused.is_none() {
+            self.paused = Some(SystemTime::now());
+        }
+    }
+
+    // Carry on from where it was paused, returns how long it was paused for
+    pub fn resume(&mut self) -> Duration {
+        let Some(paused) = self.paused.take() else {
+            return Duration::default();
+        };
+        let duration = paused.elapsed().unwrap_or_default();
+        shift_start(&mut self.time, duration);
+        shift_start(&mut self.entered, duration);
+        duration
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    // In the initial state and not waiting, about to start the sequence again
+    pub fn at_start(&self) -> bool {
+        self.current == 0 && self.delay.is_none()
+    }
+
+    // Return to the initial state, discarding any pending delay
+    pub fn reset(&mut self) {
+        self.close_span(Some(0));
+        self.current = 0;
+        self.delay = None;
+        self.time = SystemTime::now();
+        self.entered = SystemTime::now();
+    }
+
     pub fn graph_str(&self) -> String {
         let graph_config = "ranksep=0.1; bgcolor=\"#7A151B\"; dpi=72; size=\"4.6,7!\";";
         let node_config = "shape=rect,height=0.1,style=filled,color=\"#D48735\",fillcolor=\"#FBCF9D\",fontcolor=\"#7A151B\",fontsize=8,fontname=\"DejaVu Sans Mono\"";
@@ -269,75 +427,41 @@ impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fm
         graph
     }
 
-    pub fn graph_file(&mut self, file_root: &str) -> Result<(), Box<dyn std::error::Error>> {
-        // TODO cleanly error if dot/inkscape not installed
-        let dot_file_name = format!("{}.dot", file_root);
-        let svg_file_name = format!("{}.svg", file_root);
-        let png_file_name = format!("{}.png", file_root);
-        let mut sorted_keys: Vec<&usize> = self.states.keys().collect();
-        sorted_keys.sort();
-        let state_ids: Vec<String> = sorted_keys
-            .into_iter()
-            .map(|f| format!("shaoooh_{}", f))
+    fn layout(&self) -> layout::Layout {
+        let states = self
+            .states
+            .iter()
+            .map(|(tag, state)| (*tag, (state.name.clone(), state.check.next_states.clone())))
             .collect();
+        layout::Layout::new(&states)
+    }
 
-        let mut file = File::create(&dot_file_name)?;
-        file.write_all(self.graph_str().as_bytes())?;
-        file.flush()?;
+    // Lay out and draw the graph for graph_with_state
+    pub fn build_graph(&mut self) {
+        self.graph = Some(draw::Graph::new(self.layout()));
+    }
 
-        let svg = std::process::Command::new("dot")
-            .arg("-Tsvg")
-            .arg(&dot_file_name)
-            .output()?;
-
-        let mut file = File::create(&svg_file_name).unwrap();
-        file.write_all(&svg.stdout).unwrap();
-        file.flush().unwrap();
-
-        std::process::Command::new("inkscape")
-            .arg(&svg_file_name)
-            .arg("-o")
-            .arg(&png_file_name)
-            .status()?;
-
-        let svg_info = std::process::Command::new("inkscape")
-            .arg(svg_file_name)
-            .arg("-I")
-            .arg(state_ids.join(","))
-            .arg("-X")
-            .arg("-Y")
-            .arg("-W")
-            .arg("-H")
-            .output()?;
-
-        let stdout = String::from_utf8(svg_info.stdout)?;
-        let svg_info_arrays: Vec<&str> = stdout.split('\n').collect();
-        let x_array = svg_info_arrays[0]
-            .split(',')
-            .map(|f| f.parse::<f32>().unwrap().round() as i32)
-            .collect();
-        let y_array = svg_info_arrays[1]
-            .split(',')
-            .map(|f| f.parse::<f32>().unwrap().round() as i32)
-            .collect();
-        let w_array = svg_info_arrays[2]
-            .split(',')
-            .map(|f| f.parse::<f32>().unwrap().round() as i32)
-            .collect();
-        let h_array = svg_info_arrays[3]
-            .split(',')
-            .map(|f| f.parse::<f32>().unwrap().round() as i32)
-            .collect();
+    // Graph to draw as SVG, with the state from current_index highlighted
+    pub fn state_graph(&self) -> StateGraph {
+        match &self.graph {
+            Some(g) => StateGraph(g.layout().clone()),
+            None => StateGraph(self.layout()),
+        }
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
 
-        self.graph = Some(draw::Graph::new(
-            png_file_name.as_str(),
-            x_array,
-            y_array,
-            w_array,
-            h_array,
-        ));
+    // Write the graph as Graphviz and SVG files
+    pub fn graph_file(&self, file_root: &str) -> std::io::Result<()> {
+        let mut file = File::create(format!("{}.dot", file_root))?;
+        file.write_all(self.graph_str().as_bytes())?;
+        file.flush()?;
 
-        Ok(())
+        let mut file = File::create(format!("{}.svg", file_root))?;
+        file.write_all(self.layout().svg(None).as_bytes())?;
+        file.flush()
     }
 
     pub fn graph_with_state(&self) -> Option<opencv::core::Mat> {
@@ -348,6 +472,8 @@ impl<InputKind, InputValue, StateOutput, StateTransition, InternalState: std::fm
 impl<InputKind, InputValue, StateOutput, StateTransition, InternalState> Default
     for StateMachine<InputKind, InputValue, StateOutput, StateTransition, InternalState>
 where
+    InputKind: std::fmt::Debug,
+    StateOutput: std::fmt::Debug,
     InternalState: Default + std::fmt::Debug,
 {
     fn default() -> Self {
@@ -0,0 +1,146 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+use serde_json::json;
+
+// Oldest spans are dropped past this, a soft reset loop is a few dozen spans
+const MAX_SPANS: usize = 8192;
+
+pub(crate) fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceDelay {
+    pub start_us: u64,
+    pub msecs: u64,
+}
+
+// Time from entering a state until leaving it, including any delays
+#[derive(Clone, Debug, Serialize)]
+pub struct TraceSpan {
+    pub tag: usize,
+    pub name: String,
+    // Microseconds since the UNIX epoch
+    pub start_us: u64,
+    pub end_us: u64,
+    pub next: Option<usize>,
+    // Delays chosen from the state's range, more than one if it loops on itself
+    pub delays: Vec<TraceDelay>,
+    // Number of times the check ran, with the inputs requested for it
+    pub checks: u32,
+    pub inputs: Vec<String>,
+    // Outputs emitted each time the check ran, listed once
+    pub outputs: Vec<String>,
+}
+
+impl TraceSpan {
+    pub(crate) fn new(tag: usize, name: String, inputs: Vec<String>) -> Self {
+        Self {
+            tag,
+            name,
+            start_us: now_us(),
+            end_us: 0,
+            next: None,
+            delays: Vec::new(),
+            checks: 0,
+            inputs,
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn duration_ms(&self) -> f64 {
+        self.end_us.saturating_sub(self.start_us) as f64 / 1000.0
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StateTimings {
+    pub tag: usize,
+    pub name: String,
+    // Every visit in order, oldest first
+    pub durations_ms: Vec<f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    spans: VecDeque<TraceSpan>,
+}
+
+impl Trace {
+    pub fn extend(&mut self, spans: Vec<TraceSpan>) {
+        self.spans.extend(spans);
+        while self.spans.len() > MAX_SPANS {
+            self.spans.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.spans.clear();
+    }
+
+    pub fn json(&self) -> String {
+        serde_json::to_string(&self.spans).unwrap_or_default()
+    }
+
+    // Chrome trace event format, opens in Perfetto and chrome://tracing
+    pub fn chrome(&self) -> String {
+        let mut events = Vec::new();
+        for span in &self.spans {
+            events.push(json!({
+                "name": span.name,
+                "cat": "state",
+                "ph": "X",
+                "ts": span.start_us,
+                "dur": span.end_us.saturating_sub(span.start_us),
+                "pid": 1,
+                "tid": 1,
+                "args": {
+                    "tag": span.tag,
+                    "next": span.next,
+                    "checks": span.checks,
+                    "inputs": span.inputs,
+                    "outputs": span.outputs,
+                },
+            }));
+            for delay in &span.delays {
+                events.push(json!({
+                    "name": "Wait",
+                    "cat": "delay",
+                    "ph": "X",
+                    "ts": delay.start_us,
+                    "dur": delay.msecs * 1000,
+                    "pid": 1,
+                    "tid": 1,
+                    "args": { "msecs": delay.msecs },
+                }));
+            }
+        }
+        json!({ "traceEvents": events, "displayTimeUnit": "ms" }).to_string()
+    }
+
+    // Time spent in each state over every visit, ordered by tag
+    pub fn timings(&self) -> Vec<StateTimings> {
+        let mut timings: HashMap<usize, StateTimings> = HashMap::new();
+        for span in &self.spans {
+            timings
+                .entry(span.tag)
+                .or_insert_with(|| StateTimings {
+                    tag: span.tag,
+                    name: span.name.clone(),
+                    durations_ms: Vec::new(),
+                })
+                .durations_ms
+                .push(span.duration_ms());
+        }
+        let mut timings: Vec<StateTimings> = timings.into_values().collect();
+        timings.sort_by_key(|t| t.tag);
+        timings
+    }
+}
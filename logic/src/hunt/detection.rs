@@ -7,7 +7,7 @@ use crate::{
     control::{Button, Delay},
     hunt::{
         BoxedProcessFn, Branch2, Branch3, HuntFSMBuilder, HuntResult, HuntStateOutput,
-        InternalHuntState, StateDescription,
+        InternalHuntState, Library, StateDescription,
     },
     vision::{Processing, ProcessingResult},
 };
@@ -31,6 +31,8 @@ enum Detection {
     Run5,
     Run6,
     Toggle,
+    MemoryShiny,
+    MemorySetFound,
 }
 
 #[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
@@ -47,13 +49,7 @@ enum HGSSStarter {
 
 #[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
 enum CheckSummary {
-    Start,
-    Down,
-    ToPokemon,
-    Up1,
-    Up2,
-    Select,
-    ToSummary,
+    OpenSummary,
     Detect,
     Done,
     NextAttempt,
@@ -67,11 +63,7 @@ enum StickyState {
     StartMashB,
     MashB,
     CheckTimer,
-    OpenMenu,
-    ToParty,
-    ToLast,
-    Select,
-    OpenSummary,
+    PartySummary,
     DetectStar,
     FoundTarget,
     Done,
@@ -134,43 +126,10 @@ impl DetectionResolver {
         let method = builder.method().clone();
         let base = if game == Game::RubySapphire { 250 } else { 500 };
 
-        let states = vec![
-            StateDescription::linear_state(
-                CheckSummary::Start,
-                vec![HuntStateOutput::button(Button::Start)],
-                (base * 2)..(base * 2),
-            ),
-            StateDescription::linear_state(
-                CheckSummary::Down,
-                vec![HuntStateOutput::button(Button::Down)],
-                (base * 1)..(base * 1),
-            ),
-            StateDescription::linear_state(
-                CheckSummary::ToPokemon,
-                vec![HuntStateOutput::button(Button::A)],
-                (base * 4)..(base * 4),
-            ),
-            StateDescription::linear_state(
-                CheckSummary::Up1,
-                vec![HuntStateOutput::button(Button::Up)],
-                (base * 1)..(base * 1),
-            ),
-            StateDescription::linear_state(
-                CheckSummary::Up2,
-                vec![HuntStateOutput::button(Button::Up)],
-                (base * 1)..(base * 1),
-            ),
-            StateDescription::linear_state(
-                CheckSummary::Select,
-                vec![HuntStateOutput::button(Button::A)],
-                (base * 2)..(base * 2),
-            ),
-            StateDescription::linear_state(
-                CheckSummary::ToSummary,
-                vec![HuntStateOutput::button(Button::A)],
-                (base * 4)..(base * 4),
-            ),
-        ];
+        let states = vec![StateDescription::call_next(
+            CheckSummary::OpenSummary,
+            Library::gen3_last_summary(base),
+        )];
 
         builder.add_states(states);
 
@@ -378,6 +337,70 @@ impl DetectionResolver {
         builder
     }
 
+    // Wait for the battle to be ready, timing it to spot the shiny animation,
+    // unless the wild Pokemon can be read from memory. Not shiny skips the
+    // wait, shiny waits for the battle to be ready before it's found.
+    fn gen7_detect_state(species: u32) -> StateDescription<Detection> {
+        let ready = Processing::USUMBottomScreen(60.0);
+        let from_memory = move |res: &Vec<ProcessingResult>| {
+            res.iter()
+                .find(|r| r.process == Processing::USUM_WILD_PK7 && r.met && r.species == species)
+                .map(|r| r.shiny)
+        };
+        let ready_for_met = ready.clone();
+
+        let mut detect_checks: HashMap<Detection, BoxedProcessFn> = HashMap::new();
+        detect_checks.insert(
+            Detection::DlyScreen,
+            Box::new(
+                move |res: &Vec<ProcessingResult>, int: &mut InternalHuntState| {
+                    if from_memory(res).is_none()
+                        && res.iter().any(|r| r.process == ready_for_met && r.met)
+                    {
+                        int.last_duration = int.time.elapsed().unwrap_or_default();
+                        Some(HuntResult::default())
+                    } else {
+                        None
+                    }
+                },
+            ),
+        );
+        detect_checks.insert(
+            Detection::MemoryShiny,
+            Box::new(
+                move |res: &Vec<ProcessingResult>, _: &mut InternalHuntState| {
+                    if from_memory(res) == Some(true) {
+                        log::info!("Shiny from memory");
+                        Some(HuntResult::default())
+                    } else {
+                        None
+                    }
+                },
+            ),
+        );
+        detect_checks.insert(
+            Detection::Run2,
+            Box::new(
+                move |res: &Vec<ProcessingResult>, _: &mut InternalHuntState| {
+                    if from_memory(res) == Some(false) {
+                        log::info!("Not shiny from memory");
+                        Some(HuntResult::default())
+                    } else {
+                        None
+                    }
+                },
+            ),
+        );
+
+        StateDescription::new(
+            Detection::Detect,
+            vec![ready, Processing::USUM_WILD_PK7],
+            vec![],
+            0..0,
+            detect_checks,
+        )
+    }
+
     pub fn gen7_legend(mut builder: HuntFSMBuilder) -> HuntFSMBuilder {
         let species = builder.target();
         let timer = match species {
@@ -406,7 +429,8 @@ impl DetectionResolver {
             _ => 100,
         };
 
-        if builder.target() == 806 {
+        let run_branch = Branch3::new(Detection::Run1, Detection::Toggle, Detection::Run2);
+        let run1 = if species == 806 {
             let pre_states = vec![
                 StateDescription::simple_process_state_no_output(
                     Branch2::new(Detection::PrePreEnterEncounter, Detection::WaitPrePreEnter),
@@ -420,74 +444,45 @@ impl DetectionResolver {
             ];
             builder.add_states(pre_states);
 
-            let states = vec![
-                // TODO only check bottom screen for UBs? avoid getting stuck on missed input
-                // or add timeout to this
-                StateDescription::simple_process_state_no_output(
-                    Branch2::new(Detection::PreEnterEncounter, Detection::EnterEncounter),
-                    Processing::USUMBottomScreenInv(5.0),
-                ),
-                StateDescription::simple_process_state_no_output_start_timer(
-                    Branch2::new(Detection::EnterEncounter, Detection::Detect),
-                    Processing::USUMBottomScreen(5.0),
-                ),
-                StateDescription::simple_process_state_no_output_end_timer(
-                    Branch2::new(Detection::Detect, Detection::DlyScreen),
-                    Processing::USUMBottomScreen(60.0),
-                ),
-                StateDescription::linear_state(Detection::DlyScreen, vec![], 0..250),
-                StateDescription::simple_process_state_no_output(
-                    Branch2::new(Detection::SetFound, Detection::Run1),
-                    Processing::SetFound(true),
-                ),
-                StateDescription::branch_last_delay_state_plus_range(
-                    Branch3::new(Detection::Run1, Detection::Toggle, Detection::Run2),
-                    timer,
-                    10500..15000,
-                ),
-                StateDescription::found_target_state(Detection::Toggle, Detection::Done),
-                StateDescription::deadend_state(Detection::Done),
-                StateDescription::incr_encounter_state(Detection::Run2, Detection::Run3),
-                StateDescription::clear_atomic_state(Detection::Run3, Detection::Run4),
-                StateDescription::linear_state(Detection::Run4, vec![], 0..500),
-            ];
-
-            builder.add_states(states);
+            StateDescription::branch_last_delay_state_plus_range(run_branch, timer, 10500..15000)
         } else {
-            let states = vec![
-                // TODO only check bottom screen for UBs? avoid getting stuck on missed input
-                // or add timeout to this
-                StateDescription::simple_process_state_no_output(
-                    Branch2::new(Detection::PreEnterEncounter, Detection::EnterEncounter),
-                    Processing::USUMBottomScreenInv(5.0),
-                ),
-                StateDescription::simple_process_state_no_output_start_timer(
-                    Branch2::new(Detection::EnterEncounter, Detection::Detect),
-                    Processing::USUMBottomScreen(5.0),
-                ),
-                StateDescription::simple_process_state_no_output_end_timer(
-                    Branch2::new(Detection::Detect, Detection::DlyScreen),
-                    Processing::USUMBottomScreen(60.0),
-                ),
-                StateDescription::linear_state(Detection::DlyScreen, vec![], 0..250),
-                StateDescription::simple_process_state_no_output(
-                    Branch2::new(Detection::SetFound, Detection::Run1),
-                    Processing::SetFound(true),
-                ),
-                StateDescription::branch_last_delay_state(
-                    Branch3::new(Detection::Run1, Detection::Toggle, Detection::Run2),
-                    timer,
-                ),
-                StateDescription::found_target_state(Detection::Toggle, Detection::Done),
-                StateDescription::deadend_state(Detection::Done),
-                StateDescription::incr_encounter_state(Detection::Run2, Detection::Run3),
-                StateDescription::clear_atomic_state(Detection::Run3, Detection::Run4),
-                StateDescription::linear_state(Detection::Run4, vec![], 0..500),
-            ];
+            StateDescription::branch_last_delay_state(run_branch, timer)
+        };
 
-            builder.add_states(states);
-        }
+        let states = vec![
+            // TODO only check bottom screen for UBs? avoid getting stuck on missed input
+            // or add timeout to this
+            StateDescription::simple_process_state_no_output(
+                Branch2::new(Detection::PreEnterEncounter, Detection::EnterEncounter),
+                Processing::USUMBottomScreenInv(5.0),
+            ),
+            StateDescription::simple_process_state_no_output_start_timer(
+                Branch2::new(Detection::EnterEncounter, Detection::Detect),
+                Processing::USUMBottomScreen(5.0),
+            ),
+            Self::gen7_detect_state(species),
+            StateDescription::linear_state(Detection::DlyScreen, vec![], 0..250),
+            StateDescription::simple_process_state_no_output(
+                Branch2::new(Detection::SetFound, Detection::Run1),
+                Processing::SetFound(true),
+            ),
+            run1,
+            StateDescription::found_target_state(Detection::Toggle, Detection::Done),
+            StateDescription::deadend_state(Detection::Done),
+            StateDescription::simple_process_state_no_output(
+                Branch2::new(Detection::MemoryShiny, Detection::MemorySetFound),
+                Processing::USUMBottomScreen(60.0),
+            ),
+            StateDescription::simple_process_state_no_output(
+                Branch2::new(Detection::MemorySetFound, Detection::Toggle),
+                Processing::SetFound(true),
+            ),
+            StateDescription::incr_encounter_state(Detection::Run2, Detection::Run3),
+            StateDescription::clear_atomic_state(Detection::Run3, Detection::Run4),
+            StateDescription::linear_state(Detection::Run4, vec![], 0..500),
+        ];
 
+        builder.add_states(states);
         builder
     }
 
@@ -834,35 +829,14 @@ impl DetectionResolver {
                 StateDescription::branch_delay_state(
                     Branch3::new(
                         StickyState::CheckTimer,
-                        StickyState::OpenMenu,
+                        StickyState::PartySummary,
                         StickyState::MashB,
                     ),
                     18000,
                 ),
-                StateDescription::linear_state(
-                    StickyState::OpenMenu,
-                    vec![HuntStateOutput::button(Button::X)],
-                    2000..2000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::ToParty,
-                    vec![HuntStateOutput::button(Button::A)],
-                    2000..2000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::Select,
-                    vec![HuntStateOutput::button(Button::A)],
-                    2000..2000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::OpenSummary,
-                    vec![HuntStateOutput::button(Button::A)],
-                    3000..3000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::ToLast,
-                    vec![HuntStateOutput::button(Button::Up)],
-                    2000..2000,
+                StateDescription::call_next(
+                    StickyState::PartySummary,
+                    Library::usum_party_summary(),
                 ),
                 StateDescription::simple_process_state_no_output3(
                     Branch3::new(
@@ -911,35 +885,14 @@ impl DetectionResolver {
                 StateDescription::branch_delay_state(
                     Branch3::new(
                         StickyState::CheckTimer,
-                        StickyState::OpenMenu,
+                        StickyState::PartySummary,
                         StickyState::MashB,
                     ),
                     37000,
                 ),
-                StateDescription::linear_state(
-                    StickyState::OpenMenu,
-                    vec![HuntStateOutput::button(Button::X)],
-                    2000..2000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::ToParty,
-                    vec![HuntStateOutput::button(Button::A)],
-                    2000..2000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::Select,
-                    vec![HuntStateOutput::button(Button::A)],
-                    2000..2000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::OpenSummary,
-                    vec![HuntStateOutput::button(Button::A)],
-                    3000..3000,
-                ),
-                StateDescription::linear_state(
-                    StickyState::ToLast,
-                    vec![HuntStateOutput::button(Button::Up)],
-                    2000..2000,
+                StateDescription::call_next(
+                    StickyState::PartySummary,
+                    Library::usum_party_summary(),
                 ),
                 StateDescription::simple_process_state_no_output3(
                     Branch3::new(
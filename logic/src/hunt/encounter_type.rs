@@ -1,9 +1,11 @@
+use std::time::Duration;
+
 use strum_macros::AsRefStr;
 
 use crate::{
     app::{Game, Method},
     control::{Button, Delay},
-    hunt::{Branch2, Branch3, HuntFSMBuilder, HuntStateOutput, StateDescription},
+    hunt::{Branch2, Branch3, Compare, HuntFSMBuilder, HuntStateOutput, Library, StateDescription},
     vision::Processing,
 };
 
@@ -20,10 +22,6 @@ enum TryGetEncounter {
 #[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
 enum SoftResetProcess {
     SoftReset,
-    Title1,
-    Title2,
-    Title3,
-    Title4,
     SkipMemory,
     GetGift,
 }
@@ -45,17 +43,7 @@ enum StartSoftResetEncounter {
 
 #[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
 enum USUM {
-    SoftReset1,
-    SoftReset2,
-    Clear1,
-    Clear2,
-    Clear3,
-    AllowHeartbeat,
-    WaitHeartbeat,
-    Title1,
-    Title2,
-    Title3,
-    Title4,
+    SoftReset,
     Circle1,
     Circle2,
     Circle3,
@@ -64,47 +52,26 @@ enum USUM {
 
 #[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
 enum USUMRandom {
-    CheckCounter,
+    CheckHoney,
     StopHeartbeat,
-    SetCounter,
+    SetHoney,
     Wait,
     SoftReset,
-    Clear1,
-    Clear2,
-    Clear3,
-    AllowHeartbeat,
-    WaitHeartbeat,
-    Title1,
-    Title2,
-    Title3,
-    Title4,
-    XToMenu,
-    Down,
-    AToBag,
-    Left1,
-    Left2,
-    Left3,
-    Jump,
-    AToHoney,
-    AToUse,
-    XToMenu2,
-    AToBag2,
-    DecrCounter,
+    UseHoney,
+    UseAgain,
+    CountHoney,
+    Used,
 }
 
 #[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
 enum DarkCave {
     Start,
-    CheckToggle,
+    CheckPass,
     First,
     Second,
     SoftReset,
-    Title1,
-    Title2,
-    Title3,
-    Title4,
-    SetToggle,
-    SetToggle2,
+    FlipPass,
+    FlipPass2,
     Down1,
     Down2,
     Right,
@@ -194,6 +161,16 @@ pub struct EncounterTypeResolver {}
 impl EncounterTypeResolver {
     const MOVE_DELAY: u64 = 75;
     const MOVE_DELAY_DP: u64 = 300;
+    // Every state of a walking or fishing hunt is over in seconds, so a stall
+    // can be caught well before the default limit
+    const WALKING_MAX_DWELL: Duration = Duration::from_secs(2 * 60);
+
+    // Close any text box left open by a missed press, then start walking again
+    fn walking_watchdog(builder: &mut HuntFSMBuilder) {
+        let watchdog = builder.watchdog();
+        watchdog.set_max_dwell(Self::WALKING_MAX_DWELL);
+        watchdog.set_recovery(vec![HuntStateOutput::new(Button::B, Delay::Tenth)]);
+    }
 
     pub fn add_states(builder: HuntFSMBuilder) -> Option<HuntFSMBuilder> {
         let game = builder.game();
@@ -241,37 +218,28 @@ impl EncounterTypeResolver {
             HuntStateOutput::new(Button::Start, Delay::Tenth),
             HuntStateOutput::new(Button::Select, Delay::Tenth),
         ];
+        builder.watchdog().set_recovery(sr_buttons.clone());
+        // Smashing alternates between right after a reset and coming back in
+        builder.variables().declare_bool("second_pass", false);
 
         let states = vec![
             StateDescription::linear_state_no_delay(DarkCave::Start, vec![]),
-            StateDescription::choose_toggle_state(
-                DarkCave::CheckToggle,
+            StateDescription::choose_bool_state(
+                DarkCave::CheckPass,
                 DarkCave::Second,
                 DarkCave::First,
+                "second_pass",
             ),
             StateDescription::linear_state_no_delay(DarkCave::First, vec![]),
-            StateDescription::linear_state(DarkCave::SoftReset, sr_buttons, 7500..8000),
-            StateDescription::linear_state(
-                DarkCave::Title1,
-                vec![HuntStateOutput::button(Button::A)],
-                4000..4250,
-            ),
-            StateDescription::linear_state(
-                DarkCave::Title2,
-                vec![HuntStateOutput::button(Button::A)],
-                4500..4750,
-            ),
-            StateDescription::linear_state(
-                DarkCave::Title3,
-                vec![HuntStateOutput::button(Button::A)],
-                3500..3750,
-            ),
-            StateDescription::linear_state(
-                DarkCave::Title4,
-                vec![HuntStateOutput::button(Button::A)],
-                3000..3250,
+            StateDescription::call_next(
+                DarkCave::SoftReset,
+                Library::soft_reset(
+                    sr_buttons,
+                    7500..8000,
+                    &[4000..4250, 4500..4750, 3500..3750, 3000..3250],
+                ),
             ),
-            StateDescription::toggle_state(DarkCave::SetToggle, DarkCave::Smash),
+            StateDescription::flip_bool_state(DarkCave::FlipPass, DarkCave::Smash, "second_pass"),
             StateDescription::linear_state_no_delay(DarkCave::Second, vec![]),
             StateDescription::linear_state(
                 DarkCave::B1,
@@ -298,7 +266,7 @@ impl EncounterTypeResolver {
                 vec![HuntStateOutput::new(Button::Right, Delay::Tenth)],
                 250..250,
             ),
-            StateDescription::toggle_state(DarkCave::SetToggle2, DarkCave::Smash),
+            StateDescription::flip_bool_state(DarkCave::FlipPass2, DarkCave::Smash, "second_pass"),
             StateDescription::linear_state(
                 DarkCave::Smash,
                 vec![HuntStateOutput::new(Button::A, Delay::Tenth)],
@@ -345,23 +313,16 @@ impl EncounterTypeResolver {
             HuntStateOutput::new(Button::Start, Delay::Tenth),
             HuntStateOutput::new(Button::Select, Delay::Tenth),
         ];
+        builder.watchdog().set_recovery(sr_buttons.clone());
         let states = if builder.game() == &Game::FireRedLeafGreen {
             vec![
-                StateDescription::linear_state(SoftResetProcess::SoftReset, sr_buttons, 3750..3750),
-                StateDescription::linear_state(
-                    SoftResetProcess::Title1,
-                    vec![HuntStateOutput::button(Button::A)],
-                    5000..5000,
-                ),
-                StateDescription::linear_state(
-                    SoftResetProcess::Title2,
-                    vec![HuntStateOutput::button(Button::A)],
-                    3750..3750,
-                ),
-                StateDescription::linear_state(
-                    SoftResetProcess::Title3,
-                    vec![HuntStateOutput::button(Button::A)],
-                    2500..2500,
+                StateDescription::call_next(
+                    SoftResetProcess::SoftReset,
+                    Library::soft_reset(
+                        sr_buttons,
+                        3750..3750,
+                        &[5000..5000, 3750..3750, 2500..2500],
+                    ),
                 ),
                 StateDescription::linear_state(
                     SoftResetProcess::SkipMemory,
@@ -370,29 +331,14 @@ impl EncounterTypeResolver {
                 ),
             ]
         } else {
-            vec![
-                StateDescription::linear_state(SoftResetProcess::SoftReset, sr_buttons, 3750..3750),
-                StateDescription::linear_state(
-                    SoftResetProcess::Title1,
-                    vec![HuntStateOutput::button(Button::A)],
-                    5000..5000,
-                ),
-                StateDescription::linear_state(
-                    SoftResetProcess::Title2,
-                    vec![HuntStateOutput::button(Button::A)],
+            vec![StateDescription::call_next(
+                SoftResetProcess::SoftReset,
+                Library::soft_reset(
+                    sr_buttons,
                     3750..3750,
+                    &[5000..5000, 3750..3750, 2500..2500, 2000..2500],
                 ),
-                StateDescription::linear_state(
-                    SoftResetProcess::Title3,
-                    vec![HuntStateOutput::button(Button::A)],
-                    2500..2500,
-                ),
-                StateDescription::linear_state(
-                    SoftResetProcess::Title4,
-                    vec![HuntStateOutput::button(Button::A)],
-                    2000..2500,
-                ),
-            ]
+            )]
         };
 
         builder.add_states(states);
@@ -528,22 +474,15 @@ impl EncounterTypeResolver {
             HuntStateOutput::new(Button::Start, Delay::Tenth),
             HuntStateOutput::new(Button::Select, Delay::Tenth),
         ];
+        builder.watchdog().set_recovery(sr_buttons.clone());
         let states = vec![
-            StateDescription::linear_state(SoftResetProcess::SoftReset, sr_buttons, 3750..4250),
-            StateDescription::linear_state(
-                SoftResetProcess::Title1,
-                vec![HuntStateOutput::button(Button::A)],
-                5000..5500,
-            ),
-            StateDescription::linear_state(
-                SoftResetProcess::Title2,
-                vec![HuntStateOutput::button(Button::A)],
-                3750..4250,
-            ),
-            StateDescription::linear_state(
-                SoftResetProcess::Title3,
-                vec![HuntStateOutput::button(Button::A)],
-                2500..3000,
+            StateDescription::call_next(
+                SoftResetProcess::SoftReset,
+                Library::soft_reset(
+                    sr_buttons,
+                    3750..4250,
+                    &[5000..5500, 3750..4250, 2500..3000],
+                ),
             ),
             StateDescription::linear_state(
                 SoftResetProcess::SkipMemory,
@@ -686,29 +625,15 @@ impl EncounterTypeResolver {
             HuntStateOutput::new(Button::Start, Delay::Half),
             HuntStateOutput::new(Button::Select, Delay::Half),
         ];
-        let states = vec![
-            StateDescription::linear_state(SoftResetProcess::SoftReset, sr_buttons, 7500..8000),
-            StateDescription::linear_state(
-                SoftResetProcess::Title1,
-                vec![HuntStateOutput::button(Button::A)],
-                4000..4250,
-            ),
-            StateDescription::linear_state(
-                SoftResetProcess::Title2,
-                vec![HuntStateOutput::button(Button::A)],
-                2500..2750,
-            ),
-            StateDescription::linear_state(
-                SoftResetProcess::Title3,
-                vec![HuntStateOutput::button(Button::A)],
-                3500..3750,
-            ),
-            StateDescription::linear_state(
-                SoftResetProcess::Title4,
-                vec![HuntStateOutput::button(Button::A)],
-                3000..3250,
-            ),
-        ];
+        builder.watchdog().set_recovery(sr_buttons.clone());
+        let states = vec![StateDescription::call_next(
+            SoftResetProcess::SoftReset,
+            Library::soft_reset(
+                sr_buttons,
+                7500..8000,
+                &[4000..4250, 2500..2750, 3500..3750, 3000..3250],
+            ),
+        )];
         builder.add_states(states);
 
         match builder.target() {
@@ -846,6 +771,7 @@ impl EncounterTypeResolver {
     }
 
     pub fn frlg_random(mut builder: HuntFSMBuilder) -> HuntFSMBuilder {
+        Self::walking_watchdog(&mut builder);
         let states = vec![
             StateDescription::choose_toggle_state(
                 TryGetEncounter::Init,
@@ -900,6 +826,7 @@ impl EncounterTypeResolver {
     }
 
     pub fn rs_fishing(mut builder: HuntFSMBuilder) -> HuntFSMBuilder {
+        Self::walking_watchdog(&mut builder);
         let states = vec![
             StateDescription::linear_state(
                 FishingStates::TryFish,
@@ -968,6 +895,7 @@ impl EncounterTypeResolver {
             // Wailmer
             return Self::rs_fishing(builder);
         }
+        Self::walking_watchdog(&mut builder);
 
         let states = vec![
             StateDescription::choose_toggle_state(
@@ -1023,6 +951,7 @@ impl EncounterTypeResolver {
     }
 
  pub fn dp_random(mut builder: HuntFSMBuilder) -> HuntFSMBuilder {
+        Self::walking_watchdog(&mut builder);
         let states = vec![
             StateDescription::choose_toggle_state(
                 TryGetEncounter::Init,
@@ -1078,37 +1007,13 @@ impl EncounterTypeResolver {
 
     pub fn gen7_softreset(mut builder: HuntFSMBuilder) -> Option<HuntFSMBuilder> {
         let target = builder.target();
-        let sr_buttons = vec![
-            HuntStateOutput::new(Button::L, Delay::Half),
-            HuntStateOutput::new(Button::R, Delay::Half),
-            HuntStateOutput::new(Button::Start, Delay::Half),
-            HuntStateOutput::new(Button::Select, Delay::Half),
-        ];
-        let states = vec![
-            StateDescription::linear_state(USUM::SoftReset1, sr_buttons.clone(), 50..50),
-            StateDescription::linear_state(USUM::SoftReset2, sr_buttons, 11000..12000),
-            StateDescription::set_atomic_state(USUM::AllowHeartbeat, USUM::Title1),
-            StateDescription::linear_state(
-                USUM::Title1,
-                vec![HuntStateOutput::button(Button::A)],
-                2000..2250,
-            ),
-            StateDescription::linear_state(
-                USUM::Title2,
-                vec![HuntStateOutput::button(Button::A)],
-                2500..2750,
-            ),
-            StateDescription::linear_state(
-                USUM::Title3,
-                vec![HuntStateOutput::button(Button::A)],
-                2000..2250,
-            ),
-            StateDescription::linear_state(
-                USUM::Title4,
-                vec![HuntStateOutput::button(Button::A)],
-                2500..2750,
-            ),
-        ];
+        builder
+            .watchdog()
+            .set_recovery(Library::usum_reset_buttons());
+        let states = vec![StateDescription::call_next(
+            USUM::SoftReset,
+            Library::usum_soft_reset(&[2000..2250, 2500..2750, 2000..2250, 2500..2750]),
+        )];
         let states_walk = vec![
             StateDescription::linear_state(
                 USUM::Circle1,
@@ -1145,46 +1050,7 @@ impl EncounterTypeResolver {
         ];
         let states_honey = vec![
             StateDescription::linear_state(USUMRandom::Wait, vec![], 5000..12500),
-            StateDescription::linear_state(
-                USUMRandom::XToMenu,
-                vec![HuntStateOutput::button(Button::X)],
-                1000..1500,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Down,
-                vec![HuntStateOutput::button(Button::Down)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::AToBag,
-                vec![HuntStateOutput::button(Button::A)],
-                1500..2000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Left1,
-                vec![HuntStateOutput::button(Button::Left)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Left2,
-                vec![HuntStateOutput::button(Button::Left)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Left3,
-                vec![HuntStateOutput::button(Button::Left)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::AToHoney,
-                vec![HuntStateOutput::button(Button::A)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::AToUse,
-                vec![HuntStateOutput::button(Button::A)],
-                500..500,
-            ),
+            StateDescription::call_next(USUMRandom::UseHoney, Library::usum_use_item(3)),
         ];
         builder.add_states(states);
         match target {
@@ -1197,54 +1063,13 @@ impl EncounterTypeResolver {
     }
 
     pub fn gen7_softreset_gift(mut builder: HuntFSMBuilder) -> Option<HuntFSMBuilder> {
-        let sr_buttons = vec![
-            HuntStateOutput::new(Button::L, Delay::Half),
-            HuntStateOutput::new(Button::R, Delay::Half),
-            HuntStateOutput::new(Button::Start, Delay::Half),
-            HuntStateOutput::new(Button::Select, Delay::Half),
-        ];
-        let states = vec![
-            StateDescription::linear_state(USUM::SoftReset1, sr_buttons, 50..50),
-            //StateDescription::linear_state(USUM::SoftReset2, sr_buttons, 50..50),
-            // Add states to clear button presses in case packet got missed
-            StateDescription::linear_state(
-                USUM::Clear1,
-                vec![HuntStateOutput::button(Button::Up)],
-                1000..2000,
-            ),
-            StateDescription::linear_state(
-                USUM::Clear2,
-                vec![HuntStateOutput::button(Button::Up)],
-                1000..2000,
-            ),
-            StateDescription::linear_state(
-                USUM::Clear3,
-                vec![HuntStateOutput::button(Button::Up)],
-                9000..8000,
-            ),
-            StateDescription::set_atomic_state(USUM::AllowHeartbeat, USUM::WaitHeartbeat),
-            StateDescription::linear_state(USUM::WaitHeartbeat, vec![], 2500..2500),
-            StateDescription::linear_state(
-                USUM::Title1,
-                vec![HuntStateOutput::button(Button::A)],
-                50..50,
-            ),
-            StateDescription::linear_state(
-                USUM::Title2,
-                vec![HuntStateOutput::button(Button::A)],
-                2000..2250,
-            ),
-            StateDescription::linear_state(
-                USUM::Title3,
-                vec![HuntStateOutput::button(Button::A)],
-                50..50,
-            ),
-            StateDescription::linear_state(
-                USUM::Title4,
-                vec![HuntStateOutput::button(Button::A)],
-                2500..12750,
-            ),
-        ];
+        builder
+            .watchdog()
+            .set_recovery(Library::usum_reset_buttons());
+        let states = vec![StateDescription::call_next(
+            USUM::SoftReset,
+            Library::usum_soft_reset(&[50..50, 2000..2250, 50..50, 2500..12750]),
+        )];
         builder.add_states(states);
 
         if builder.target() == 803 {
@@ -1315,129 +1140,53 @@ impl EncounterTypeResolver {
     }
 
     pub fn gen7_random_encounter(mut builder: HuntFSMBuilder) -> Option<HuntFSMBuilder> {
-        let sr_buttons = vec![
-            HuntStateOutput::new(Button::L, Delay::Half),
-            HuntStateOutput::new(Button::R, Delay::Half),
-            HuntStateOutput::new(Button::Start, Delay::Half),
-            HuntStateOutput::new(Button::Select, Delay::Half),
-        ];
+        builder
+            .watchdog()
+            .set_recovery(Library::usum_reset_buttons());
+        // Honeys to use before the next soft reset
+        builder.variables().declare_int("honey_left", 0);
 
+        // Soft reset and use a honey every 64 encounters, otherwise the bag
+        // is still open where the last honey was used
         let states = vec![
-            StateDescription::choose_counter_state(
-                USUMRandom::CheckCounter,
-                USUMRandom::StopHeartbeat,
-                USUMRandom::XToMenu2,
-            ),
-            StateDescription::clear_atomic_state(USUMRandom::StopHeartbeat, USUMRandom::SetCounter),
-            StateDescription::set_counter_state(USUMRandom::SetCounter, USUMRandom::Wait, 64),
-            StateDescription::linear_state(USUMRandom::Wait, vec![], 500..1500),
-            // Do soft reset
-            StateDescription::linear_state(USUMRandom::SoftReset, sr_buttons, 50..50),
-            // Add states to clear button presses in case packet got missed
-            StateDescription::linear_state(
-                USUMRandom::Clear1,
-                vec![HuntStateOutput::button(Button::Up)],
-                1000..2000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Clear2,
-                vec![HuntStateOutput::button(Button::Up)],
-                1000..2000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Clear3,
-                vec![HuntStateOutput::button(Button::Up)],
-                9000..8000,
-            ),
-            StateDescription::set_atomic_state(
-                USUMRandom::AllowHeartbeat,
-                USUMRandom::WaitHeartbeat,
-            ),
-            StateDescription::linear_state(USUMRandom::WaitHeartbeat, vec![], 2500..2500),
-            StateDescription::linear_state(
-                USUMRandom::Title1,
-                vec![HuntStateOutput::button(Button::A)],
-                50..50,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Title2,
-                vec![HuntStateOutput::button(Button::A)],
-                2000..2250,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Title3,
-                vec![HuntStateOutput::button(Button::A)],
-                50..50,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Title4,
-                vec![HuntStateOutput::button(Button::A)],
-                5500..12750,
-            ),
-            // Go to bag after soft reset
-            StateDescription::linear_state(
-                USUMRandom::XToMenu,
-                vec![HuntStateOutput::button(Button::X)],
-                1000..1500,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Down,
-                vec![HuntStateOutput::button(Button::Down)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::AToBag,
-                vec![HuntStateOutput::button(Button::A)],
-                1500..2000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Left1,
-                vec![HuntStateOutput::button(Button::Left)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Left2,
-                vec![HuntStateOutput::button(Button::Left)],
-                500..1000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::Left3,
-                vec![HuntStateOutput::button(Button::Left)],
-                500..1000,
-            ),
-            StateDescription::branch_state(USUMRandom::Jump, USUMRandom::AToHoney, 50..50),
-            // Go to bag after running
-            StateDescription::linear_state(
-                USUMRandom::XToMenu2,
-                vec![HuntStateOutput::button(Button::X)],
-                1000..1500,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::AToBag2,
-                vec![HuntStateOutput::button(Button::A)],
-                1500..2000,
-            ),
-            StateDescription::linear_state(
-                USUMRandom::AToHoney,
-                vec![HuntStateOutput::button(Button::A)],
-                500..1000,
+            StateDescription::choose_int_state(
+                Branch3::new(
+                    USUMRandom::CheckHoney,
+                    USUMRandom::StopHeartbeat,
+                    USUMRandom::UseAgain,
+                ),
+                "honey_left",
+                Compare::Le,
+                0,
             ),
-            StateDescription::decr_counter_state(USUMRandom::DecrCounter, USUMRandom::AToUse),
-            StateDescription::linear_state(
-                USUMRandom::AToUse,
-                vec![HuntStateOutput::button(Button::A)],
-                500..500,
+            StateDescription::clear_atomic_state(USUMRandom::StopHeartbeat, USUMRandom::SetHoney),
+            StateDescription::set_int_state(
+                USUMRandom::SetHoney,
+                USUMRandom::Wait,
+                "honey_left",
+                64,
             ),
+            StateDescription::linear_state(USUMRandom::Wait, vec![], 500..1500),
+            StateDescription::call_next(
+                USUMRandom::SoftReset,
+                Library::usum_soft_reset(&[50..50, 2000..2250, 50..50, 5500..12750]),
+            ),
+            StateDescription::call(
+                USUMRandom::UseHoney,
+                Library::usum_use_item(3),
+                USUMRandom::CountHoney,
+            ),
+            StateDescription::call_next(USUMRandom::UseAgain, Library::usum_use_last_item()),
+            StateDescription::add_int_state(
+                USUMRandom::CountHoney,
+                USUMRandom::Used,
+                "honey_left",
+                -1,
+            ),
+            StateDescription::linear_state_no_delay(USUMRandom::Used, vec![]),
         ];
 
         builder.add_states(states);
-        // If counter non-zero
-        // Go to bag, press honey
-        //   X, A, A
-        // If counter zero
-        // Soft reset
-        // Set counter 64
-        // Go to bag, press honey
         Some(builder)
     }
 
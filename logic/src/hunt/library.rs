@@ -0,0 +1,276 @@
+use std::ops::Range;
+
+use strum_macros::AsRefStr;
+
+use crate::{
+    control::{Button, Delay},
+    hunt::{HuntStateOutput, StateDescription, SubMachine},
+};
+
+#[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
+enum UseItem {
+    XToMenu,
+    Down,
+    AToBag,
+    Left,
+    AToItem,
+    AToUse,
+}
+
+#[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
+enum USUMReset {
+    Reset,
+    Clear1,
+    Clear2,
+    Clear3,
+    AllowHeartbeat,
+    WaitHeartbeat,
+}
+
+#[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
+enum PartySummary {
+    OpenMenu,
+    ToParty,
+    Select,
+    OpenSummary,
+    ToLast,
+}
+
+#[derive(PartialEq, Hash, Eq, AsRefStr, Clone)]
+enum Gen3Summary {
+    Start,
+    Down,
+    ToPokemon,
+    Up1,
+    Up2,
+    Select,
+    ToSummary,
+}
+
+// Sequences shared between hunts, called with StateDescription::call
+pub struct Library {}
+
+impl Library {
+    // A once per title screen, waiting the given delays
+    fn title_states(titles: &[Range<u64>]) -> Vec<StateDescription<String>> {
+        titles
+            .iter()
+            .enumerate()
+            .map(|(i, delay)| {
+                StateDescription::linear_state(
+                    format!("Title{}", i + 1),
+                    vec![HuntStateOutput::button(Button::A)],
+                    delay.clone(),
+                )
+            })
+            .collect()
+    }
+
+    // Press the reset combo then A once per title screen, waiting the given
+    // delays. Ends where the game has loaded
+    pub fn soft_reset(
+        buttons: Vec<HuntStateOutput>,
+        reset: Range<u64>,
+        titles: &[Range<u64>],
+    ) -> SubMachine {
+        let mut states = vec![StateDescription::linear_state(
+            "Reset".to_string(),
+            buttons,
+            reset,
+        )];
+        states.extend(Self::title_states(titles));
+        let exit = match titles.len() {
+            0 => "Reset".to_string(),
+            n => format!("Title{}", n),
+        };
+        SubMachine::new(exit, states)
+    }
+
+    // Soft reset combo for the 3DS, also used to recover stuck hunts
+    pub fn usum_reset_buttons() -> Vec<HuntStateOutput> {
+        vec![
+            HuntStateOutput::new(Button::L, Delay::Half),
+            HuntStateOutput::new(Button::R, Delay::Half),
+            HuntStateOutput::new(Button::Start, Delay::Half),
+            HuntStateOutput::new(Button::Select, Delay::Half),
+        ]
+    }
+
+    // Soft reset, pressing Up while the game restarts to clear any button
+    // left held by a missed packet. The heartbeat is allowed again once
+    // restarted, then A is pressed once per title screen
+    pub fn usum_soft_reset(titles: &[Range<u64>]) -> SubMachine {
+        let clear = |tag: USUMReset, delay: Range<u64>| {
+            StateDescription::linear_state(
+                tag.as_ref().to_string(),
+                vec![HuntStateOutput::button(Button::Up)],
+                delay,
+            )
+        };
+        let mut states = vec![
+            StateDescription::linear_state(
+                USUMReset::Reset.as_ref().to_string(),
+                Self::usum_reset_buttons(),
+                50..50,
+            ),
+            clear(USUMReset::Clear1, 1000..2000),
+            clear(USUMReset::Clear2, 1000..2000),
+            clear(USUMReset::Clear3, 9000..9000),
+            StateDescription::set_atomic_state(
+                USUMReset::AllowHeartbeat.as_ref().to_string(),
+                USUMReset::WaitHeartbeat.as_ref().to_string(),
+            ),
+            StateDescription::linear_state(
+                USUMReset::WaitHeartbeat.as_ref().to_string(),
+                vec![],
+                2500..2500,
+            ),
+        ];
+        states.extend(Self::title_states(titles));
+        let exit = match titles.len() {
+            0 => USUMReset::WaitHeartbeat.as_ref().to_string(),
+            n => format!("Title{}", n),
+        };
+        SubMachine::new(exit, states)
+    }
+
+    // Open the bag and use the item lefts presses from the first pocket
+    pub fn usum_use_item(lefts: usize) -> SubMachine {
+        let mut states = vec![
+            StateDescription::linear_state(
+                UseItem::XToMenu.as_ref().to_string(),
+                vec![HuntStateOutput::button(Button::X)],
+                1000..1500,
+            ),
+            StateDescription::linear_state(
+                UseItem::Down.as_ref().to_string(),
+                vec![HuntStateOutput::button(Button::Down)],
+                500..1000,
+            ),
+            StateDescription::linear_state(
+                UseItem::AToBag.as_ref().to_string(),
+                vec![HuntStateOutput::button(Button::A)],
+                1500..2000,
+            ),
+        ];
+        for i in 0..lefts {
+            states.push(StateDescription::linear_state(
+                format!("{}{}", UseItem::Left.as_ref(), i + 1),
+                vec![HuntStateOutput::button(Button::Left)],
+                500..1000,
+            ));
+        }
+        states.push(StateDescription::linear_state(
+            UseItem::AToItem.as_ref().to_string(),
+            vec![HuntStateOutput::button(Button::A)],
+            500..1000,
+        ));
+        states.push(StateDescription::linear_state(
+            UseItem::AToUse.as_ref().to_string(),
+            vec![HuntStateOutput::button(Button::A)],
+            500..500,
+        ));
+        SubMachine::new(UseItem::AToUse.as_ref().to_string(), states)
+    }
+
+    // Use the item used last, the menu and bag open where they were left
+    pub fn usum_use_last_item() -> SubMachine {
+        let states = vec![
+            StateDescription::linear_state(
+                UseItem::XToMenu,
+                vec![HuntStateOutput::button(Button::X)],
+                1000..1500,
+            ),
+            StateDescription::linear_state(
+                UseItem::AToBag,
+                vec![HuntStateOutput::button(Button::A)],
+                1500..2000,
+            ),
+            StateDescription::linear_state(
+                UseItem::AToItem,
+                vec![HuntStateOutput::button(Button::A)],
+                500..1000,
+            ),
+            StateDescription::linear_state(
+                UseItem::AToUse,
+                vec![HuntStateOutput::button(Button::A)],
+                500..500,
+            ),
+        ];
+        SubMachine::new(UseItem::AToUse, states)
+    }
+
+    // Open the summary of the last party member
+    pub fn usum_party_summary() -> SubMachine {
+        let states = vec![
+            StateDescription::linear_state(
+                PartySummary::OpenMenu,
+                vec![HuntStateOutput::button(Button::X)],
+                2000..2000,
+            ),
+            StateDescription::linear_state(
+                PartySummary::ToParty,
+                vec![HuntStateOutput::button(Button::A)],
+                2000..2000,
+            ),
+            StateDescription::linear_state(
+                PartySummary::Select,
+                vec![HuntStateOutput::button(Button::A)],
+                2000..2000,
+            ),
+            StateDescription::linear_state(
+                PartySummary::OpenSummary,
+                vec![HuntStateOutput::button(Button::A)],
+                3000..3000,
+            ),
+            StateDescription::linear_state(
+                PartySummary::ToLast,
+                vec![HuntStateOutput::button(Button::Up)],
+                2000..2000,
+            ),
+        ];
+        SubMachine::new(PartySummary::ToLast, states)
+    }
+
+    // Open the summary of the last party member, delays are multiples of base
+    pub fn gen3_last_summary(base: u64) -> SubMachine {
+        let states = vec![
+            StateDescription::linear_state(
+                Gen3Summary::Start,
+                vec![HuntStateOutput::button(Button::Start)],
+                (base * 2)..(base * 2),
+            ),
+            StateDescription::linear_state(
+                Gen3Summary::Down,
+                vec![HuntStateOutput::button(Button::Down)],
+                base..base,
+            ),
+            StateDescription::linear_state(
+                Gen3Summary::ToPokemon,
+                vec![HuntStateOutput::button(Button::A)],
+                (base * 4)..(base * 4),
+            ),
+            StateDescription::linear_state(
+                Gen3Summary::Up1,
+                vec![HuntStateOutput::button(Button::Up)],
+                base..base,
+            ),
+            StateDescription::linear_state(
+                Gen3Summary::Up2,
+                vec![HuntStateOutput::button(Button::Up)],
+                base..base,
+            ),
+            StateDescription::linear_state(
+                Gen3Summary::Select,
+                vec![HuntStateOutput::button(Button::A)],
+                (base * 2)..(base * 2),
+            ),
+            StateDescription::linear_state(
+                Gen3Summary::ToSummary,
+                vec![HuntStateOutput::button(Button::A)],
+                (base * 4)..(base * 4),
+            ),
+        ];
+        SubMachine::new(Gen3Summary::ToSummary, states)
+    }
+}
@@ -25,6 +25,16 @@ mod encounter_type;
 pub use crate::hunt::encounter_type::*;
 mod detection;
 pub use crate::hunt::detection::*;
+mod watchdog;
+pub use crate::hunt::watchdog::*;
+mod validate;
+pub use crate::hunt::validate::{ValidationIssue, panic_message};
+mod library;
+pub use crate::hunt::library::*;
+mod variables;
+pub use crate::hunt::variables::*;
+mod overrides;
+pub use crate::hunt::overrides::HuntOverrides;
 
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
@@ -46,6 +56,21 @@ impl HuntBuild {
         method: Method,
         atomic: Arc<AtomicBool>,
     ) -> Option<HuntFSM> {
+        let mut builder = Self::builder(target, game, method)?;
+        HuntOverrides::load().apply(&mut builder);
+        match builder.build(atomic) {
+            Ok(fsm) => Some(fsm),
+            Err(issues) => {
+                for issue in issues {
+                    log::error!("Hunt state machine: {}", issue);
+                }
+                None
+            }
+        }
+    }
+
+    // All states added but not yet built, for validation
+    pub fn builder(target: u32, game: Game, method: Method) -> Option<HuntFSMBuilder> {
         let base = BaseHunt {
             target,
             game: game.clone(),
@@ -56,7 +81,7 @@ impl HuntBuild {
 
         if let Some(builder) = EncounterTypeResolver::add_states(builder) {
             if let Some(builder) = DetectionResolver::add_states(builder) {
-                return Some(builder.build(atomic));
+                return Some(builder);
             } else {
                 log::error!("Failed to add detection type states");
             }
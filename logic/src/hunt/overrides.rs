@@ -0,0 +1,81 @@
+use serde::Deserialize;
+
+use crate::{
+    app::{Game, Method},
+    hunt::HuntFSMBuilder,
+};
+
+const OVERRIDES_FILE: &str = "hunt_overrides.json";
+
+// Delay for a state by name, e.g. "SoftReset.Title2", for any game or method
+// unless given
+#[derive(Clone, Debug, Deserialize)]
+pub struct DelayOverride {
+    game: Option<Game>,
+    method: Option<Method>,
+    state: String,
+    delay: [u64; 2],
+}
+
+// Changes to the hunts read from hunt_overrides.json, so timings can be tuned
+// and reloaded without rebuilding
+#[derive(Clone, Debug, Default)]
+pub struct HuntOverrides {
+    delays: Vec<DelayOverride>,
+}
+
+impl HuntOverrides {
+    // No overrides if there is no file, or it can't be read. A reversed or
+    // empty delay would remove the wait, so those are left out unless both
+    // ends are 0
+    pub fn load() -> Self {
+        if !std::fs::exists(OVERRIDES_FILE).unwrap_or(false) {
+            return Self::default();
+        }
+        let mut delays: Vec<DelayOverride> = match std::fs::read_to_string(OVERRIDES_FILE)
+            .map_err(|e| e.to_string())
+            .and_then(|data| serde_json::from_str(&data).map_err(|e| e.to_string()))
+        {
+            Ok(delays) => delays,
+            Err(e) => {
+                log::warn!("Ignoring {}: {}", OVERRIDES_FILE, e);
+                return Self::default();
+            }
+        };
+        delays.retain(|o| match o.delay {
+            [0, 0] => true,
+            [start, end] if start >= end => {
+                log::warn!(
+                    "Ignoring override for {} in {}: delay {}..{} is empty",
+                    o.state,
+                    OVERRIDES_FILE,
+                    start,
+                    end
+                );
+                false
+            }
+            _ => true,
+        });
+        Self { delays }
+    }
+
+    pub fn apply(&self, builder: &mut HuntFSMBuilder) {
+        for o in &self.delays {
+            if o.game.as_ref().is_some_and(|g| g != builder.game())
+                || o.method.as_ref().is_some_and(|m| m != builder.method())
+            {
+                continue;
+            }
+            match builder.override_delay(&o.state, o.delay[0]..o.delay[1]) {
+                0 => log::warn!("Override for {} doesn't match any state", o.state),
+                n => log::info!(
+                    "Delay of {} ({} states) set to {}..{}",
+                    o.state,
+                    n,
+                    o.delay[0],
+                    o.delay[1]
+                ),
+            }
+        }
+    }
+}
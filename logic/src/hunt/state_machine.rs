@@ -7,9 +7,9 @@ use std::sync::atomic::AtomicBool;
 
 use crate::{
     control::{BotControl, Button, Delay},
-    fsm::StateMachine,
-    hunt::HuntResult,
-    vision::{Processing, ProcessingResult},
+    fsm::{StateGraph, StateMachine, TraceSpan, shift_start},
+    hunt::{HuntResult, Variables, Watchdog},
+    vision::{DebugWindows, Processing, ProcessingResult, WinInfo},
 };
 
 #[derive(Debug, Clone)]
@@ -37,6 +37,8 @@ pub struct InternalHuntState {
     pub time: SystemTime,
     pub last_duration: Duration,
     pub counter: usize,
+    // Declared by the hunt
+    pub vars: Variables,
 }
 
 impl std::fmt::Debug for InternalHuntState {
@@ -46,6 +48,7 @@ impl std::fmt::Debug for InternalHuntState {
             .field("atomic", &self.atomic)
             .field("counter", &self.counter)
             .field("duration", &self.last_duration)
+            .field("vars", &self.vars)
             .finish()
     }
 }
@@ -58,6 +61,7 @@ impl InternalHuntState {
             time: SystemTime::now(),
             last_duration: Duration::default(),
             counter: 0,
+            vars: Variables::default(),
         }
     }
 }
@@ -65,9 +69,19 @@ impl InternalHuntState {
 #[derive(Debug)]
 pub struct HuntFSM {
     fsm: StateMachine<Processing, ProcessingResult, HuntStateOutput, HuntResult, InternalHuntState>,
+    watchdog: Watchdog,
 }
 
 impl HuntFSM {
+    const FSM_WIN: WinInfo = WinInfo {
+        name: "fsm",
+        x: 632,
+        y: 32,
+        scale: 1,
+        size: None,
+        flags: WINDOW_AUTOSIZE | WINDOW_KEEPRATIO | WINDOW_GUI_NORMAL,
+    };
+
     pub fn new(
         mut fsm: StateMachine<
             Processing,
@@ -76,17 +90,10 @@ impl HuntFSM {
             HuntResult,
             InternalHuntState,
         >,
+        watchdog: Watchdog,
     ) -> Self {
-        // TODO temporary file
-        fsm.graph_file("current_fsm").expect("Failed to draw graph");
-        opencv::highgui::named_window(
-            "fsm",
-            WINDOW_AUTOSIZE | WINDOW_KEEPRATIO | WINDOW_GUI_NORMAL,
-        )
-        .unwrap_or_else(|_| panic!("Failed to create 'fsm' window"));
-        opencv::highgui::move_window("fsm", 576, 32)
-            .unwrap_or_else(|_| panic!("Failed to move 'fsm' window"));
-        HuntFSM { fsm }
+        fsm.build_graph();
+        HuntFSM { fsm, watchdog }
     }
 
     pub fn processing(&self) -> &Vec<Processing> {
@@ -117,6 +124,39 @@ impl HuntFSM {
 
     pub fn cleanup(&mut self) {}
 
+    // Description of the stall if the hunt has been in one state for too long
+    pub fn stalled(&self) -> Option<String> {
+        let dwell = self.fsm.time_in_state();
+        if self.watchdog.expired(dwell) {
+            Some(format!(
+                "Stuck in {} for {}s (limit {}s)",
+                self.fsm.debug_name(),
+                dwell.as_secs(),
+                self.watchdog.max_dwell().as_secs()
+            ))
+        } else {
+            None
+        }
+    }
+
+    // Run the recovery buttons and restart the state machine, returns false if
+    // there is no recovery for this hunt
+    pub fn recover(&mut self, control: &mut Box<dyn BotControl>) -> bool {
+        let recovery = self.watchdog.recovery();
+        if recovery.is_empty() {
+            return false;
+        }
+        let buttons: Vec<&Button> = recovery.iter().map(|v| &v.button).collect();
+        control.presses_delay(buttons.as_slice(), &recovery[0].delay);
+        self.fsm.reset();
+        true
+    }
+
+    // Restart the dwell time after the hunt has been held, e.g. while reconnecting
+    pub fn restart_timer(&mut self) {
+        self.fsm.restart_timer();
+    }
+
     pub fn current_name(&self) -> String {
         self.fsm.current_name()
     }
@@ -125,6 +165,27 @@ impl HuntFSM {
         self.fsm.debug_name()
     }
 
+    pub fn pause(&mut self) {
+        self.fsm.pause();
+    }
+
+    // Timers in the hunt's state carry on from where they were too
+    pub fn resume(&mut self) {
+        let paused = self.fsm.resume();
+        let internal = self.fsm.internal_mut();
+        shift_start(&mut internal.time, paused);
+        internal.vars.shift_timers(paused);
+    }
+
+    // Nothing is lost by swapping the hunt for a rebuilt one
+    pub fn at_start(&self) -> bool {
+        self.fsm.at_start()
+    }
+
+    pub fn variables(&self) -> &Variables {
+        &self.fsm.internal().vars
+    }
+
     // Only public for testing
     pub fn step_no_output(&mut self, results: Vec<ProcessingResult>) -> HuntResult {
         if let Some(output) = self.fsm.process(results) {
@@ -137,21 +198,30 @@ impl HuntFSM {
         }
     }
 
-    pub fn graph_file(&mut self, file_root: &str) -> Result<(), Box<dyn std::error::Error>> {
+    // States left since the last call, for the timing trace
+    pub fn take_trace(&mut self) -> Vec<TraceSpan> {
+        self.fsm.take_trace()
+    }
+
+    pub fn graph_file(&self, file_root: &str) -> std::io::Result<()> {
         self.fsm.graph_file(file_root)
     }
 
+    pub fn state_graph(&self) -> StateGraph {
+        self.fsm.state_graph()
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.fsm.current_index()
+    }
+
     pub fn graph(&self) -> Option<opencv::core::Mat> {
         self.fsm.graph_with_state()
     }
 
-    pub fn display(&self) {
+    pub fn display(&self, windows: &DebugWindows) {
         if let Some(m) = self.graph() {
-            // TODO combine window drawing code with vision
-            opencv::highgui::imshow("fsm", &m)
-                .unwrap_or_else(|_| panic!("Failed to show 'fsm' window"));
-            opencv::highgui::move_window("fsm", 632, 32)
-                .unwrap_or_else(|_| panic!("Failed to move 'fsm' window"));
+            windows.show(&Self::FSM_WIN, &m);
         }
     }
 }
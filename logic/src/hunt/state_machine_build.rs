@@ -1,7 +1,10 @@
 use crate::app::{Game, Method, RequestTransition, Transition, TransitionArg};
 use crate::fsm::{BoxedStateCheck, StateId, StateMachine};
 use crate::hunt::state_machine::HuntStateOutput;
-use crate::hunt::{BaseHunt, HuntFSM, HuntResult, InternalHuntState};
+use crate::hunt::validate::{self, BuiltState, ValidationIssue};
+use crate::hunt::{
+    BaseHunt, Compare, HuntFSM, HuntResult, InternalHuntState, VariableKind, Variables, Watchdog,
+};
 use crate::vision::{Processing, ProcessingResult};
 use std::cmp::Eq;
 use std::collections::HashMap;
@@ -48,6 +51,38 @@ pub struct StateDescription<K> {
     buttons: Vec<HuntStateOutput>,
     delay_msecs: Range<u64>,
     check: HashMap<K, BoxedProcessFn>,
+    // Never leaves the state, on purpose
+    deadend: bool,
+    // Sub-machine run in place of this state, and where to go after it
+    call: Option<(SubMachine, Option<K>)>,
+    // Hunt variables the checks use, must be declared
+    vars: Vec<(VariableKind, String)>,
+}
+
+// Sequence of states that can be called from any hunt. It starts at the first
+// state and leaving the exit state returns to the caller, the exit has to be
+// a linear state. The states are inlined in place of each call, so the call
+// itself doesn't add a state
+pub struct SubMachine {
+    exit: String,
+    states: Vec<StateDescription<String>>,
+}
+
+impl SubMachine {
+    pub fn new<S: Hash + Eq + Clone + AsRef<str>>(
+        exit: S,
+        states: Vec<StateDescription<S>>,
+    ) -> Self {
+        SubMachine {
+            exit: exit.as_ref().to_string(),
+            states: states.into_iter().map(|s| s.retag()).collect(),
+        }
+    }
+
+    // Number of states once inlined, including anything it calls
+    fn len(&self) -> usize {
+        self.states.iter().map(|s| s.len()).sum()
+    }
 }
 
 struct FragmentState {
@@ -57,6 +92,8 @@ struct FragmentState {
     outputs: Vec<HuntStateOutput>,
     delay_msecs: Range<u64>,
     checks: HashMap<usize, BoxedProcessFn>,
+    deadend: bool,
+    vars: Vec<(VariableKind, String)>,
 }
 
 struct FSMFragment {
@@ -66,12 +103,28 @@ struct FSMFragment {
 pub struct HuntFSMBuilder {
     fragments: Vec<FSMFragment>,
     base: BaseHunt,
+    watchdog: Watchdog,
+    variables: Variables,
 }
 
 impl HuntFSMBuilder {
     pub fn new(base: BaseHunt) -> Self {
         let fragments = Vec::new();
-        HuntFSMBuilder { fragments, base }
+        HuntFSMBuilder {
+            fragments,
+            base,
+            watchdog: Watchdog::new(),
+            variables: Variables::default(),
+        }
+    }
+
+    pub fn watchdog(&mut self) -> &mut Watchdog {
+        &mut self.watchdog
+    }
+
+    // Declare the variables used by the hunt's states
+    pub fn variables(&mut self) -> &mut Variables {
+        &mut self.variables
     }
 
     pub fn game(&self) -> &Game {
@@ -86,44 +139,188 @@ impl HuntFSMBuilder {
         self.base.target
     }
 
+    // Change the delay of every state with this name, e.g. "SoftReset.Title2".
+    // Returns how many were changed
+    pub fn override_delay(&mut self, name: &str, delay: Range<u64>) -> usize {
+        let mut changed = 0;
+        for state in self.fragments.iter_mut().flat_map(|f| f.states.iter_mut()) {
+            if state.name == name {
+                state.delay_msecs = delay.clone();
+                changed += 1;
+            }
+        }
+        changed
+    }
+
     pub fn add_states<K: Hash + Eq + Clone + AsRef<str>>(
         &mut self,
         states: Vec<StateDescription<K>>,
     ) {
+        let (fragment, _) = Self::flatten(states, 0, "", None);
+        self.fragments.push(FSMFragment { states: fragment });
+    }
+
+    // Number the states from first, inlining any called sub-machine in place
+    // of its call state. When flattening a sub-machine, leaving the exit state
+    // goes to the caller's return state
+    fn flatten<K: Hash + Eq + Clone + AsRef<str>>(
+        states: Vec<StateDescription<K>>,
+        first: usize,
+        prefix: &str,
+        exit: Option<(String, usize)>,
+    ) -> (Vec<FragmentState>, HashMap<K, usize>) {
         let mut state_mapping = HashMap::new();
         let mut fragment = Vec::new();
 
+        let mut index = first;
         for s in &states {
-            let index = state_mapping.len();
             state_mapping.insert(s.tag.clone(), index);
+            index += s.len();
         }
 
         for s in states {
-            let mut new_checks = HashMap::new();
+            let tag = *state_mapping.get(&s.tag).expect("Failed to get tag");
+            let len = s.len();
+            let name = format!("{}{}", prefix, s.tag.as_ref());
+
+            if let Some((sub, ret)) = s.call {
+                // Without a return state carry on after the call, like a linear state
+                let ret = match ret {
+                    Some(ret) => *state_mapping.get(&ret).expect("Failed to get tag"),
+                    None => tag + len,
+                };
+                let (sub_states, _) = Self::flatten(
+                    sub.states,
+                    tag,
+                    &format!("{}.", name),
+                    Some((sub.exit, ret)),
+                );
+                fragment.extend(sub_states);
+                continue;
+            }
+
+            let mut new_checks: HashMap<usize, BoxedProcessFn> = HashMap::new();
             for chk in s.check {
                 new_checks.insert(
                     *state_mapping.get(&chk.0).expect("Failed to get tag"),
                     chk.1,
                 );
             }
+            if let Some((exit, ret)) = &exit
+                && s.tag.as_ref() == exit
+            {
+                assert!(
+                    new_checks.is_empty() && s.call.is_none(),
+                    "Sub-machine exit '{}' must be a linear state",
+                    name
+                );
+                new_checks.insert(*ret, Box::new(|_, _| Some(HuntResult::default())));
+            }
 
-            let fragment_state = FragmentState {
-                tag: *state_mapping.get(&s.tag).expect("Failed to get tag"),
-                name: s.tag.as_ref().to_string(),
+            fragment.push(FragmentState {
+                tag,
+                name,
                 inputs: s.inputs,
                 outputs: s.buttons,
                 delay_msecs: s.delay_msecs,
                 checks: new_checks,
-            };
-            fragment.push(fragment_state);
+                deadend: s.deadend,
+                vars: s.vars,
+            });
         }
 
-        self.fragments.push(FSMFragment { states: fragment });
+        (fragment, state_mapping)
+    }
+
+    // States numbered as they will be in the built machine
+    pub(crate) fn states(&self) -> Vec<BuiltState<'_>> {
+        let total: usize = self.fragments.iter().map(|f| f.states.len()).sum();
+        let wrap = |tag: usize| if tag == total { 0 } else { tag };
+        let mut states = Vec::new();
+        let mut fragment_first = 0;
+
+        for (findex, fragment) in self.fragments.iter().enumerate() {
+            for state in &fragment.states {
+                let tag = state.tag + fragment_first;
+                let mut checks: Vec<(usize, &BoxedProcessFn)> = state
+                    .checks
+                    .iter()
+                    .map(|(next, check)| (wrap(next + fragment_first), check))
+                    .collect();
+                checks.sort_by_key(|c| c.0);
+                let next_states = if checks.is_empty() {
+                    vec![if tag + 1 == total { 0 } else { tag + 1 }]
+                } else {
+                    checks.iter().map(|c| c.0).collect()
+                };
+                states.push(BuiltState {
+                    tag,
+                    name: format!("fragment{}-{}", findex, state.name),
+                    inputs: &state.inputs,
+                    outputs: &state.outputs,
+                    delay_msecs: state.delay_msecs.clone(),
+                    checks,
+                    next_states,
+                    deadend: state.deadend,
+                    vars: &state.vars,
+                });
+            }
+            fragment_first += fragment.states.len();
+        }
+
+        states
+    }
+
+    // One line per state. Checks can't be compared, so two builders with the
+    // same description may still behave differently
+    pub fn describe(&self) -> Vec<String> {
+        self.states()
+            .iter()
+            .map(|s| {
+                format!(
+                    "{} {:?} {:?} {:?} -> {:?}{}",
+                    s.name,
+                    s.inputs,
+                    s.outputs,
+                    s.delay_msecs,
+                    s.next_states,
+                    if s.deadend { " deadend" } else { "" }
+                )
+            })
+            .collect()
+    }
+
+    // Checks the graph, then probes every state's checks with made up
+    // results and internal states, so can be slow
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let states = self.states();
+        let mut issues = validate::check_graph(&states);
+        issues.extend(validate::check_variables(&states, &self.variables));
+        issues.extend(validate::probe(&states, self.target(), &self.variables));
+        issues
     }
 
-    pub fn build(self, atomic: Arc<AtomicBool>) -> HuntFSM {
+    // Fails if some states can't be reached or can't get back to the start,
+    // the hunt would get stuck rather than finish, or if states use variables
+    // that weren't declared
+    pub fn build(self, atomic: Arc<AtomicBool>) -> Result<HuntFSM, Vec<ValidationIssue>> {
+        let issues = {
+            let states = self.states();
+            let mut issues = validate::check_graph(&states);
+            issues.extend(validate::check_variables(&states, &self.variables));
+            issues
+        };
+        if !issues.is_empty() {
+            return Err(issues);
+        }
+
         let mut last_index = 0;
-        let mut fsm = StateMachine::new(InternalHuntState::new(atomic));
+        let mut internal = InternalHuntState::new(atomic);
+        internal.vars = self.variables;
+        let mut fsm = StateMachine::new(internal);
+        // Sub-machines called at the very end return past the last state
+        let total: usize = self.fragments.iter().map(|f| f.states.len()).sum();
+        let wrap = move |tag: usize| if tag == total { 0 } else { tag };
 
         let last_fragment = self.fragments.len() - 1;
         for (findex, fragment) in self.fragments.into_iter().enumerate() {
@@ -151,8 +348,11 @@ impl HuntFSMBuilder {
                     Vec<usize>,
                     BoxedStateCheck<ProcessingResult, HuntResult, InternalHuntState>,
                 ) = if !state.checks.is_empty() {
-                    let next_states: Vec<usize> =
-                        state.checks.keys().map(|x| x + fragment_first).collect();
+                    let next_states: Vec<usize> = state
+                        .checks
+                        .keys()
+                        .map(|x| wrap(x + fragment_first))
+                        .collect();
                     let check = Box::new(
                         move |x: &Vec<ProcessingResult>, int: &mut InternalHuntState| {
                             // Vec of InputValue -> (usize, HuntResult)
@@ -167,7 +367,7 @@ impl HuntFSMBuilder {
                                         result.is_none(),
                                         "Only one state check should match"
                                     );
-                                    result = Some((*target + fragment_first, res));
+                                    result = Some((wrap(*target + fragment_first), res));
                                 }
                             }
 
@@ -198,7 +398,32 @@ impl HuntFSMBuilder {
             last_index = fragment_last + 1;
         }
 
-        HuntFSM::new(fsm)
+        Ok(HuntFSM::new(fsm, self.watchdog))
+    }
+}
+
+impl<K> StateDescription<K>
+where
+    K: Hash + Eq + Clone + AsRef<str>,
+{
+    // Use the tag names, so states of any hunt can go in a sub-machine
+    fn retag(self) -> StateDescription<String> {
+        StateDescription {
+            tag: self.tag.as_ref().to_string(),
+            inputs: self.inputs,
+            buttons: self.buttons,
+            delay_msecs: self.delay_msecs,
+            check: self
+                .check
+                .into_iter()
+                .map(|(k, f)| (k.as_ref().to_string(), f))
+                .collect(),
+            deadend: self.deadend,
+            call: self
+                .call
+                .map(|(sub, ret)| (sub, ret.map(|r| r.as_ref().to_string()))),
+            vars: self.vars,
+        }
     }
 }
 
@@ -219,9 +444,33 @@ where
             buttons,
             delay_msecs,
             check,
+            deadend: false,
+            call: None,
+            vars: Vec::new(),
         }
     }
 
+    // Run a sub-machine, then go to ret
+    pub fn call(tag: K, sub: SubMachine, ret: K) -> Self {
+        Self {
+            call: Some((sub, Some(ret))),
+            ..Self::new(tag, vec![], vec![], 0..0, HashMap::new())
+        }
+    }
+
+    // Run a sub-machine, then carry on to the next state
+    pub fn call_next(tag: K, sub: SubMachine) -> Self {
+        Self {
+            call: Some((sub, None)),
+            ..Self::new(tag, vec![], vec![], 0..0, HashMap::new())
+        }
+    }
+
+    // Number of states once inlined
+    fn len(&self) -> usize {
+        self.call.as_ref().map_or(1, |c| c.0.len())
+    }
+
     pub fn set_counter_state(tag: K, to: K, value: usize) -> Self {
         let mut set_count_check: HashMap<K, BoxedProcessFn> = HashMap::new();
 
@@ -264,7 +513,7 @@ where
         branch_state.insert(
             to,
             Box::new(move |_, int| {
-                int.last_duration = int.time.elapsed().unwrap();
+                int.last_duration = int.time.elapsed().unwrap_or_default();
                 Some(HuntResult::default())
             }),
         );
@@ -281,7 +530,7 @@ where
             branch.to_met,
             Box::new(
                 move |_: &Vec<ProcessingResult>, int: &mut InternalHuntState| {
-                    if int.time.elapsed().unwrap() > duration {
+                    if int.time.elapsed().unwrap_or_default() > duration {
                         Some(HuntResult::default())
                     } else {
                         None
@@ -293,7 +542,7 @@ where
             branch.to_not,
             Box::new(
                 move |_: &Vec<ProcessingResult>, int: &mut InternalHuntState| {
-                    if int.time.elapsed().unwrap() > dur2 {
+                    if int.time.elapsed().unwrap_or_default() > dur2 {
                         None
                     } else {
                         Some(HuntResult::default())
@@ -542,6 +791,198 @@ where
         Self::new(tag, vec![], vec![], 0..0, toggle_check)
     }
 
+    pub fn set_int_state(tag: K, to: K, name: &str, value: i64) -> Self {
+        let vars = vec![(VariableKind::Int, name.to_string())];
+        let name = name.to_string();
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            to,
+            Box::new(move |_, int| {
+                int.vars.set_int(&name, value);
+                Some(HuntResult::default())
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
+    // Negative to decrement
+    pub fn add_int_state(tag: K, to: K, name: &str, value: i64) -> Self {
+        let vars = vec![(VariableKind::Int, name.to_string())];
+        let name = name.to_string();
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            to,
+            Box::new(move |_, int| {
+                let current = int.vars.int(&name);
+                int.vars.set_int(&name, current + value);
+                Some(HuntResult::default())
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
+    // To met if the variable compares true against value
+    pub fn choose_int_state(branch: Branch3<K>, name: &str, compare: Compare, value: i64) -> Self {
+        let vars = vec![(VariableKind::Int, name.to_string())];
+        let name = name.to_string();
+        let name2 = name.clone();
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            branch.to_met,
+            Box::new(move |_, int| {
+                if compare.test(int.vars.int(&name), value) {
+                    Some(HuntResult::default())
+                } else {
+                    None
+                }
+            }),
+        );
+        var_check.insert(
+            branch.to_not,
+            Box::new(move |_, int| {
+                if compare.test(int.vars.int(&name2), value) {
+                    None
+                } else {
+                    Some(HuntResult::default())
+                }
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(branch.tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
+    pub fn set_bool_state(tag: K, to: K, name: &str, value: bool) -> Self {
+        let vars = vec![(VariableKind::Bool, name.to_string())];
+        let name = name.to_string();
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            to,
+            Box::new(move |_, int| {
+                int.vars.set_bool(&name, value);
+                Some(HuntResult::default())
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
+    pub fn flip_bool_state(tag: K, to: K, name: &str) -> Self {
+        let vars = vec![(VariableKind::Bool, name.to_string())];
+        let name = name.to_string();
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            to,
+            Box::new(move |_, int| {
+                let current = int.vars.bool(&name);
+                int.vars.set_bool(&name, !current);
+                Some(HuntResult::default())
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
+    pub fn choose_bool_state(tag: K, set: K, clear: K, name: &str) -> Self {
+        let vars = vec![(VariableKind::Bool, name.to_string())];
+        let name = name.to_string();
+        let name2 = name.clone();
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            set,
+            Box::new(move |_, int| {
+                if int.vars.bool(&name) {
+                    Some(HuntResult::default())
+                } else {
+                    None
+                }
+            }),
+        );
+        var_check.insert(
+            clear,
+            Box::new(move |_, int| {
+                if int.vars.bool(&name2) {
+                    None
+                } else {
+                    Some(HuntResult::default())
+                }
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
+    pub fn start_named_timer_state(tag: K, to: K, name: &str) -> Self {
+        let vars = vec![(VariableKind::Timer, name.to_string())];
+        let name = name.to_string();
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            to,
+            Box::new(move |_, int| {
+                int.vars.start_timer(&name);
+                Some(HuntResult::default())
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
+    // To met once the timer has run for longer than delay
+    pub fn branch_named_timer_state(branch: Branch3<K>, name: &str, delay: u64) -> Self {
+        let vars = vec![(VariableKind::Timer, name.to_string())];
+        let name = name.to_string();
+        let name2 = name.clone();
+        let duration = Duration::from_millis(delay);
+        let mut var_check: HashMap<K, BoxedProcessFn> = HashMap::new();
+        var_check.insert(
+            branch.to_met,
+            Box::new(move |_, int| {
+                if int.vars.elapsed(&name) > duration {
+                    Some(HuntResult::default())
+                } else {
+                    None
+                }
+            }),
+        );
+        var_check.insert(
+            branch.to_not,
+            Box::new(move |_, int| {
+                if int.vars.elapsed(&name2) > duration {
+                    None
+                } else {
+                    Some(HuntResult::default())
+                }
+            }),
+        );
+
+        Self {
+            vars,
+            ..Self::new(branch.tag, vec![], vec![], 0..0, var_check)
+        }
+    }
+
     pub fn linear_state_no_delay(tag: K, buttons: Vec<HuntStateOutput>) -> Self {
         Self::linear_state(tag, buttons, 0..0)
     }
@@ -558,7 +999,10 @@ where
                 Some(HuntResult::default())
             }),
         );
-        Self::new(tag, Vec::new(), Vec::new(), 0..0, deadend_checks)
+        Self {
+            deadend: true,
+            ..Self::new(tag, Vec::new(), Vec::new(), 0..0, deadend_checks)
+        }
     }
 
     fn simple_process_state_helper(
@@ -590,7 +1034,7 @@ where
                             int.time = SystemTime::now()
                         }
                         if end_timer {
-                            int.last_duration = int.time.elapsed().expect("Couldn't get duration")
+                            int.last_duration = int.time.elapsed().unwrap_or_default()
                         }
                         Some(HuntResult::default())
                     } else {
@@ -0,0 +1,360 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Display;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, SystemTime};
+
+use crate::app::{RequestTransition, Transition};
+use crate::hunt::{BoxedProcessFn, HuntStateOutput, InternalHuntState, VariableKind, Variables};
+use crate::vision::{Processing, ProcessingResult};
+
+// Timers are probed every PROBE_STEP up to PROBE_MAX, and once long after
+const PROBE_STEP: u64 = 250;
+const PROBE_MAX: u64 = 16000;
+const PROBE_LONG: u64 = 3_600_000;
+// Counters are probed from zero up to this
+const PROBE_COUNTER: usize = 64;
+// Limit on the result combinations tried for states with many inputs
+const PROBE_RESULTS: usize = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationIssue {
+    Unreachable(String),
+    DeadEnd(String),
+    NoFoundTarget,
+    NoEncounter,
+    NeverMatches {
+        state: String,
+        next: String,
+    },
+    Panicked {
+        state: String,
+        next: String,
+        message: String,
+    },
+    MultipleMatches(String),
+    UndeclaredVariable {
+        state: String,
+        kind: VariableKind,
+        name: String,
+    },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unreachable(state) => write!(f, "{} can't be reached", state),
+            Self::DeadEnd(state) => write!(
+                f,
+                "{} can't get back to the start and isn't a deadend state",
+                state
+            ),
+            Self::NoFoundTarget => write!(f, "No check reports FoundTarget"),
+            Self::NoEncounter => write!(f, "No check increments the encounters"),
+            Self::NeverMatches { state, next } => write!(
+                f,
+                "{} check for {} never matched, does it read an input that isn't requested?",
+                state, next
+            ),
+            Self::Panicked {
+                state,
+                next,
+                message,
+            } => write!(f, "{} check for {} panicked: {}", state, next, message),
+            Self::MultipleMatches(state) => {
+                write!(f, "{} has more than one check matching at once", state)
+            }
+            Self::UndeclaredVariable { state, kind, name } => write!(
+                f,
+                "{} uses {} variable {}, which the hunt doesn't declare",
+                state,
+                kind.name(),
+                name
+            ),
+        }
+    }
+}
+
+// A state of the stitched together machine, tags are global
+pub(crate) struct BuiltState<'a> {
+    pub(crate) tag: usize,
+    pub(crate) name: String,
+    pub(crate) inputs: &'a [Processing],
+    pub(crate) outputs: &'a [HuntStateOutput],
+    pub(crate) delay_msecs: Range<u64>,
+    pub(crate) checks: Vec<(usize, &'a BoxedProcessFn)>,
+    pub(crate) next_states: Vec<usize>,
+    pub(crate) deadend: bool,
+    pub(crate) vars: &'a [(VariableKind, String)],
+}
+
+fn find<'a, 'b>(states: &'b [BuiltState<'a>], tag: usize) -> Option<&'b BuiltState<'a>> {
+    states.iter().find(|s| s.tag == tag)
+}
+
+// States reachable from the start states, or that can reach them if reverse
+fn reach(states: &[BuiltState], start: Vec<usize>, reverse: bool) -> HashSet<usize> {
+    let mut seen: HashSet<usize> = start.iter().copied().collect();
+    let mut queue: VecDeque<usize> = start.into();
+
+    while let Some(tag) = queue.pop_front() {
+        let next: Vec<usize> = if reverse {
+            states
+                .iter()
+                .filter(|s| s.next_states.contains(&tag))
+                .map(|s| s.tag)
+                .collect()
+        } else {
+            find(states, tag).map_or(vec![], |s| s.next_states.clone())
+        };
+        for n in next {
+            if seen.insert(n) {
+                queue.push_back(n);
+            }
+        }
+    }
+
+    seen
+}
+
+// Unreachable states and states that get stuck, without running any checks
+pub(crate) fn check_graph(states: &[BuiltState]) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    if states.is_empty() {
+        return issues;
+    }
+
+    let reachable = reach(states, vec![0], false);
+    let ends = states
+        .iter()
+        .filter(|s| s.tag == 0 || s.deadend)
+        .map(|s| s.tag)
+        .collect();
+    let returns = reach(states, ends, true);
+
+    for state in states {
+        if !reachable.contains(&state.tag) {
+            issues.push(ValidationIssue::Unreachable(state.name.clone()));
+        } else if !returns.contains(&state.tag) {
+            issues.push(ValidationIssue::DeadEnd(state.name.clone()));
+        }
+    }
+
+    issues
+}
+
+// Variables used by states without being declared, which would panic once
+// the state is reached
+pub(crate) fn check_variables(states: &[BuiltState], vars: &Variables) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    for state in states {
+        for (kind, name) in state.vars {
+            if !vars.declared(*kind, name) {
+                issues.push(ValidationIssue::UndeclaredVariable {
+                    state: state.name.clone(),
+                    kind: *kind,
+                    name: name.clone(),
+                });
+            }
+        }
+    }
+    issues
+}
+
+struct ProbeState {
+    toggle: bool,
+    atomic: bool,
+    elapsed: Duration,
+    counter: usize,
+}
+
+impl ProbeState {
+    // Timers and counters are varied separately, trying every pairing would
+    // take PROBE_COUNTER times as long. Timer checks only read the time and
+    // last duration, counter and integer variable checks only the counter,
+    // and the detection checks only add timers. A check reading both would
+    // only be tried with one of them at zero, so shows up as NeverMatches
+    // rather than being missed
+    fn all() -> Vec<Self> {
+        let mut elapsed: Vec<u64> = (0..=PROBE_MAX).step_by(PROBE_STEP as usize).collect();
+        elapsed.push(PROBE_LONG);
+
+        let mut probes = Vec::new();
+        for toggle in [false, true] {
+            for atomic in [false, true] {
+                for msecs in &elapsed {
+                    probes.push(Self {
+                        toggle,
+                        atomic,
+                        elapsed: Duration::from_millis(*msecs),
+                        counter: 0,
+                    });
+                }
+                for counter in 1..=PROBE_COUNTER {
+                    probes.push(Self {
+                        toggle,
+                        atomic,
+                        elapsed: Duration::default(),
+                        counter,
+                    });
+                }
+            }
+        }
+        probes
+    }
+
+    // Checks may change the state, so each gets a fresh one. The hunt's
+    // variables follow the counter, toggle and timer
+    fn state(&self, vars: &Variables) -> InternalHuntState {
+        InternalHuntState {
+            toggle: self.toggle,
+            atomic: Arc::new(AtomicBool::new(self.atomic)),
+            time: SystemTime::now() - self.elapsed,
+            last_duration: self.elapsed,
+            counter: self.counter,
+            vars: vars.probe(self.counter as i64, self.toggle, self.elapsed),
+        }
+    }
+}
+
+// Possible (met, species, shiny) results for an input
+fn input_results(input: &Processing, target: u32) -> Vec<(bool, u32, bool)> {
+    let mut species = vec![0, target];
+    match input {
+        Processing::Sprite(_, list, _)
+        | Processing::SpriteT(_, list, _, _)
+        | Processing::Sprite3DS(_, list) => species.extend(list),
+        _ => {}
+    }
+    species.sort();
+    species.dedup();
+
+    let mut results = Vec::new();
+    for met in [false, true] {
+        for s in &species {
+            for shiny in [false, true] {
+                results.push((met, *s, shiny));
+            }
+        }
+    }
+    results
+}
+
+// Every combination of results for the inputs, one result per input
+fn result_combinations(inputs: &[Processing], target: u32) -> Vec<Vec<(bool, u32, bool)>> {
+    let mut combinations = vec![vec![]];
+    for input in inputs {
+        let options = input_results(input, target);
+        combinations = combinations
+            .iter()
+            .flat_map(|c| {
+                options.iter().map(move |o| {
+                    let mut c = c.clone();
+                    c.push(*o);
+                    c
+                })
+            })
+            .take(PROBE_RESULTS)
+            .collect();
+    }
+    combinations
+}
+
+// Message of a caught panic, for reporting
+pub fn panic_message(err: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = err.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = err.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown".to_string()
+    }
+}
+
+// Run the checks of every reachable state against made up results and
+// internal states. Checks log as they match and panics are caught, callers
+// may want to quiet both
+pub(crate) fn probe(states: &[BuiltState], target: u32, vars: &Variables) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let reachable = reach(states, vec![0], false);
+    let probes = ProbeState::all();
+    let name = |tag: usize| find(states, tag).map_or(tag.to_string(), |s| s.name.clone());
+
+    let mut found = false;
+    let mut encounter = false;
+    for state in states.iter().filter(|s| reachable.contains(&s.tag)) {
+        let mut matched = vec![false; state.checks.len()];
+        let mut panicked: Vec<Option<String>> = vec![None; state.checks.len()];
+        let mut multiple = false;
+
+        for combination in result_combinations(state.inputs, target) {
+            let results: Vec<ProcessingResult> = state
+                .inputs
+                .iter()
+                .zip(combination)
+                .map(|(process, (met, species, shiny))| ProcessingResult {
+                    process: process.clone(),
+                    met,
+                    species,
+                    shiny,
+                })
+                .collect();
+
+            for probe in &probes {
+                let mut matches = 0;
+                for (i, (_, check)) in state.checks.iter().enumerate() {
+                    if panicked[i].is_some() {
+                        continue;
+                    }
+                    let mut int = probe.state(vars);
+                    match panic::catch_unwind(AssertUnwindSafe(|| check(&results, &mut int))) {
+                        Ok(Some(res)) => {
+                            matched[i] = true;
+                            matches += 1;
+                            found |= matches!(
+                                res.transition,
+                                Some(RequestTransition {
+                                    transition: Transition::FoundTarget,
+                                    ..
+                                })
+                            );
+                            encounter |= res.incr_encounters;
+                        }
+                        Ok(None) => {}
+                        Err(err) => panicked[i] = Some(panic_message(err)),
+                    }
+                }
+                multiple |= matches > 1;
+            }
+        }
+
+        for (i, (next, _)) in state.checks.iter().enumerate() {
+            if let Some(message) = panicked[i].take() {
+                issues.push(ValidationIssue::Panicked {
+                    state: state.name.clone(),
+                    next: name(*next),
+                    message,
+                });
+            } else if !matched[i] {
+                issues.push(ValidationIssue::NeverMatches {
+                    state: state.name.clone(),
+                    next: name(*next),
+                });
+            }
+        }
+        if multiple {
+            issues.push(ValidationIssue::MultipleMatches(state.name.clone()));
+        }
+    }
+
+    if !found {
+        issues.push(ValidationIssue::NoFoundTarget);
+    }
+    if !encounter {
+        issues.push(ValidationIssue::NoEncounter);
+    }
+
+    issues
+}
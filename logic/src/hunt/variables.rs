@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::fsm::shift_start;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compare {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Compare {
+    pub fn test(&self, a: i64, b: i64) -> bool {
+        match self {
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+            Self::Lt => a < b,
+            Self::Le => a <= b,
+            Self::Gt => a > b,
+            Self::Ge => a >= b,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VariableKind {
+    Int,
+    Bool,
+    Timer,
+}
+
+impl VariableKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Int => "int",
+            Self::Bool => "bool",
+            Self::Timer => "timer",
+        }
+    }
+}
+
+// Current value of a variable, for the web page
+#[derive(Clone, Debug, Serialize)]
+pub struct VariableValue {
+    pub name: String,
+    pub kind: &'static str,
+    pub value: String,
+}
+
+// Named variables declared by a hunt. A hunt whose states use one that wasn't
+// declared fails to build
+#[derive(Clone, Debug, Default)]
+pub struct Variables {
+    ints: BTreeMap<String, i64>,
+    bools: BTreeMap<String, bool>,
+    timers: BTreeMap<String, SystemTime>,
+    // Counts every change, so a copy can tell it is out of date
+    changes: u64,
+}
+
+impl Variables {
+    pub fn declare_int(&mut self, name: &str, initial: i64) -> &mut Self {
+        self.ints.insert(name.to_string(), initial);
+        self
+    }
+
+    pub fn declare_bool(&mut self, name: &str, initial: bool) -> &mut Self {
+        self.bools.insert(name.to_string(), initial);
+        self
+    }
+
+    // Timers start when the hunt is built
+    pub fn declare_timer(&mut self, name: &str) -> &mut Self {
+        self.timers.insert(name.to_string(), SystemTime::now());
+        self
+    }
+
+    pub fn declared(&self, kind: VariableKind, name: &str) -> bool {
+        match kind {
+            VariableKind::Int => self.ints.contains_key(name),
+            VariableKind::Bool => self.bools.contains_key(name),
+            VariableKind::Timer => self.timers.contains_key(name),
+        }
+    }
+
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    pub fn int(&self, name: &str) -> i64 {
+        *self
+            .ints
+            .get(name)
+            .unwrap_or_else(|| panic!("Hunt variable {} isn't a declared int", name))
+    }
+
+    pub fn set_int(&mut self, name: &str, value: i64) {
+        self.changes += 1;
+        *self
+            .ints
+            .get_mut(name)
+            .unwrap_or_else(|| panic!("Hunt variable {} isn't a declared int", name)) = value;
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        *self
+            .bools
+            .get(name)
+            .unwrap_or_else(|| panic!("Hunt variable {} isn't a declared bool", name))
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.changes += 1;
+        *self
+            .bools
+            .get_mut(name)
+            .unwrap_or_else(|| panic!("Hunt variable {} isn't a declared bool", name)) = value;
+    }
+
+    pub fn elapsed(&self, name: &str) -> Duration {
+        self.timers
+            .get(name)
+            .unwrap_or_else(|| panic!("Hunt variable {} isn't a declared timer", name))
+            .elapsed()
+            .unwrap_or_default()
+    }
+
+    pub fn start_timer(&mut self, name: &str) {
+        self.changes += 1;
+        *self
+            .timers
+            .get_mut(name)
+            .unwrap_or_else(|| panic!("Hunt variable {} isn't a declared timer", name)) =
+            SystemTime::now();
+    }
+
+    // Move every timer's start later, so time paused isn't counted
+    pub(crate) fn shift_timers(&mut self, duration: Duration) {
+        self.changes += 1;
+        for start in self.timers.values_mut() {
+            shift_start(start, duration);
+        }
+    }
+
+    pub fn values(&self) -> Vec<VariableValue> {
+        let mut values = Vec::new();
+        for (name, value) in &self.ints {
+            values.push(VariableValue {
+                name: name.clone(),
+                kind: VariableKind::Int.name(),
+                value: value.to_string(),
+            });
+        }
+        for (name, value) in &self.bools {
+            values.push(VariableValue {
+                name: name.clone(),
+                kind: VariableKind::Bool.name(),
+                value: value.to_string(),
+            });
+        }
+        for name in self.timers.keys() {
+            values.push(VariableValue {
+                name: name.clone(),
+                kind: VariableKind::Timer.name(),
+                value: format!("{:.1}s", self.elapsed(name).as_secs_f64()),
+            });
+        }
+        values
+    }
+
+    // Same variables with every int, bool and timer set to the given values
+    pub(crate) fn probe(&self, int: i64, flag: bool, elapsed: Duration) -> Self {
+        Self {
+            ints: self.ints.keys().map(|k| (k.clone(), int)).collect(),
+            bools: self.bools.keys().map(|k| (k.clone(), flag)).collect(),
+            timers: self
+                .timers
+                .keys()
+                .map(|k| (k.clone(), SystemTime::now() - elapsed))
+                .collect(),
+            changes: 0,
+        }
+    }
+}
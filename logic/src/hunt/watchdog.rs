@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::hunt::HuntStateOutput;
+
+// Time a hunt can stay in one state before it's assumed to be stuck, generous
+// enough for the longest soft reset delays
+const DEFAULT_MAX_DWELL: Duration = Duration::from_secs(10 * 60);
+
+// Detects a hunt that has been stuck in a single state, e.g. waiting for a
+// detection that will never happen after a missed button press
+#[derive(Debug)]
+pub struct Watchdog {
+    max_dwell: Duration,
+    // Buttons to press to get back to a known point before restarting the
+    // state machine, normally a soft reset
+    recovery: Vec<HuntStateOutput>,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self {
+            max_dwell: DEFAULT_MAX_DWELL,
+            recovery: Vec::new(),
+        }
+    }
+
+    pub fn set_max_dwell(&mut self, max_dwell: Duration) {
+        self.max_dwell = max_dwell;
+    }
+
+    pub fn set_recovery(&mut self, recovery: Vec<HuntStateOutput>) {
+        self.recovery = recovery;
+    }
+
+    pub fn recovery(&self) -> &Vec<HuntStateOutput> {
+        &self.recovery
+    }
+
+    pub fn max_dwell(&self) -> Duration {
+        self.max_dwell
+    }
+
+    pub fn expired(&self, dwell: Duration) -> bool {
+        dwell > self.max_dwell
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -4,6 +4,9 @@ pub mod control;
 pub mod displays;
 pub mod fsm;
 pub mod hunt;
+pub mod notify;
+#[cfg(feature = "virtual_console")]
+pub mod virtual_console;
 pub mod vision;
 
 #[cfg(all(target_arch = "aarch64", target_os = "linux"))]
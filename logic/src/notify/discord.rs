@@ -0,0 +1,54 @@
+use serde_json::json;
+
+use super::{Notification, NotificationKind, Notifier, NotifyFuture, check_response};
+
+// Discord style webhook, a message with an embed and the sprite as a file
+pub struct DiscordNotifier {
+    url: String,
+}
+
+impl DiscordNotifier {
+    pub fn new(url: String) -> Self {
+        Self { url }
+    }
+
+    fn colour(kind: NotificationKind) -> u32 {
+        match kind {
+            NotificationKind::Status => 0x277c5e,
+            NotificationKind::Alert => 0xd48735,
+            NotificationKind::Error => 0x7a151b,
+        }
+    }
+}
+
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &'static str {
+        "Discord"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let attachment = notification.read_attachment().await;
+            let mut embed = json!({
+                "title": notification.title,
+                "description": notification.message,
+                "color": Self::colour(notification.kind),
+            });
+            if let Some((name, _)) = &attachment {
+                embed["thumbnail"] = json!({ "url": format!("attachment://{}", name) });
+            }
+            let payload = json!({ "embeds": [embed] });
+            let mut content =
+                reqwest::multipart::Form::new().text("payload_json", payload.to_string());
+            if let Some((name, data)) = attachment {
+                let part = reqwest::multipart::Part::bytes(data)
+                    .file_name(name)
+                    .mime_str("image/png")
+                    .map_err(|e| e.to_string())?;
+                content = content.part("files[0]", part);
+            }
+            let client = reqwest::Client::new();
+            check_response(client.post(&self.url).multipart(content).send().await).await
+        })
+    }
+}
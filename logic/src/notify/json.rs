@@ -0,0 +1,48 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Serialize;
+
+use super::{Notification, NotificationKind, Notifier, NotifyFuture, Priority, check_response};
+
+// Generic webhook, POSTs the notification as a JSON object with the
+// attachment base64 encoded
+pub struct JsonNotifier {
+    url: String,
+}
+
+#[derive(Serialize)]
+struct JsonPayload<'a> {
+    kind: NotificationKind,
+    title: &'a str,
+    message: &'a str,
+    priority: Priority,
+    attachment_name: Option<String>,
+    attachment: Option<String>,
+}
+
+impl JsonNotifier {
+    pub fn new(url: String) -> Self {
+        Self { url }
+    }
+}
+
+impl Notifier for JsonNotifier {
+    fn name(&self) -> &'static str {
+        "JSON webhook"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let attachment = notification.read_attachment().await;
+            let payload = JsonPayload {
+                kind: notification.kind,
+                title: &notification.title,
+                message: &notification.message,
+                priority: notification.priority,
+                attachment_name: attachment.as_ref().map(|a| a.0.clone()),
+                attachment: attachment.map(|a| STANDARD.encode(a.1)),
+            };
+            let client = reqwest::Client::new();
+            check_response(client.post(&self.url).json(&payload).send().await).await
+        })
+    }
+}
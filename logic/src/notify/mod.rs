@@ -0,0 +1,188 @@
+use std::{future::Future, pin::Pin};
+
+use serde::{Deserialize, Serialize};
+
+mod discord;
+mod json;
+mod ntfy;
+mod pushover;
+mod rules;
+mod smtp;
+
+pub use discord::DiscordNotifier;
+pub use json::JsonNotifier;
+pub use ntfy::NtfyNotifier;
+pub use pushover::PushoverNotifier;
+pub use rules::{NotificationRules, NotifyEvent, QuietHours, RuleEngine};
+pub use smtp::SmtpNotifier;
+
+pub type NotifyFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum NotificationKind {
+    Status,
+    Alert,
+    Error,
+}
+
+// A single event to be sent by every configured notifier
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub title: String,
+    pub message: String,
+    pub priority: Priority,
+    // Path to a PNG to attach, normally the sprite of the target
+    pub attachment: Option<String>,
+}
+
+impl Notification {
+    pub fn new(kind: NotificationKind, title: String, message: String) -> Self {
+        let priority = match kind {
+            NotificationKind::Status => Priority::Normal,
+            NotificationKind::Alert | NotificationKind::Error => Priority::High,
+        };
+        Self {
+            kind,
+            title,
+            message,
+            priority,
+            attachment: None,
+        }
+    }
+
+    pub fn with_attachment(mut self, path: String) -> Self {
+        self.attachment = Some(path);
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    // A missing or unreadable attachment is logged and left off, so the
+    // notification is still sent
+    pub(crate) async fn read_attachment(&self) -> Option<(String, Vec<u8>)> {
+        let path = self.attachment.as_ref()?;
+        match tokio::fs::read(path).await {
+            Ok(data) => {
+                let name = std::path::Path::new(path)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or("sprite.png".to_string());
+                Some((name, data))
+            }
+            Err(e) => {
+                log::warn!("Couldn't read attachment {}: {}", path, e);
+                None
+            }
+        }
+    }
+}
+
+pub trait Notifier: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn send<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a>;
+}
+
+// Notifier backends as selected in user_config.json, e.g.
+// { "type": "Ntfy", "url": "https://ntfy.sh", "topic": "shaoooh" }
+#[derive(Clone, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum NotifierConfig {
+    Pushover {
+        api_key: String,
+        user_id: String,
+        url: Option<String>,
+    },
+    Json {
+        url: String,
+    },
+    Discord {
+        url: String,
+    },
+    Ntfy {
+        url: String,
+        topic: String,
+        token: Option<String>,
+    },
+    Smtp {
+        host: String,
+        port: Option<u16>,
+        tls: Option<bool>,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: String,
+    },
+}
+
+// Entry in the notifiers list, can be disabled without removing its settings
+#[derive(Clone, Deserialize, Debug)]
+pub struct NotifierEntry {
+    #[serde(default = "NotifierEntry::default_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub config: NotifierConfig,
+}
+
+impl NotifierEntry {
+    fn default_enabled() -> bool {
+        true
+    }
+}
+
+impl NotifierConfig {
+    pub fn build(&self) -> Result<Box<dyn Notifier>, String> {
+        Ok(match self {
+            Self::Pushover {
+                api_key,
+                user_id,
+                url,
+            } => Box::new(PushoverNotifier::new(
+                api_key.clone(),
+                user_id.clone(),
+                url.clone(),
+            )),
+            Self::Json { url } => Box::new(JsonNotifier::new(url.clone())),
+            Self::Discord { url } => Box::new(DiscordNotifier::new(url.clone())),
+            Self::Ntfy { url, topic, token } => {
+                Box::new(NtfyNotifier::new(url.clone(), topic.clone(), token.clone()))
+            }
+            Self::Smtp {
+                host,
+                port,
+                tls,
+                username,
+                password,
+                from,
+                to,
+            } => Box::new(SmtpNotifier::new(
+                host,
+                *port,
+                tls.unwrap_or(true),
+                username.clone().zip(password.clone()),
+                from,
+                to,
+            )?),
+        })
+    }
+}
+
+pub(crate) async fn check_response(
+    res: Result<reqwest::Response, reqwest::Error>,
+) -> Result<(), String> {
+    match res {
+        Ok(r) if r.status().is_success() => Ok(()),
+        Ok(r) => Err(format!("Got response {}", r.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,48 @@
+use super::{Notification, Notifier, NotifyFuture, Priority, check_response};
+
+// ntfy style topic server, the attachment is sent as the body and the text
+// in headers
+pub struct NtfyNotifier {
+    url: String,
+    topic: String,
+    token: Option<String>,
+}
+
+impl NtfyNotifier {
+    pub fn new(url: String, topic: String, token: Option<String>) -> Self {
+        Self { url, topic, token }
+    }
+}
+
+impl Notifier for NtfyNotifier {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let url = format!("{}/{}", self.url.trim_end_matches('/'), self.topic);
+            let priority = match notification.priority {
+                Priority::Low => "2",
+                Priority::Normal => "3",
+                Priority::High => "5",
+            };
+            let client = reqwest::Client::new();
+            let mut req = client
+                .put(url)
+                .header("Title", &notification.title)
+                .header("Priority", priority);
+            if let Some(token) = &self.token {
+                req = req.bearer_auth(token);
+            }
+            req = match notification.read_attachment().await {
+                Some((name, data)) => req
+                    .header("Message", &notification.message)
+                    .header("Filename", name)
+                    .body(data),
+                None => req.body(notification.message.clone()),
+            };
+            check_response(req.send().await).await
+        })
+    }
+}
@@ -0,0 +1,52 @@
+use super::{Notification, Notifier, NotifyFuture, Priority, check_response};
+
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+
+pub struct PushoverNotifier {
+    api_key: String,
+    user_id: String,
+    url: String,
+}
+
+impl PushoverNotifier {
+    pub fn new(api_key: String, user_id: String, url: Option<String>) -> Self {
+        Self {
+            api_key,
+            user_id,
+            url: url.unwrap_or(PUSHOVER_URL.to_string()),
+        }
+    }
+}
+
+impl Notifier for PushoverNotifier {
+    fn name(&self) -> &'static str {
+        "Pushover"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let mut content = reqwest::multipart::Form::new()
+                .text("message", notification.message.clone())
+                .text("token", self.api_key.clone())
+                .text("user", self.user_id.clone())
+                .text("title", notification.title.clone());
+            // Pushover priorities go from -2 to 2
+            content = match notification.priority {
+                Priority::Low => content.text("priority", "-1"),
+                Priority::High => content.text("priority", "1"),
+                Priority::Normal => content,
+            };
+            if let Some((name, data)) = notification.read_attachment().await {
+                let part = reqwest::multipart::Part::bytes(data)
+                    .file_name(name)
+                    .mime_str("image/png")
+                    .map_err(|e| e.to_string())?;
+                content = content
+                    .text("attachment_type", "image/png")
+                    .part("attachment", part);
+            }
+            let client = reqwest::Client::new();
+            check_response(client.post(&self.url).multipart(content).send().await).await
+        })
+    }
+}
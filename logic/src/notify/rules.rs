@@ -0,0 +1,218 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use super::Priority;
+use crate::app::{AppState, HuntState};
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+pub enum NotifyEvent {
+    Milestone,
+    Found,
+    Phase,
+    FalseDetect,
+    RateDrop,
+    Error,
+}
+
+#[derive(Clone, Deserialize, Debug)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+    // Events at or above this priority are still sent during quiet hours
+    #[serde(default = "QuietHours::default_min_priority")]
+    min_priority: Priority,
+}
+
+impl QuietHours {
+    fn default_min_priority() -> Priority {
+        Priority::High
+    }
+
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            time >= self.start && time < self.end
+        } else {
+            // Wraps past midnight
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+// Rules for when to notify, read from the "rules" section of user_config.json.
+// The defaults match the original behaviour of notifying every 64 encounters
+// and whenever something is found.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct NotificationRules {
+    // Notify every N encounters
+    milestone: Option<u64>,
+    found: bool,
+    phase: bool,
+    // Notify after N false detections in a row
+    false_detect: Option<u32>,
+    // Notify when the encounter rate drops below this many per hour
+    min_rate: Option<f64>,
+    rate_window_mins: u64,
+    quiet_hours: Option<QuietHours>,
+    priorities: HashMap<NotifyEvent, Priority>,
+}
+
+impl Default for NotificationRules {
+    fn default() -> Self {
+        Self {
+            milestone: Some(64),
+            found: true,
+            phase: false,
+            false_detect: None,
+            min_rate: None,
+            rate_window_mins: 30,
+            quiet_hours: None,
+            priorities: HashMap::new(),
+        }
+    }
+}
+
+impl NotificationRules {
+    pub fn priority(&self, event: NotifyEvent) -> Priority {
+        match self.priorities.get(&event) {
+            Some(p) => *p,
+            None => match event {
+                NotifyEvent::Found | NotifyEvent::Error => Priority::High,
+                _ => Priority::Normal,
+            },
+        }
+    }
+
+    // Whether a notification of the given priority can be sent now
+    pub fn allowed(&self, priority: Priority) -> bool {
+        match &self.quiet_hours {
+            Some(q) => !q.contains(Local::now().time()) || priority >= q.min_priority,
+            None => true,
+        }
+    }
+}
+
+// Tracks the application state between updates to work out which events
+// have happened, as the watch channel only provides snapshots
+pub struct RuleEngine {
+    rules: NotificationRules,
+    last: Option<AppState>,
+    false_detects: u32,
+    samples: VecDeque<(Instant, u64)>,
+    rate_alerted: bool,
+}
+
+impl RuleEngine {
+    pub fn new(rules: NotificationRules) -> Self {
+        Self {
+            rules,
+            last: None,
+            false_detects: 0,
+            samples: VecDeque::new(),
+            rate_alerted: false,
+        }
+    }
+
+    pub fn rules(&self) -> &NotificationRules {
+        &self.rules
+    }
+
+    fn same_hunt(a: &AppState, b: &AppState) -> bool {
+        match (&a.arg, &b.arg) {
+            (Some(x), Some(y)) => x.name == y.name,
+            _ => false,
+        }
+    }
+
+    // Encounters per hour over the window, once a full window is available
+    fn rate(&mut self, state: &AppState) -> Option<f64> {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.rules.rate_window_mins * 60);
+        if state.state != HuntState::Hunt {
+            self.samples.clear();
+            return None;
+        }
+        // Encounters reset on a phase, so restart the window
+        if self.samples.back().is_some_and(|s| s.1 > state.encounters) {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, state.encounters));
+        while self.samples.len() > 2 && now - self.samples[1].0 >= window {
+            self.samples.pop_front();
+        }
+        let (start, count) = *self.samples.front()?;
+        let elapsed = now - start;
+        if elapsed < window {
+            return None;
+        }
+        Some((state.encounters - count) as f64 / elapsed.as_secs_f64() * 3600.0)
+    }
+
+    pub fn events(&mut self, state: &AppState) -> Vec<NotifyEvent> {
+        let mut events = Vec::new();
+        let last = self.last.replace(state.clone());
+
+        if let Some(last) = last.filter(|l| Self::same_hunt(l, state)) {
+            let found =
+                |s: &HuntState| *s == HuntState::FoundNonTarget || *s == HuntState::FoundTarget;
+            if self.rules.found && found(&state.state) && !found(&last.state) {
+                events.push(NotifyEvent::Found);
+            }
+            if state.phases.len() > last.phases.len() {
+                self.false_detects = 0;
+                if self.rules.phase {
+                    events.push(NotifyEvent::Phase);
+                }
+            } else if found(&last.state) && state.state == HuntState::Hunt {
+                // Back to hunting without a phase being recorded
+                self.false_detects += 1;
+                if self
+                    .rules
+                    .false_detect
+                    .is_some_and(|n| self.false_detects == n)
+                {
+                    events.push(NotifyEvent::FalseDetect);
+                }
+            }
+            if let Some(m) = self.rules.milestone
+                && m != 0
+                && state.encounters / m > last.encounters / m
+                && state.encounters > last.encounters
+            {
+                events.push(NotifyEvent::Milestone);
+            }
+        } else {
+            self.false_detects = 0;
+            self.samples.clear();
+            self.rate_alerted = false;
+        }
+
+        if let Some(min_rate) = self.rules.min_rate {
+            match self.rate(state) {
+                Some(r) if r < min_rate && !self.rate_alerted => {
+                    self.rate_alerted = true;
+                    events.push(NotifyEvent::RateDrop);
+                }
+                Some(r) if r >= min_rate => self.rate_alerted = false,
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    pub fn current_rate(&self) -> Option<f64> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        let elapsed = (last.0 - first.0).as_secs_f64();
+        if elapsed > 0.0 {
+            Some((last.1 - first.1) as f64 / elapsed * 3600.0)
+        } else {
+            None
+        }
+    }
+}
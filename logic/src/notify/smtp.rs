@@ -0,0 +1,73 @@
+use lettre::{
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{Attachment, Mailbox, MultiPart, SinglePart, header::ContentType},
+    transport::smtp::authentication::Credentials,
+};
+
+use super::{Notification, Notifier, NotifyFuture};
+
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Mailbox,
+}
+
+impl SmtpNotifier {
+    // Without TLS the connection is unencrypted, only intended for a local relay
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        tls: bool,
+        credentials: Option<(String, String)>,
+        from: &str,
+        to: &str,
+    ) -> Result<Self, String> {
+        let mut builder = if tls {
+            AsyncSmtpTransport::<Tokio1Executor>::relay(host).map_err(|e| e.to_string())?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some((user, pass)) = credentials {
+            builder = builder.credentials(Credentials::new(user, pass));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from: from
+                .parse()
+                .map_err(|e| format!("Bad from address: {}", e))?,
+            to: to.parse().map_err(|e| format!("Bad to address: {}", e))?,
+        })
+    }
+}
+
+impl Notifier for SmtpNotifier {
+    fn name(&self) -> &'static str {
+        "SMTP"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let text = SinglePart::plain(notification.message.clone());
+            let body = match notification.read_attachment().await {
+                Some((name, data)) => MultiPart::mixed().singlepart(text).singlepart(
+                    Attachment::new(name).body(data, ContentType::parse("image/png").unwrap()),
+                ),
+                None => MultiPart::mixed().singlepart(text),
+            };
+            let email = Message::builder()
+                .from(self.from.clone())
+                .to(self.to.clone())
+                .subject(&notification.title)
+                .multipart(body)
+                .map_err(|e| e.to_string())?;
+            self.transport
+                .send(email)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use super::Timeline;
+use crate::control::Button;
+
+const TOUCH_SCREEN_WIDTH: u32 = 320;
+const TOUCH_SCREEN_HEIGHT: u32 = 240;
+
+// Pad bits, cleared when pressed
+const PAD: [(u32, Button); 12] = [
+    (0, Button::A),
+    (1, Button::B),
+    (2, Button::Select),
+    (3, Button::Start),
+    (4, Button::Right),
+    (5, Button::Left),
+    (6, Button::Up),
+    (7, Button::Down),
+    (8, Button::R),
+    (9, Button::L),
+    (10, Button::X),
+    (11, Button::Y),
+];
+
+// Listens for the packets the InputRedirection service on the 3DS receives
+// and records the buttons they press
+pub struct VirtualInputRedirection {
+    addr: SocketAddr,
+    timeline: Timeline,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl VirtualInputRedirection {
+    pub fn start(bind: &str) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let addr = socket.local_addr()?;
+        log::info!("Virtual InputRedirection on {}", addr);
+
+        let timeline = Timeline::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let timeline_clone = timeline.clone();
+        let stop_clone = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let mut held: Vec<Button> = Vec::new();
+            let mut buf = [0u8; 64];
+            while !stop_clone.load(Ordering::Acquire) {
+                let n = match socket.recv(&mut buf) {
+                    Ok(n) => n,
+                    Err(e)
+                        if e.kind() == std::io::ErrorKind::WouldBlock
+                            || e.kind() == std::io::ErrorKind::TimedOut =>
+                    {
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("Virtual InputRedirection receive error: {}", e);
+                        break;
+                    }
+                };
+                let Some(pressed) = Self::decode(&buf[..n]) else {
+                    log::warn!("Invalid InputRedirection packet of {} bytes", n);
+                    continue;
+                };
+                for b in held.iter().filter(|b| !pressed.contains(b)) {
+                    timeline_clone.record(b.clone(), false);
+                }
+                for b in pressed.iter().filter(|b| !held.contains(b)) {
+                    timeline_clone.record(b.clone(), true);
+                }
+                held = pressed;
+            }
+        });
+
+        Ok(Self {
+            addr,
+            timeline,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    fn word(buf: &[u8], idx: usize) -> u32 {
+        u32::from_le_bytes(buf[idx * 4..idx * 4 + 4].try_into().unwrap())
+    }
+
+    // Inverse of the scaling to the 12-bit range used for the touch screen
+    fn unscale(val: u32, size: u32) -> u16 {
+        (val * size).div_ceil(0xfff) as u16
+    }
+
+    // Buttons held in a 20 byte InputRedirection packet
+    pub fn decode(buf: &[u8]) -> Option<Vec<Button>> {
+        if buf.len() != 20 {
+            return None;
+        }
+        let pad = Self::word(buf, 0);
+        let touch = Self::word(buf, 1);
+        let circle = Self::word(buf, 2);
+        let cpp = Self::word(buf, 3);
+        let interface = Self::word(buf, 4);
+
+        let mut buttons: Vec<Button> = PAD
+            .iter()
+            .filter(|(bit, _)| pad & (1 << bit) == 0)
+            .map(|(_, b)| b.clone())
+            .collect();
+        if touch & (1 << 24) != 0 {
+            buttons.push(Button::Touch(
+                Self::unscale(touch & 0xfff, TOUCH_SCREEN_WIDTH),
+                Self::unscale((touch >> 12) & 0xfff, TOUCH_SCREEN_HEIGHT),
+            ));
+        }
+        if circle != 0x7ff7ff {
+            buttons.push(Button::Circle(
+                ((circle & 0xfff) / 16) as u8,
+                (((circle >> 12) & 0xfff) / 16) as u8,
+            ));
+        }
+        if cpp & (0x4 << 8) != 0 {
+            buttons.push(Button::ZL);
+        }
+        if cpp & (0x2 << 8) != 0 {
+            buttons.push(Button::ZR);
+        }
+        if interface & 0x1 != 0 {
+            buttons.push(Button::Home);
+        }
+        Some(buttons)
+    }
+}
+
+impl Drop for VirtualInputRedirection {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Stand-ins for the console end of the control and vision backends, so the
+// encoding and timing of button presses, and the handling of streamed frames,
+// can be checked without hardware. Used by the virtual_console binary.
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::control::Button;
+
+mod input_redirection;
+mod ntr;
+mod serial;
+
+pub use input_redirection::VirtualInputRedirection;
+pub use ntr::{NTRFrames, NTROptions, NTRStats, VirtualNTR};
+pub use serial::{SerialOptions, VirtualSerial};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimelineEvent {
+    // Time since the timeline was created or last cleared
+    pub at: Duration,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+// Record of button changes seen by a virtual console
+#[derive(Clone)]
+pub struct Timeline {
+    start: Arc<Mutex<Instant>>,
+    events: Arc<Mutex<Vec<TimelineEvent>>>,
+}
+
+impl Default for Timeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self {
+            start: Arc::new(Mutex::new(Instant::now())),
+            events: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub(crate) fn record(&self, button: Button, pressed: bool) {
+        let at = self.start.lock().unwrap().elapsed();
+        log::info!(
+            "{:>8.3}s {:?} {}",
+            at.as_secs_f64(),
+            button,
+            if pressed { "pressed" } else { "released" }
+        );
+        self.events.lock().unwrap().push(TimelineEvent {
+            at,
+            button,
+            pressed,
+        });
+    }
+
+    pub fn events(&self) -> Vec<TimelineEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        *self.start.lock().unwrap() = Instant::now();
+        self.events.lock().unwrap().clear();
+    }
+
+    // Wait until at least the given number of events have been recorded
+    pub fn wait_for(&self, count: usize, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if self.events.lock().unwrap().len() >= count {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    // How long each button was held for, pairing each press with the next
+    // release of the same button
+    pub fn holds(&self) -> Vec<(Button, Duration)> {
+        let events = self.events();
+        let mut holds = Vec::new();
+        for (i, e) in events.iter().enumerate() {
+            if !e.pressed {
+                continue;
+            }
+            if let Some(r) = events[i + 1..]
+                .iter()
+                .find(|r| !r.pressed && r.button == e.button)
+            {
+                holds.push((e.button.clone(), r.at - e.at));
+            }
+        }
+        holds
+    }
+}
@@ -0,0 +1,486 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::vision::{NTRPacket, NTRStreamSettings};
+
+// Image data in each UDP packet, after the 4 byte header
+const FRAGMENT_SIZE: usize = 1444;
+// Sequence numbers are a single byte
+const MAX_FRAGMENTS: usize = 256;
+// Process id reported for the game
+const GAME_PID: u32 = 0x29;
+
+#[derive(Clone, Debug)]
+pub struct NTROptions {
+    pub bind: IpAddr,
+    // Port for the control connection, frames are also sent from this port
+    pub port: u16,
+    // Port the client receives frames on
+    pub client_port: u16,
+    // Frames per second for each screen
+    pub fps: u32,
+    // Chance of dropping each fragment
+    pub loss: f64,
+    // Chance of swapping each fragment with the one after it
+    pub reorder: f64,
+    // Seed for loss and reordering, so a run can be repeated
+    pub seed: u64,
+    // Name of the game process in the process list
+    pub process: String,
+}
+
+impl Default for NTROptions {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8000,
+            client_port: 8001,
+            fps: 30,
+            loss: 0.0,
+            reorder: 0.0,
+            seed: 0,
+            process: String::from("niji_loc"),
+        }
+    }
+}
+
+// JPEG images to stream for each screen, sent in a loop
+#[derive(Clone, Debug, Default)]
+pub struct NTRFrames {
+    pub top: Vec<Vec<u8>>,
+    pub bottom: Vec<Vec<u8>>,
+}
+
+impl NTRFrames {
+    // Load images from the 'top' and 'bottom' subdirectories, in name order.
+    // Images are sent as they are, so should be rotated clockwise like the
+    // frames from NTR.
+    pub fn from_dir(path: &str) -> std::io::Result<Self> {
+        let frames = Self {
+            top: Self::load(&Path::new(path).join("top"))?,
+            bottom: Self::load(&Path::new(path).join("bottom"))?,
+        };
+        if frames.top.is_empty() && frames.bottom.is_empty() {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("No images in {}/top or {}/bottom", path, path),
+            ))
+        } else {
+            log::info!(
+                "Loaded {} top and {} bottom frames",
+                frames.top.len(),
+                frames.bottom.len()
+            );
+            Ok(frames)
+        }
+    }
+
+    fn load(dir: &Path) -> std::io::Result<Vec<Vec<u8>>> {
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut paths: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension().is_some_and(|e| {
+                    e.eq_ignore_ascii_case("jpg") || e.eq_ignore_ascii_case("jpeg")
+                })
+            })
+            .collect();
+        paths.sort();
+        paths.iter().map(std::fs::read).collect()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NTRStats {
+    pub inits: u64,
+    pub heartbeats: u64,
+    pub top_frames: u64,
+    pub bottom_frames: u64,
+    pub fragments: u64,
+    pub lost: u64,
+    pub reordered: u64,
+    pub memory_reads: u64,
+    pub memory_writes: u64,
+    // Requested by the last init packet
+    pub settings: Option<NTRStreamSettings>,
+}
+
+struct Shared {
+    options: NTROptions,
+    stats: Mutex<NTRStats>,
+    // Where to send frames, set by the init packet
+    client: Mutex<Option<SocketAddr>>,
+    streaming: AtomicBool,
+    stop: AtomicBool,
+    // Game memory, by address
+    memory: Mutex<HashMap<u32, u8>>,
+}
+
+// Emulates the remote play part of NTR on a 3DS. Accepts the init packet
+// and heartbeats over TCP, then streams the frames as JPEG fragments over
+// UDP in the format BishaanVisionSocket expects. The debugger commands work
+// on a single game process, whose memory is empty until set.
+pub struct VirtualNTR {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl VirtualNTR {
+    pub fn start(options: NTROptions, frames: NTRFrames) -> std::io::Result<Self> {
+        if !(0.0..=1.0).contains(&options.loss) || !(0.0..=1.0).contains(&options.reorder) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Loss and reorder must be between 0 and 1",
+            ));
+        }
+        if frames
+            .top
+            .iter()
+            .chain(frames.bottom.iter())
+            .any(|f| f.len() > FRAGMENT_SIZE * MAX_FRAGMENTS)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Frame is too large to send",
+            ));
+        }
+        if options.fps == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "FPS must be greater than 0",
+            ));
+        }
+
+        let listener = TcpListener::bind((options.bind, options.port))?;
+        listener.set_nonblocking(true)?;
+        let img_socket = UdpSocket::bind((options.bind, options.port))?;
+        let addr = listener.local_addr()?;
+        log::info!("Virtual NTR on {}", addr);
+
+        let shared = Arc::new(Shared {
+            options,
+            stats: Mutex::new(NTRStats::default()),
+            client: Mutex::new(None),
+            streaming: AtomicBool::new(true),
+            stop: AtomicBool::new(false),
+            memory: Mutex::new(HashMap::new()),
+        });
+
+        let listen_shared = shared.clone();
+        let stream_shared = shared.clone();
+        let handles = vec![
+            std::thread::spawn(move || Self::listen(listener, listen_shared)),
+            std::thread::spawn(move || Self::stream(img_socket, frames, stream_shared)),
+        ];
+
+        Ok(Self {
+            addr,
+            shared,
+            handles,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn stats(&self) -> NTRStats {
+        self.shared.stats.lock().unwrap().clone()
+    }
+
+    // Stop sending frames without closing any connections, like a console
+    // that has gone to sleep
+    pub fn set_streaming(&self, streaming: bool) {
+        log::info!(
+            "Virtual NTR {}",
+            if streaming { "streaming" } else { "paused" }
+        );
+        self.shared.streaming.store(streaming, Ordering::Release);
+    }
+
+    pub fn set_memory(&self, address: u32, data: &[u8]) {
+        Self::write(&self.shared, address, data);
+    }
+
+    // None if any of the memory hasn't been set
+    pub fn memory(&self, address: u32, length: u32) -> Option<Vec<u8>> {
+        Self::read(&self.shared, address, length)
+    }
+
+    fn read(shared: &Shared, address: u32, length: u32) -> Option<Vec<u8>> {
+        let memory = shared.memory.lock().unwrap();
+        (address..address.checked_add(length)?)
+            .map(|a| memory.get(&a).copied())
+            .collect()
+    }
+
+    fn write(shared: &Shared, address: u32, data: &[u8]) {
+        let mut memory = shared.memory.lock().unwrap();
+        for (a, b) in (address..).zip(data) {
+            memory.insert(a, *b);
+        }
+    }
+
+    fn listen(listener: TcpListener, shared: Arc<Shared>) {
+        let mut connections = Vec::new();
+        while !shared.stop.load(Ordering::Acquire) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    log::info!("Virtual NTR connection from {}", peer);
+                    let shared = shared.clone();
+                    connections.retain(|c: &JoinHandle<()>| !c.is_finished());
+                    connections.push(std::thread::spawn(move || {
+                        Self::connection(stream, peer, shared)
+                    }));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => {
+                    log::error!("Virtual NTR accept error: {}", e);
+                    break;
+                }
+            }
+        }
+        for c in connections {
+            let _ = c.join();
+        }
+    }
+
+    fn connection(mut stream: TcpStream, peer: SocketAddr, shared: Arc<Shared>) {
+        if let Err(e) = stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(Duration::from_millis(100))))
+        {
+            log::error!("Virtual NTR failed to configure connection: {}", e);
+            return;
+        }
+        let mut buf = Vec::new();
+        let mut read_buf = [0u8; 256];
+        // Debug output, sent in reply to heartbeats
+        let mut output = String::new();
+        while !shared.stop.load(Ordering::Acquire) {
+            match stream.read(&mut read_buf) {
+                Ok(0) => {
+                    log::info!("Virtual NTR connection from {} closed", peer);
+                    break;
+                }
+                Ok(n) => buf.extend_from_slice(&read_buf[..n]),
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("Virtual NTR read error: {}", e);
+                    break;
+                }
+            }
+
+            while buf.len() >= NTRPacket::HDR_SIZE {
+                let header: [u8; NTRPacket::HDR_SIZE] =
+                    buf[..NTRPacket::HDR_SIZE].try_into().unwrap();
+                let Some(packet) = NTRPacket::from_wire(&header) else {
+                    log::warn!("Invalid NTR packet from {}", peer);
+                    buf.clear();
+                    break;
+                };
+                let len = NTRPacket::HDR_SIZE + packet.extra_len();
+                if buf.len() < len {
+                    break;
+                }
+                let data: Vec<u8> = buf.drain(..len).skip(NTRPacket::HDR_SIZE).collect();
+                if let Err(e) =
+                    Self::handle(&packet, &data, &mut stream, peer, &shared, &mut output)
+                {
+                    log::error!("Virtual NTR write error: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn reply(stream: &mut TcpStream, packet: &NTRPacket, data: &[u8]) -> std::io::Result<()> {
+        let reply = NTRPacket::data_command(
+            packet.seq(),
+            packet.cmd(),
+            &[],
+            data.len().try_into().unwrap(),
+        );
+        stream.write_all(&reply.to_wire())?;
+        stream.write_all(data)
+    }
+
+    fn handle(
+        packet: &NTRPacket,
+        data: &[u8],
+        stream: &mut TcpStream,
+        peer: SocketAddr,
+        shared: &Shared,
+        output: &mut String,
+    ) -> std::io::Result<()> {
+        if packet.is_init() {
+            let settings = packet.stream_settings();
+            log::info!("Virtual NTR init (seq={}, {:?})", packet.seq(), settings);
+            let mut stats = shared.stats.lock().unwrap();
+            stats.inits += 1;
+            stats.settings = Some(settings);
+            drop(stats);
+            *shared.client.lock().unwrap() =
+                Some(SocketAddr::new(peer.ip(), shared.options.client_port));
+        } else if packet.is_heartbeat() {
+            log::trace!("Virtual NTR heartbeat (seq={})", packet.seq());
+            shared.stats.lock().unwrap().heartbeats += 1;
+            Self::reply(stream, packet, output.as_bytes())?;
+            output.clear();
+        } else if packet.cmd() == NTRPacket::CMD_LIST_PROCESS {
+            output.push_str(&format!(
+                "pid: 0x{:08x}, pname: {:>8}, tid: 0004000000175e00, kpobj: fff76c70\n",
+                GAME_PID, shared.options.process
+            ));
+            output.push_str("end of process list.\n");
+        } else if packet.cmd() == NTRPacket::CMD_READ_MEMORY {
+            let (pid, address, length) = (packet.arg(0), packet.arg(1), packet.arg(2));
+            shared.stats.lock().unwrap().memory_reads += 1;
+            // Like NTR, errors are only reported in the log
+            if pid != GAME_PID {
+                output.push_str(&format!("openprocess failed: {:08x}\n", pid));
+            } else if let Some(memory) = Self::read(shared, address, length) {
+                Self::reply(stream, packet, &memory)?;
+            } else {
+                output.push_str(&format!("readremotememory failed: {:08x}\n", address));
+            }
+        } else if packet.cmd() == NTRPacket::CMD_WRITE_MEMORY {
+            let (pid, address) = (packet.arg(0), packet.arg(1));
+            shared.stats.lock().unwrap().memory_writes += 1;
+            if pid == GAME_PID {
+                Self::write(shared, address, data);
+            } else {
+                output.push_str(&format!("openprocess failed: {:08x}\n", pid));
+            }
+        } else {
+            log::warn!("Virtual NTR ignoring command {}", packet.cmd());
+        }
+        Ok(())
+    }
+
+    fn stream(socket: UdpSocket, frames: NTRFrames, shared: Arc<Shared>) {
+        let mut rng = StdRng::seed_from_u64(shared.options.seed);
+        let interval = Duration::from_secs(1) / shared.options.fps;
+        let mut deadline = Instant::now();
+        let mut frame_id: u8 = 0;
+        let mut index = 0;
+        while !shared.stop.load(Ordering::Acquire) {
+            deadline += interval;
+            std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+
+            let client = *shared.client.lock().unwrap();
+            let Some(client) = client else {
+                continue;
+            };
+            if !shared.streaming.load(Ordering::Acquire) {
+                continue;
+            }
+
+            if !frames.top.is_empty() {
+                let data = &frames.top[index % frames.top.len()];
+                Self::send_frame(&socket, client, data, true, frame_id, &mut rng, &shared);
+                shared.stats.lock().unwrap().top_frames += 1;
+                frame_id = frame_id.wrapping_add(1);
+            }
+            if !frames.bottom.is_empty() {
+                let data = &frames.bottom[index % frames.bottom.len()];
+                Self::send_frame(&socket, client, data, false, frame_id, &mut rng, &shared);
+                shared.stats.lock().unwrap().bottom_frames += 1;
+                frame_id = frame_id.wrapping_add(1);
+            }
+            index += 1;
+        }
+    }
+
+    // Split a frame into fragments, each with a header of the frame id, the
+    // screen and last fragment flags, a format byte and the sequence number
+    fn fragments(data: &[u8], top: bool, frame_id: u8) -> Vec<Vec<u8>> {
+        let count = data.len().div_ceil(FRAGMENT_SIZE);
+        data.chunks(FRAGMENT_SIZE)
+            .enumerate()
+            .map(|(seq, chunk)| {
+                let last = if seq + 1 == count { 0x10 } else { 0x00 };
+                let mut packet = vec![frame_id, last | top as u8, 0, seq as u8];
+                packet.extend_from_slice(chunk);
+                packet
+            })
+            .collect()
+    }
+
+    fn send_frame(
+        socket: &UdpSocket,
+        client: SocketAddr,
+        data: &[u8],
+        top: bool,
+        frame_id: u8,
+        rng: &mut StdRng,
+        shared: &Shared,
+    ) {
+        let fragments = Self::fragments(data, top, frame_id);
+        let send = |packet: &[u8]| {
+            if let Err(e) = socket.send_to(packet, client) {
+                log::warn!("Virtual NTR send error: {}", e);
+            }
+        };
+        let mut stats = NTRStats::default();
+        let mut held: Option<Vec<u8>> = None;
+        for packet in fragments {
+            if rng.random_bool(shared.options.loss) {
+                stats.lost += 1;
+                continue;
+            }
+            if held.is_none() && rng.random_bool(shared.options.reorder) {
+                held = Some(packet);
+                continue;
+            }
+            send(&packet);
+            stats.fragments += 1;
+            if let Some(h) = held.take() {
+                send(&h);
+                stats.fragments += 1;
+                stats.reordered += 1;
+            }
+        }
+        // Nothing left to swap with
+        if let Some(h) = held {
+            send(&h);
+            stats.fragments += 1;
+        }
+
+        let mut total = shared.stats.lock().unwrap();
+        total.fragments += stats.fragments;
+        total.lost += stats.lost;
+        total.reordered += stats.reordered;
+    }
+}
+
+impl Drop for VirtualNTR {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Release);
+        for h in self.handles.drain(..) {
+            let _ = h.join();
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    io::{Read, Write},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use serialport::{SerialPort, TTYPort};
+
+use super::Timeline;
+use crate::control::Button;
+
+const SEQ_MOD: u32 = 65536;
+const MAX_LINE: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct SerialOptions {
+    // Protocol version to report
+    pub version: u32,
+    // Stop replying after this many commands, like a wire coming loose
+    pub reply_limit: Option<u32>,
+}
+
+impl Default for SerialOptions {
+    fn default() -> Self {
+        Self {
+            version: 3,
+            reply_limit: None,
+        }
+    }
+}
+
+enum Action {
+    Set(Button, bool),
+    Wait(u64),
+}
+
+// Control board emulator on a pseudo-terminal, speaking the protocol from
+// control/main.py. Open path() in place of the real serial port.
+pub struct VirtualSerial {
+    path: String,
+    timeline: Timeline,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+struct Board {
+    port: TTYPort,
+    options: SerialOptions,
+    timeline: Timeline,
+    received: u32,
+    malformed: u32,
+    dropped: u32,
+    expected_seq: Option<u32>,
+}
+
+impl VirtualSerial {
+    pub fn start(options: SerialOptions) -> serialport::Result<Self> {
+        let (port, slave) = TTYPort::pair()?;
+        let path = slave.name().ok_or(serialport::Error::new(
+            serialport::ErrorKind::NoDevice,
+            "No name for pseudo-terminal",
+        ))?;
+        log::info!("Virtual control board on {}", path);
+
+        let timeline = Timeline::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut board = Board {
+            port,
+            options,
+            timeline: timeline.clone(),
+            received: 0,
+            malformed: 0,
+            dropped: 0,
+            expected_seq: None,
+        };
+        let stop_clone = stop.clone();
+        let handle = std::thread::spawn(move || {
+            // Keep the slave end open, otherwise reads fail while the
+            // backend is reconnecting
+            let _slave = slave;
+            board.run(stop_clone);
+        });
+
+        Ok(Self {
+            path,
+            timeline,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+}
+
+impl Drop for VirtualSerial {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+impl Board {
+    fn run(&mut self, stop: Arc<AtomicBool>) {
+        let _ = self
+            .port
+            .write_all(b"Shaoooh control board initialised...\n");
+        let mut line = Vec::new();
+        let mut overflow = false;
+        let mut byte = [0u8; 1];
+        while !stop.load(Ordering::Acquire) {
+            match self.port.read(&mut byte) {
+                Ok(1) => {}
+                Ok(_) => continue,
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    log::error!("Virtual control board read error: {}", e);
+                    break;
+                }
+            }
+            if byte[0] == b'\n' {
+                if overflow {
+                    self.malformed += 1;
+                    self.reply("- E line too long");
+                } else if !line.is_empty() {
+                    let l = String::from_utf8_lossy(&line).trim().to_string();
+                    self.handle(&l);
+                }
+                line.clear();
+                overflow = false;
+            } else if line.len() < MAX_LINE {
+                line.push(byte[0]);
+            } else {
+                overflow = true;
+            }
+        }
+    }
+
+    fn reply(&mut self, reply: &str) {
+        if self.options.reply_limit.is_none_or(|n| self.received <= n) {
+            let _ = self.port.write_all(format!("{}\n", reply).as_bytes());
+        }
+    }
+
+    fn button(c: char) -> Option<Button> {
+        match c {
+            'A' => Some(Button::A),
+            'B' => Some(Button::B),
+            'X' => Some(Button::X),
+            'Y' => Some(Button::Y),
+            'S' => Some(Button::Start),
+            's' => Some(Button::Select),
+            'L' => Some(Button::L),
+            'R' => Some(Button::R),
+            'l' => Some(Button::Left),
+            'r' => Some(Button::Right),
+            'u' => Some(Button::Up),
+            'd' => Some(Button::Down),
+            _ => None,
+        }
+    }
+
+    // Same parsing as parse_buttons in control/main.py
+    fn parse_buttons(data: &str) -> Result<Vec<Action>, String> {
+        let chars: Vec<char> = data.chars().collect();
+        let mut actions = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] != 'q' || i + 1 >= chars.len() {
+                return Err(format!("expected q at {}", i));
+            }
+            let cmd = chars[i + 1];
+            match cmd {
+                'm' | 'p' | 'P' | 'M' => {
+                    actions.push(Action::Wait(match cmd {
+                        'm' => 50,
+                        'p' => 100,
+                        'P' => 500,
+                        _ => 1000,
+                    }));
+                    i += 2;
+                }
+                'w' => {
+                    let rest: String = chars[i + 2..].iter().collect();
+                    let end = rest.find(';').ok_or(format!("bad wait at {}", i))?;
+                    let ms = rest[..end]
+                        .parse()
+                        .map_err(|_| format!("bad wait at {}", i))?;
+                    actions.push(Action::Wait(ms));
+                    i += 2 + end + 1;
+                }
+                _ => {
+                    let b = Self::button(cmd).ok_or(format!("unknown command {}", cmd))?;
+                    match chars.get(i + 2) {
+                        Some('1') => actions.push(Action::Set(b, true)),
+                        Some('0') => actions.push(Action::Set(b, false)),
+                        _ => return Err(format!("bad value for {}", cmd)),
+                    }
+                    i += 3;
+                }
+            }
+        }
+        Ok(actions)
+    }
+
+    fn execute(&self, actions: Vec<Action>) {
+        let mut deadline = Instant::now();
+        for a in actions {
+            match a {
+                Action::Set(b, pressed) => self.timeline.record(b, pressed),
+                Action::Wait(ms) => {
+                    deadline += Duration::from_millis(ms);
+                    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, line: &str) {
+        let mut parts = line.splitn(3, ' ');
+        let Some(seq) = parts.next().and_then(|s| s.parse::<u32>().ok()) else {
+            self.malformed += 1;
+            self.reply("- E bad sequence number");
+            return;
+        };
+        let op = parts.next().unwrap_or("");
+        self.received += 1;
+        if op != "V"
+            && let Some(expected) = self.expected_seq
+            && seq != expected
+        {
+            self.dropped += (seq + SEQ_MOD - expected) % SEQ_MOD;
+        }
+        self.expected_seq = Some((seq + 1) % SEQ_MOD);
+
+        match (op, parts.next()) {
+            ("V", _) => self.reply(&format!("{} V {}", seq, self.options.version)),
+            ("P", _) => self.reply(&format!(
+                "{} P {} {} {}",
+                seq, self.received, self.malformed, self.dropped
+            )),
+            ("C", Some(data)) => match Self::parse_buttons(data) {
+                Ok(actions) => {
+                    self.execute(actions);
+                    self.reply(&format!("{} K", seq));
+                }
+                Err(e) => {
+                    self.malformed += 1;
+                    self.reply(&format!("{} E {}", seq, e));
+                }
+            },
+            _ => {
+                self.malformed += 1;
+                self.reply(&format!("{} E unknown operation", seq));
+            }
+        }
+    }
+}
@@ -1,18 +1,19 @@
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
-use crate::app::ShaooohError;
+use crate::app::{Backoff, Links, ShaooohError};
 use crate::vision::{
-    BotVision, ColourChannel, ColourChannelDetect3DSSettings, ProcessingResult, compat,
+    BotVision, ColourChannel, ColourChannelDetect3DSSettings, DebugWindows,
+    MemoryDetect3DSSettings, ProcessingResult, WinInfo, compat,
 };
 
 use crate::{app::states::Game, context::PkContext};
 
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 
 use opencv::core::Rect;
-use opencv::highgui;
+use opencv::highgui::WINDOW_AUTOSIZE;
 use opencv::imgcodecs::IMREAD_UNCHANGED;
 use opencv::imgproc::THRESH_BINARY_INV;
 use opencv::{
@@ -23,12 +24,16 @@ use opencv::{
 };
 
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::AsyncWriteExt,
     net::{TcpStream, UdpSocket},
-    sync::{broadcast, watch},
+    sync::{broadcast, mpsc, watch},
 };
 
-use super::{NTRPacket, Processing};
+use super::memory;
+use super::reassembly::{FrameReassembler, StreamStats};
+use super::{
+    MemoryRequest, NTRDebugClient, NTRMemoryReader, NTRPacket, NTRStreamSettings, Processing,
+};
 
 enum Frame {
     None,
@@ -49,23 +54,31 @@ pub struct BishaanVision {
     reference: HashMap<u32, (Mat, Mat, Mat)>,
     game: Game,
     flipped: bool,
+    memory: NTRMemoryReader,
+    windows: DebugWindows,
 }
 
 pub struct BishaanVisionSocket {
+    ip: core::net::Ipv4Addr,
     tx_top: watch::Sender<Mat>,
     tx_bottom: watch::Sender<Mat>,
     tcp_sock: Option<TcpStream>,
     img_socket: UdpSocket,
-    top_frame_num: u8,
-    top_frame_seq: u8,
-    top_screen_buf: Vec<u8>,
-    bot_frame_num: u8,
-    bot_frame_seq: u8,
-    bot_screen_buf: Vec<u8>,
+    top: FrameReassembler,
+    bottom: FrameReassembler,
     can_heartbeat: Arc<AtomicBool>,
-    last_fps: SystemTime,
-    last_frame_count: usize,
+    // Sequence number for packets on any connection
+    seq: Arc<AtomicU32>,
+    // None once the sender has gone
+    settings: Option<watch::Receiver<NTRStreamSettings>>,
+    // Settings the stream was last started with
+    stream: NTRStreamSettings,
+    last_top_frame: Instant,
+    stats: Arc<Mutex<StreamStats>>,
+    // None once the reader has gone
+    memory_rx: Option<mpsc::Receiver<MemoryRequest>>,
     error_tx: Arc<broadcast::Sender<ShaooohError>>,
+    links: Links,
 }
 
 impl BotVision for BishaanVision {
@@ -84,16 +97,14 @@ impl BotVision for BishaanVision {
                     &Vector::new(),
                 )
                 .expect("Failed to encode frame");
-                opencv::highgui::imshow("bottom", &bottom)
-                    .unwrap_or_else(|_| panic!("Failed to show bottom window"));
-                opencv::highgui::wait_key(1).expect("Event loop failed");
+                self.windows.show(&Self::BOTTOM_WIN, &bottom);
+                self.windows.wait();
             }
             if !top.empty() {
                 opencv::imgcodecs::imencode(".png", &top, &mut self.encoded_top, &Vector::new())
                     .expect("Failed to encode frame");
-                opencv::highgui::imshow("top", &top)
-                    .unwrap_or_else(|_| panic!("Failed to show top window"));
-                opencv::highgui::wait_key(1).expect("Event loop failed");
+                self.windows.show(&Self::TOP_WIN, &top);
+                self.windows.wait();
             }
             Some(
                 processing
@@ -123,7 +134,37 @@ impl BotVision for BishaanVision {
 }
 
 impl BishaanVision {
-    pub fn new(rx_top: watch::Receiver<Mat>, rx_bottom: watch::Receiver<Mat>) -> Self {
+    const TOP_WIN: WinInfo = WinInfo {
+        name: "top",
+        x: 32,
+        y: 32,
+        scale: 1,
+        size: None,
+        flags: WINDOW_AUTOSIZE,
+    };
+    const BOTTOM_WIN: WinInfo = WinInfo {
+        name: "bottom",
+        x: 72,
+        y: 304,
+        scale: 1,
+        size: None,
+        flags: WINDOW_AUTOSIZE,
+    };
+    const FOUND_WIN: WinInfo = WinInfo {
+        name: "found",
+        x: 424,
+        y: 304,
+        scale: 1,
+        size: None,
+        flags: WINDOW_AUTOSIZE,
+    };
+
+    pub fn new(
+        rx_top: watch::Receiver<Mat>,
+        rx_bottom: watch::Receiver<Mat>,
+        memory: NTRMemoryReader,
+        windows: DebugWindows,
+    ) -> Self {
         let ref_shiny_star =
             opencv::imgcodecs::imread("static/usum_shiny_star.png", IMREAD_GRAYSCALE)
                 .expect("Couldn't read image");
@@ -151,6 +192,8 @@ impl BishaanVision {
             reference: HashMap::new(),
             game: Game::None,
             flipped: false,
+            memory,
+            windows,
         }
     }
 
@@ -480,9 +523,7 @@ impl BishaanVision {
         }
 
         // Display current find TODO should this be included?
-        highgui::imshow("FOUND", &for_rect).expect("Failed to show found window");
-        //Self::show_window(Self::FOUND_WIN, &for_rect);
-        //Self::transform_window(Self::FOUND_WIN);
+        self.windows.show(&Self::FOUND_WIN, &for_rect);
 
         let is_shiny = is_shiny_conv;
         let res = ProcessingResult {
@@ -508,6 +549,19 @@ impl BishaanVision {
         }
     }
 
+    fn memory_detect(&mut self, settings: &MemoryDetect3DSSettings) -> ProcessingResult {
+        let length = settings.value.length();
+        let data = match self.memory.poll(settings.process, settings.address, length) {
+            Some(Ok(data)) => Some(data),
+            Some(Err(e)) => {
+                log::debug!("Memory read failed: {}", e);
+                None
+            }
+            None => None,
+        };
+        memory::check(settings, data.as_deref())
+    }
+
     fn process(
         &mut self,
         process: &Processing,
@@ -536,264 +590,264 @@ impl BishaanVision {
                     self.colour_channel_detect(settings, bot_frame)
                 }
             }
+            Processing::Memory3DS(settings) => self.memory_detect(settings),
             _ => unimplemented!("Processing not implemented for 3DS"),
         }
     }
 }
 
 impl BishaanVisionSocket {
+    const LINK: &str = "NTR";
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         ip: core::net::Ipv4Addr,
         tx_top: watch::Sender<Mat>,
         tx_bottom: watch::Sender<Mat>,
         can_heartbeat: Arc<AtomicBool>,
         error_tx: Arc<broadcast::Sender<ShaooohError>>,
+        links: Links,
+        stats: Arc<Mutex<StreamStats>>,
+        memory_rx: mpsc::Receiver<MemoryRequest>,
+        mut settings: watch::Receiver<NTRStreamSettings>,
     ) -> std::io::Result<Self> {
         log::info!("Creating BishaanVisionSocket");
 
         let img_socket = UdpSocket::bind("0.0.0.0:8001").await?;
         img_socket.connect((ip.clone(), 8000)).await?;
 
+        let seq = Arc::new(AtomicU32::new(1));
+        let stream_settings = settings.borrow_and_update().clone();
+        let tcp_sock = Self::start_stream(ip, &seq, &stream_settings).await?;
+
+        Ok(Self {
+            ip,
+            img_socket,
+            tcp_sock: Some(tcp_sock),
+            tx_top,
+            tx_bottom,
+            top: FrameReassembler::new(),
+            bottom: FrameReassembler::new(),
+            can_heartbeat,
+            seq,
+            settings: Some(settings),
+            stream: stream_settings,
+            last_top_frame: Instant::now(),
+            stats,
+            memory_rx: Some(memory_rx),
+            error_tx,
+            links,
+        })
+    }
+
+    // Send the init packet to start NTR streaming and connect the debug socket
+    async fn start_stream(
+        ip: core::net::Ipv4Addr,
+        seq: &AtomicU32,
+        settings: &NTRStreamSettings,
+    ) -> std::io::Result<TcpStream> {
         {
             let mut ctl1_socket = TcpStream::connect((ip.clone(), 8000)).await?;
 
             // Send init packet
-            let init = NTRPacket::init();
+            log::info!("Starting NTR stream with {:?}", settings);
+            let init = NTRPacket::init(seq.fetch_add(1, Ordering::Relaxed), settings);
             ctl1_socket.write_all(&init.to_wire()).await?;
 
             tokio::time::sleep(tokio::time::Duration::from_millis(2000)).await;
         }
 
-        let tcp_sock = TcpStream::connect((ip, 8000)).await?;
+        TcpStream::connect((ip, 8000)).await
+    }
 
-        let last_fps = SystemTime::now();
-        let last_frame_count = 0;
+    fn decode(data: &[u8]) -> Option<Mat> {
+        let s = opencv::imgcodecs::imdecode(
+            &opencv::core::Vector::from_slice(data),
+            opencv::imgcodecs::IMREAD_COLOR,
+        )
+        .ok()
+        .filter(|m| !m.empty())?;
+        let mut m2 = Mat::default();
+        opencv::core::rotate(&s, &mut m2, opencv::core::ROTATE_90_COUNTERCLOCKWISE)
+            .expect("Failed to rotate");
+        Some(m2)
+    }
 
-        Ok(Self {
-            img_socket,
-            tcp_sock: Some(tcp_sock),
-            tx_top,
-            tx_bottom,
-            top_frame_num: 0,
-            top_frame_seq: 0,
-            top_screen_buf: vec![],
-            bot_frame_num: 0,
-            bot_frame_seq: 0,
-            bot_screen_buf: vec![],
-            can_heartbeat,
-            last_fps,
-            last_frame_count,
-            error_tx,
-        })
+    fn publish_stats(&self) {
+        if let Ok(mut stats) = self.stats.lock() {
+            stats.top = self.top.stats().clone();
+            stats.bottom = self.bottom.stats().clone();
+        }
     }
 
     async fn listen(&mut self) -> std::io::Result<Frame> {
         let mut frame = Frame::None;
         let mut buf = [0u8; 1500];
         let n = self.img_socket.recv(&mut buf).await?;
+        if n < 4 {
+            return Ok(frame);
+        }
 
         let is_top_screen = (buf[1] & 0xf) == 1;
         let is_last = (buf[1] & 0xf0) == 0x10;
         let frame_id = buf[0];
         let seq_num = buf[3];
 
-        if seq_num == 0 {
-            // New frame
-            if is_top_screen {
-                self.top_frame_num = frame_id;
-                self.top_frame_seq = 0;
-                self.top_screen_buf.clear();
-                self.top_screen_buf.extend_from_slice(&buf[4..n]);
-            } else {
-                self.bot_frame_num = frame_id;
-                self.bot_frame_seq = 0;
-                self.bot_screen_buf.clear();
-                self.bot_screen_buf.extend_from_slice(&buf[4..n]);
-            }
+        let screen = if is_top_screen {
+            &mut self.top
         } else {
-            let (exp_frame_num, next_seq) = if is_top_screen {
-                (self.top_frame_num, self.top_frame_seq + 1)
-            } else {
-                (self.bot_frame_num, self.bot_frame_seq + 1)
-            };
-
-            if exp_frame_num == frame_id && seq_num == next_seq {
-                if is_top_screen {
-                    self.top_frame_seq = seq_num;
-                    self.top_screen_buf.extend_from_slice(&buf[4..n]);
-                } else {
-                    self.bot_frame_seq = seq_num;
-                    self.bot_screen_buf.extend_from_slice(&buf[4..n]);
-                }
-
-                if is_last {
-                    if is_top_screen {
-                        if let Ok(s) = opencv::imgcodecs::imdecode(
-                            &opencv::core::Vector::from_slice(&self.top_screen_buf),
-                            opencv::imgcodecs::IMREAD_COLOR,
-                        ) {
-                            self.last_frame_count += 1;
-
-                            if self.last_fps.elapsed().expect("Failed to get time")
-                                > Duration::from_secs(1)
-                            {
-                                if self.last_frame_count > 33 || self.last_frame_count < 27 {
-                                    log::info!("Last FPS: {}", self.last_frame_count);
-                                }
-
-                                self.last_frame_count = 0;
-                                self.last_fps = SystemTime::now();
-                            }
+            &mut self.bottom
+        };
+        let data = screen.add(frame_id, seq_num, is_last, &buf[4..n]);
+        let period = screen.update_rates();
+        if period && is_top_screen {
+            let stats = self.top.stats();
+            if stats.fps > 33.0 || stats.fps < 27.0 {
+                log::info!(
+                    "Last FPS: {:.1} ({:.0}% dropped)",
+                    stats.fps,
+                    stats.drop_rate * 100.0
+                );
+            }
+        }
 
-                            let mut m2 = Mat::default();
-                            opencv::core::rotate(
-                                &s,
-                                &mut m2,
-                                opencv::core::ROTATE_90_COUNTERCLOCKWISE,
-                            )
-                            .expect("Failed to rotate");
-                            frame = Frame::Top(m2);
-                        }
-                    } else {
-                        if let Ok(s) = opencv::imgcodecs::imdecode(
-                            &opencv::core::Vector::from_slice(&self.bot_screen_buf),
-                            opencv::imgcodecs::IMREAD_COLOR,
-                        ) {
-                            let mut m2 = Mat::default();
-                            opencv::core::rotate(
-                                &s,
-                                &mut m2,
-                                opencv::core::ROTATE_90_COUNTERCLOCKWISE,
-                            )
-                            .expect("Failed to rotate");
-                            frame = Frame::Bottom(m2);
-                        }
-                    }
-                }
-            } else {
-                // if is_top_screen {
-                //     print!("TOP ");
-                // } else {
-                //     print!("BOT ");
-                // }
-                //  println!(
-                //      "Missing packet? Expected frame{}, got frame{} - exp{},got{}",
-                //      exp_frame_num, frame_id, next_seq, seq_num
-                // );
-                // Poison sequence
-                if is_top_screen {
-                    self.top_frame_seq = 250;
-                } else {
-                    self.bot_frame_seq = 250;
+        if let Some(data) = &data {
+            match Self::decode(data) {
+                Some(m) if is_top_screen => {
+                    self.last_top_frame = Instant::now();
+                    frame = Frame::Top(m);
                 }
+                Some(m) => frame = Frame::Bottom(m),
+                None => log::warn!("Failed to decode frame {}", frame_id),
             }
         }
+        if data.is_some() || period {
+            self.publish_stats();
+        }
 
-        if self.last_fps.elapsed().unwrap() > Duration::from_secs(10) {
-            log::error!("Haven't got a new frame in 10 seconds");
-            self.error_tx
-                .send(ShaooohError::CommunicationError)
-                .expect("Failed to send error");
+        if self.last_top_frame.elapsed() > Duration::from_secs(10) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Haven't got a new frame in 10 seconds",
+            ));
         }
 
         Ok(frame)
     }
 
-    pub async fn task(mut self) -> std::io::Result<()> {
-        let can_heartbeat = self.can_heartbeat.clone();
-        let (mut read, mut write) = self
-            .tcp_sock
-            .take()
-            .expect("Failed to get socket")
-            .into_split();
-        let error_tx = self.error_tx.clone();
-
-        tokio::spawn(async move {
-            loop {
-                let mut header_buf = [0u8; NTRPacket::HDR_SIZE];
-                let r = read.read(&mut header_buf).await;
-                match r {
-                    Ok(n) => {
-                        if n == 84 {
-                            if let Some(hdr) = NTRPacket::from_wire(&header_buf) {
-                                if hdr.extra_len() > 0 {
-                                    let mut extra_buf = vec![0u8; hdr.extra_len()];
-                                    let e_res = read.read(&mut extra_buf).await;
-                                    match e_res {
-                                        Ok(_n) => {
-                                            let str_conv = String::from_utf8_lossy(&extra_buf);
-                                            let strings = str_conv.split('\n');
-                                            for s in strings {
-                                                if s.len() > 0 {
-                                                    log::info!("[NTR({})] {}", hdr.seq(), s);
-                                                }
-                                            }
-                                        }
-                                        Err(e) => {
-                                            log::error!("{:?}", e);
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("{:?}", e);
+    // Never completes if there is no reader
+    async fn memory_request(
+        memory_rx: &mut Option<mpsc::Receiver<MemoryRequest>>,
+    ) -> Option<MemoryRequest> {
+        match memory_rx {
+            Some(rx) => rx.recv().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    // Never completes if there is no sender
+    async fn settings_changed(
+        settings: &mut Option<watch::Receiver<NTRStreamSettings>>,
+    ) -> Option<NTRStreamSettings> {
+        match settings {
+            Some(rx) => match rx.changed().await {
+                Ok(()) => Some(rx.borrow_and_update().clone()),
+                Err(_) => None,
+            },
+            None => std::future::pending().await,
+        }
+    }
+
+    // Receive frames until the connection is lost, returns false if the
+    // receivers have gone and the task should end
+    async fn session(&mut self, tcp_sock: TcpStream) -> bool {
+        let (client, mut debug) =
+            NTRDebugClient::new(tcp_sock, self.can_heartbeat.clone(), self.seq.clone());
+        let mut memory_rx = self.memory_rx.take();
+        let mut settings = self.settings.take();
+        self.top.reset();
+        self.bottom.reset();
+        self.last_top_frame = Instant::now();
+
+        let lost = loop {
+            tokio::select! {
+                _ = &mut debug => break true,
+                req = Self::memory_request(&mut memory_rx) => match req {
+                    Some(req) => {
+                        tokio::spawn(req.serve(client.clone()));
                     }
-                }
-            }
-        });
-        tokio::spawn(async move {
-            let mut seq = 1;
-            loop {
-                tokio::time::sleep(Duration::from_millis(250)).await;
-                let hb_pkt = NTRPacket::heartbeat(seq);
-                if can_heartbeat.load(Ordering::Acquire) {
-                    match write.write_all(&hb_pkt.to_wire()).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            log::error!("Heartbeat send error: {:?}", e);
-                            error_tx
-                                .send(ShaooohError::CommunicationError)
-                                .expect("Failed to send error");
-                            break;
+                    None => memory_rx = None,
+                },
+                new = Self::settings_changed(&mut settings) => match new {
+                    Some(new) => {
+                        log::info!("Restarting NTR stream with {:?}", new);
+                        let res = client.remote_play(&new).await;
+                        self.stream = new;
+                        if let Err(e) = res {
+                            log::error!("Failed to send init packet: {}", e);
+                            break true;
                         }
                     }
-                    seq += 1;
-                }
-            }
-        });
-
-        loop {
-            match tokio::time::timeout(Duration::from_secs(10), self.listen()).await {
-                Ok(frame_res) => match frame_res {
-                    Ok(frame) => match frame {
-                        Frame::None => {}
-                        Frame::Bottom(m) => {
-                            if self.tx_bottom.send(m).is_err() {
-                                break;
+                    None => settings = None,
+                },
+                r = tokio::time::timeout(Duration::from_secs(10), self.listen()) => match r {
+                    Ok(frame_res) => match frame_res {
+                        Ok(frame) => match frame {
+                            Frame::None => {}
+                            Frame::Bottom(m) => {
+                                if self.tx_bottom.send(m).is_err() {
+                                    break false;
+                                }
                             }
-                        }
-                        Frame::Top(m) => {
-                            if self.tx_top.send(m).is_err() {
-                                break;
+                            Frame::Top(m) => {
+                                self.links.set_up(Self::LINK);
+                                if self.tx_top.send(m).is_err() {
+                                    break false;
+                                }
                             }
+                        },
+                        Err(e) => {
+                            log::error!("Frame error: {}", e);
+                            break true;
                         }
                     },
                     Err(_) => {
-                        log::error!("Frame error");
-                        self.error_tx
-                            .send(ShaooohError::CommunicationError)
-                            .expect("Failed to send error");
-                        break;
+                        log::error!("Haven't got a new frame in 10 seconds");
+                        break true;
                     }
-                },
-                Err(_) => {
-                    log::error!("Haven't got a new frame in 10 seconds");
-                    self.error_tx
-                        .send(ShaooohError::CommunicationError)
-                        .expect("Failed to send error");
-                    break;
+                }
+            }
+        };
+
+        debug.abort();
+        self.memory_rx = memory_rx;
+        self.settings = settings;
+        self.top.reset();
+        self.bottom.reset();
+        self.publish_stats();
+        lost
+    }
+
+    pub async fn task(mut self) -> std::io::Result<()> {
+        let mut backoff = Backoff::default();
+        while let Some(tcp_sock) = self.tcp_sock.take() {
+            if !self.session(tcp_sock).await {
+                break;
+            }
+            self.links.set_down(Self::LINK);
+            self.error_tx
+                .send(ShaooohError::CommunicationError)
+                .expect("Failed to send error");
+
+            // Restart the stream, the link is marked up again once frames arrive
+            while self.tcp_sock.is_none() {
+                backoff.wait().await;
+                match Self::start_stream(self.ip, &self.seq, &self.stream).await {
+                    Ok(s) => {
+                        backoff.reset();
+                        self.tcp_sock = Some(s);
+                    }
+                    Err(e) => log::error!("Failed to restart NTR stream: {:?}", e),
                 }
             }
         }
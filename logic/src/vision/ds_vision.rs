@@ -4,8 +4,8 @@ use std::{
 };
 
 use opencv::{
-    core::{Point, Rect, Size, ToInputArray, Vector},
-    highgui::{self, WINDOW_GUI_NORMAL, WINDOW_KEEPRATIO, WINDOW_NORMAL},
+    core::{Point, Rect, Size, Vector},
+    highgui::{WINDOW_GUI_NORMAL, WINDOW_KEEPRATIO, WINDOW_NORMAL},
     imgcodecs::{IMREAD_COLOR, IMREAD_UNCHANGED},
     imgproc::{LINE_8, THRESH_BINARY, THRESH_BINARY_INV, TM_CCORR_NORMED},
     prelude::*,
@@ -13,16 +13,20 @@ use opencv::{
 };
 
 use crate::{
-    app::states::Game,
+    app::{Backoff, Links, states::Game},
     context::PkContext,
     vision::{
-        BotVision, ChannelDetectSettings, ColourChannel, ColourChannelDetectSettings, Processing,
-        ProcessingResult, RegionDetectSettings, WinInfo, compat,
+        BotVision, ChannelDetectSettings, ColourChannel, ColourChannelDetectSettings, DebugWindows,
+        Processing, ProcessingResult, RegionDetectSettings, WinInfo, compat,
     },
 };
 
 pub struct Vision {
-    cam: VideoCapture,
+    path: String,
+    // None while the capture device is being reopened
+    cam: Option<VideoCapture>,
+    backoff: Backoff,
+    links: Links,
     encoded: Vector<u8>,
     found: Vector<u8>,
     found_mat: Mat,
@@ -33,15 +37,27 @@ pub struct Vision {
     // Reference, Shiny, Mask
     reference: HashMap<u32, (Mat, Mat, Mat)>,
     img_index: u32,
+    windows: DebugWindows,
     enable_debug: bool, // TODO control image/window debug separately
 }
 
 impl BotVision for Vision {
     fn process_next_frame(&mut self, processing: &[Processing]) -> Option<Vec<ProcessingResult>> {
         let mut input_frame = Mat::default();
-        self.cam
-            .read(&mut input_frame)
-            .expect("Failed to read frame");
+        let cam = self.cam.as_mut()?;
+        match cam.read(&mut input_frame) {
+            Ok(true) => {}
+            Ok(false) => {
+                log::error!("No frame from video capture");
+                self.lost();
+                return None;
+            }
+            Err(e) => {
+                log::error!("Failed to read frame: {}", e);
+                self.lost();
+                return None;
+            }
+        }
         if input_frame.empty() {
             return None;
         }
@@ -73,9 +89,8 @@ impl BotVision for Vision {
         opencv::imgcodecs::imencode(".png", &frame, &mut self.encoded, &Vector::new())
             .expect("Failed to encode frame");
 
-        Self::show_window(Self::CAPTURE_WIN, &frame);
-        Self::transform_window(Self::CAPTURE_WIN);
-        highgui::wait_key(1).expect("Event loop failed");
+        self.windows.show(&Self::CAPTURE_WIN, &frame);
+        self.windows.wait();
 
         Some(processing.iter().map(|p| self.process(p, &frame)).collect())
     }
@@ -96,6 +111,33 @@ impl BotVision for Vision {
     fn new_found(&self) -> bool {
         self.found_updated
     }
+
+    fn reconnect(&mut self) -> bool {
+        if self.cam.is_some() {
+            return true;
+        }
+        if !self.backoff.due() {
+            return false;
+        }
+        log::info!("Reopening video capture {}", self.path);
+        match Self::open_capture(&self.path) {
+            Ok(cam) => {
+                self.cam = Some(cam);
+                self.backoff.reset();
+                self.links.set_up(Self::LINK);
+                true
+            }
+            Err(e) => {
+                let delay = self.backoff.next_delay();
+                log::error!(
+                    "Failed to reopen video capture: {}, retrying in {}s",
+                    e,
+                    delay.as_secs()
+                );
+                false
+            }
+        }
+    }
 }
 
 impl Vision {
@@ -111,75 +153,82 @@ impl Vision {
     const DS_W: i32 = 256;
     const DS_H: i32 = 192;
     const MAX_IMAGES: u32 = 256;
+    const LINK: &str = "Video capture";
     const CAPTURE_WIN: WinInfo = WinInfo {
         name: "capture",
         x: 32,
         y: 32,
         scale: 2,
+        size: Some((Self::DS_W, Self::DS_H)),
+        flags: WINDOW_NORMAL | WINDOW_KEEPRATIO | WINDOW_GUI_NORMAL,
     };
     const FOUND_WIN: WinInfo = WinInfo {
         name: "found",
         x: 32,
         y: 464,
         scale: 1,
+        size: Some((Self::DS_W, Self::DS_H)),
+        flags: WINDOW_NORMAL | WINDOW_KEEPRATIO | WINDOW_GUI_NORMAL,
     };
     const FOUND_LAST_WIN: WinInfo = WinInfo {
         name: "found_last",
         x: 32 + 256,
         y: 464,
         scale: 1,
+        size: Some((Self::DS_W, Self::DS_H)),
+        flags: WINDOW_NORMAL | WINDOW_KEEPRATIO | WINDOW_GUI_NORMAL,
     };
 
-    fn show_window(win: WinInfo, mat: &impl ToInputArray) {
-        highgui::imshow(win.name, mat)
-            .unwrap_or_else(|_| panic!("Failed to show '{}' window", win.name));
-    }
-
-    fn transform_window(win: WinInfo) {
-        opencv::highgui::move_window(win.name, win.x, win.y)
-            .unwrap_or_else(|_| panic!("Failed to move '{}' window", win.name));
-        opencv::highgui::resize_window(win.name, Self::DS_W * win.scale, Self::DS_H * win.scale)
-            .unwrap_or_else(|_| panic!("Failed to resize '{}' window", win.name));
-    }
-
-    fn create_window(win: WinInfo, en_dbg: bool) {
-        let flags = if en_dbg {
-            0
-        } else {
-            WINDOW_NORMAL | WINDOW_KEEPRATIO | WINDOW_GUI_NORMAL
-        };
-        opencv::highgui::named_window(win.name, flags)
-            .unwrap_or_else(|_| panic!("Failed to create '{}' window", win.name));
-        Self::transform_window(win);
-    }
-
-    pub fn new(path: &str, raw_frame_mutex: Arc<Mutex<Mat>>) -> Self {
-        log::info!("Starting video capture");
-        let mut cam = VideoCapture::from_file(path, CAP_V4L2).expect("Couldn't open video");
+    fn open_capture(path: &str) -> opencv::Result<VideoCapture> {
+        let mut cam = VideoCapture::from_file(path, CAP_V4L2)?;
+        if !cam.is_opened()? {
+            return Err(opencv::Error::new(
+                opencv::core::StsError,
+                format!("Couldn't open {}", path),
+            ));
+        }
         log::debug!("Video capture opened");
 
-        cam.set(opencv::videoio::CAP_PROP_READ_TIMEOUT_MSEC, 2000.0)
-            .expect("Failed to set property");
+        cam.set(opencv::videoio::CAP_PROP_READ_TIMEOUT_MSEC, 2000.0)?;
 
-        cam.set(opencv::videoio::CAP_PROP_BRIGHTNESS, 50.0)
-            .expect("Failed to set property");
-        cam.set(opencv::videoio::CAP_PROP_FRAME_WIDTH, Vision::WIDTH.into())
-            .expect("Failed to set property");
+        cam.set(opencv::videoio::CAP_PROP_BRIGHTNESS, 50.0)?;
+        cam.set(opencv::videoio::CAP_PROP_FRAME_WIDTH, Vision::WIDTH.into())?;
         cam.set(
             opencv::videoio::CAP_PROP_FRAME_HEIGHT,
             Vision::HEIGHT.into(),
-        )
-        .expect("Failed to set property");
+        )?;
+        Ok(cam)
+    }
+
+    // Drop the capture so it is reopened, the hunt is held until then
+    fn lost(&mut self) {
+        self.cam = None;
+        self.backoff.reset();
+        self.links.set_down(Self::LINK);
+    }
 
-        // TODO allow debug mode without window flags
-        log::info!("Opening windows");
-        Self::create_window(Self::CAPTURE_WIN, false);
-        Self::create_window(Self::FOUND_WIN, false);
-        Self::create_window(Self::FOUND_LAST_WIN, false);
-        highgui::wait_key(1).expect("Event loop failed");
+    pub fn new(
+        path: &str,
+        raw_frame_mutex: Arc<Mutex<Mat>>,
+        links: Links,
+        windows: DebugWindows,
+    ) -> Self {
+        log::info!("Starting video capture");
+        let cam = Self::open_capture(path).expect("Couldn't open video");
+
+        if !windows.headless() {
+            log::info!("Opening windows");
+            windows.create(&Self::CAPTURE_WIN);
+            windows.create(&Self::FOUND_WIN);
+            windows.create(&Self::FOUND_LAST_WIN);
+            windows.wait();
+        }
 
         Self {
-            cam,
+            path: path.to_string(),
+            cam: Some(cam),
+            backoff: Backoff::default(),
+            links,
             encoded: Vector::default(),
             found: Vector::default(),
             found_mat: Mat::default(),
@@ -189,6 +238,7 @@ impl Vision {
             game: Game::None,
             flipped: false,
             img_index: 0,
+            windows,
             enable_debug: false,
         }
     }
@@ -536,8 +586,7 @@ impl Vision {
     }
 
     fn set_found(&mut self, frame: &Mat, top: bool) -> ProcessingResult {
-        Self::show_window(Self::FOUND_WIN, &frame);
-        Self::transform_window(Self::FOUND_WIN);
+        self.windows.show(&Self::FOUND_WIN, &frame);
 
         // Save to encoded frame
         opencv::imgcodecs::imencode(".png", &frame, &mut self.found, &Vector::new())
@@ -545,8 +594,7 @@ impl Vision {
         self.found_updated = true;
 
         if !self.found_mat.empty() {
-            Self::show_window(Self::FOUND_LAST_WIN, &self.found_mat);
-            Self::transform_window(Self::FOUND_LAST_WIN);
+            self.windows.show(&Self::FOUND_LAST_WIN, &self.found_mat);
         }
 
         self.found_mat = frame.clone();
@@ -580,6 +628,7 @@ impl Vision {
                 panic!("ColourChannelDetect3DS incompatible with DS")
             }
             Processing::Sprite3DS(..) => panic!("Sprite3DS incompatible with DS"),
+            Processing::Memory3DS(_) => panic!("Memory3DS incompatible with DS"),
         }
     }
 }
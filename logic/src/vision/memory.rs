@@ -0,0 +1,137 @@
+use super::{MemoryDetect3DSSettings, MemoryValue, Processing, ProcessingResult};
+
+// Size of an encrypted PK7, without the party stats
+const PK7_SIZE: u32 = 232;
+const PK7_BLOCK: usize = 56;
+const PK7_BLOCKS_START: usize = 8;
+
+// Order of the four blocks for each shuffle value
+const BLOCK_POSITION: [[usize; 4]; 24] = [
+    [0, 1, 2, 3],
+    [0, 1, 3, 2],
+    [0, 2, 1, 3],
+    [0, 3, 1, 2],
+    [0, 2, 3, 1],
+    [0, 3, 2, 1],
+    [1, 0, 2, 3],
+    [1, 0, 3, 2],
+    [2, 0, 1, 3],
+    [3, 0, 1, 2],
+    [2, 0, 3, 1],
+    [3, 0, 2, 1],
+    [1, 2, 0, 3],
+    [1, 3, 0, 2],
+    [2, 1, 0, 3],
+    [3, 1, 0, 2],
+    [2, 3, 0, 1],
+    [3, 2, 0, 1],
+    [1, 2, 3, 0],
+    [1, 3, 2, 0],
+    [2, 1, 3, 0],
+    [3, 1, 2, 0],
+    [2, 3, 1, 0],
+    [3, 2, 1, 0],
+];
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct PK7 {
+    species: u32,
+    pid: u32,
+    tid: u16,
+    sid: u16,
+}
+
+impl PK7 {
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    // Decrypt and check an encrypted PK7, None if it isn't valid so memory
+    // that hasn't been filled in yet is ignored
+    pub(crate) fn decrypt(encrypted: &[u8]) -> Option<Self> {
+        if encrypted.len() != PK7_SIZE as usize {
+            return None;
+        }
+        let ec = Self::u32_at(encrypted, 0);
+        if ec == 0 {
+            return None;
+        }
+
+        let mut data = encrypted.to_vec();
+        let mut seed = ec;
+        for i in (PK7_BLOCKS_START..data.len()).step_by(2) {
+            seed = seed.wrapping_mul(0x41C64E6D).wrapping_add(0x6073);
+            let word = Self::u16_at(&data, i) ^ (seed >> 16) as u16;
+            data[i..i + 2].copy_from_slice(&word.to_le_bytes());
+        }
+
+        let shuffled = data.clone();
+        let order = BLOCK_POSITION[(((ec >> 13) & 31) % 24) as usize];
+        for (block, position) in order.iter().enumerate() {
+            let from = PK7_BLOCKS_START + (PK7_BLOCK * position);
+            let to = PK7_BLOCKS_START + (PK7_BLOCK * block);
+            data[to..to + PK7_BLOCK].copy_from_slice(&shuffled[from..from + PK7_BLOCK]);
+        }
+
+        let checksum = (PK7_BLOCKS_START..data.len())
+            .step_by(2)
+            .fold(0u16, |sum, i| sum.wrapping_add(Self::u16_at(&data, i)));
+        if checksum != Self::u16_at(&data, 0x06) {
+            return None;
+        }
+
+        let species = Self::u16_at(&data, 0x08).into();
+        if species == 0 {
+            return None;
+        }
+        Some(Self {
+            species,
+            tid: Self::u16_at(&data, 0x0C),
+            sid: Self::u16_at(&data, 0x0E),
+            pid: Self::u32_at(&data, 0x18),
+        })
+    }
+
+    pub(crate) fn shiny(&self) -> bool {
+        (self.tid ^ self.sid ^ (self.pid >> 16) as u16 ^ self.pid as u16) < 16
+    }
+}
+
+impl MemoryValue {
+    pub fn length(&self) -> u32 {
+        match self {
+            MemoryValue::Equals { .. } => 4,
+            MemoryValue::WildPK7 => PK7_SIZE,
+        }
+    }
+}
+
+// Check memory read for a Memory3DS processing, the result isn't met if
+// nothing could be read
+pub(crate) fn check(settings: &MemoryDetect3DSSettings, data: Option<&[u8]>) -> ProcessingResult {
+    let (met, species, shiny) = match (&settings.value, data) {
+        (MemoryValue::Equals { mask, value }, Some(data)) if data.len() == 4 => {
+            let word = PK7::u32_at(data, 0);
+            ((word & mask) == *value, 0, false)
+        }
+        (MemoryValue::WildPK7, Some(data)) => match PK7::decrypt(data) {
+            Some(pk) => {
+                log::debug!("Wild PK7 {:?} (shiny={})", pk, pk.shiny());
+                (true, pk.species, pk.shiny())
+            }
+            None => (false, 0, false),
+        },
+        _ => (false, 0, false),
+    };
+
+    ProcessingResult {
+        process: Processing::Memory3DS(settings.clone()),
+        met,
+        species,
+        shiny,
+    }
+}
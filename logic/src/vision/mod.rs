@@ -4,14 +4,22 @@ mod bishaan_vision;
 pub mod compat;
 mod ds_vision;
 pub mod found;
+mod memory;
 mod nop_vision;
 mod ntr;
+mod reassembly;
 mod utils;
+mod windows;
 
 pub use bishaan_vision::{BishaanVision, BishaanVisionSocket};
 pub use ds_vision::Vision;
 pub use nop_vision::NopVision;
-pub use ntr::NTRPacket;
+pub use ntr::{
+    MemoryRequest, NTRDebugClient, NTRMemoryReader, NTRPacket, NTRProcess, NTRStreamSettings,
+};
+pub use reassembly::{ScreenStats, StreamStats};
+pub use windows::DebugWindows;
+pub(crate) use windows::WinInfo;
 
 #[derive(PartialEq, Clone, Debug)]
 pub struct RegionDetectSettings {
@@ -71,6 +79,23 @@ pub struct ChannelDetectSettings {
     pub num_thresh: i32,
 }
 
+#[derive(PartialEq, Clone, Debug)]
+pub enum MemoryValue {
+    // Little-endian word at the address, masked
+    Equals { mask: u32, value: u32 },
+    // Encrypted PK7 of a wild Pokemon, met when valid, with its species and
+    // whether it's shiny
+    WildPK7,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct MemoryDetect3DSSettings {
+    // Name of the process to read from, as listed by NTR
+    pub process: &'static str,
+    pub address: u32,
+    pub value: MemoryValue,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Processing {
     // List of sprites to check, and should it be flipped
@@ -85,6 +110,8 @@ pub enum Processing {
     USUMShinyStar(u32),
     USUMBottomScreen(f64),
     USUMBottomScreenInv(f64),
+    // Read from game memory through NTR instead of the frame
+    Memory3DS(MemoryDetect3DSSettings),
     SetFound(bool),
 }
 
@@ -211,6 +238,14 @@ impl Processing {
             colour: ColourChannel::Green,
             top: false,
         });
+    // Opponent in a wild battle. Unverified, the address hasn't been checked
+    // against every version of the game. Reads are validated, so if it is
+    // wrong hunts fall back to visual detection.
+    pub const USUM_WILD_PK7: Self = Processing::Memory3DS(MemoryDetect3DSSettings {
+        process: "niji_loc",
+        address: 0x3002F9A0,
+        value: MemoryValue::WildPK7,
+    });
     pub const FRLG_SHINY_STAR_OLD: Self = Processing::RegionDetect(RegionDetectSettings {
         x: 106,
         y: 52,
@@ -298,17 +333,14 @@ pub struct ProcessingResult {
     pub shiny: bool,
 }
 
-struct WinInfo {
-    name: &'static str,
-    x: i32,
-    y: i32,
-    scale: i32,
-}
-
 pub trait BotVision {
     fn process_next_frame(&mut self, processing: &[Processing]) -> Option<Vec<ProcessingResult>>;
     fn read_frame(&self) -> &[u8];
     fn read_frame2(&self) -> &[u8];
     fn read_found(&mut self) -> &[u8];
     fn new_found(&self) -> bool;
+    // Try to restore a lost video source, returns true if connected
+    fn reconnect(&mut self) -> bool {
+        true
+    }
 }
@@ -1,7 +1,8 @@
-use crate::vision::{BotVision, ProcessingResult};
+use crate::vision::{BotVision, DebugWindows, ProcessingResult};
 
 pub struct NopVision {
     frame: Vec<u8>,
+    windows: DebugWindows,
 }
 
 impl BotVision for NopVision {
@@ -19,7 +20,7 @@ impl BotVision for NopVision {
             });
         }
 
-        opencv::highgui::wait_key(1).expect("Event loop failed");
+        self.windows.wait();
 
         Some(results)
     }
@@ -42,19 +43,19 @@ impl BotVision for NopVision {
 }
 
 impl NopVision {
-    pub fn new() -> Self {
+    pub fn new(windows: DebugWindows) -> Self {
         let frame = if let Ok(f) = std::fs::read("static/metamon.png") {
             f
         } else {
             vec![]
         };
 
-        NopVision { frame }
+        NopVision { frame, windows }
     }
 }
 
 impl Default for NopVision {
     fn default() -> Self {
-        NopVision::new()
+        NopVision::new(DebugWindows::default())
     }
 }
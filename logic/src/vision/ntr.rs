@@ -1,81 +1,166 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{broadcast, mpsc, oneshot},
+    task::JoinHandle,
+};
+
+// Remote play settings, sent when streaming starts
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NTRStreamSettings {
+    // Screen sent more often
+    pub top_priority: bool,
+    // Frames of the priority screen sent for each frame of the other
+    pub priority_factor: u32,
+    // JPEG quality
+    pub quality: u32,
+    // Bandwidth limit in Mbps
+    pub qos: u32,
+}
+
+impl Default for NTRStreamSettings {
+    fn default() -> Self {
+        Self {
+            top_priority: true,
+            priority_factor: 2,
+            quality: 20,
+            qos: 16,
+        }
+    }
+}
+
+impl NTRStreamSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.priority_factor > 0xff {
+            Err(format!(
+                "Priority factor {} is more than 255",
+                self.priority_factor
+            ))
+        } else if !(10..=100).contains(&self.quality) {
+            Err(format!("Quality {} isn't between 10 and 100", self.quality))
+        } else if !(1..=100).contains(&self.qos) {
+            Err(format!("QoS {} isn't between 1 and 100", self.qos))
+        } else {
+            Ok(())
+        }
+    }
+}
+
 pub struct NTRPacket {
     seq: u32,
     typ: u32,
     cmd: u32,
-    arg_priority: u32,
-    arg_quality: u32,
-    arg_qos: u32,
-    args_extra: Vec<u32>,
+    args: [u32; 16],
     data_length: u32,
 }
 
 impl NTRPacket {
     pub const HDR_SIZE: usize = 4 + (4 * 3) + (4 * 16) + 4;
+    const MAGIC: u32 = 0x12345678;
+    pub const CMD_HEARTBEAT: u32 = 0;
+    pub const CMD_LIST_PROCESS: u32 = 5;
+    pub const CMD_READ_MEMORY: u32 = 9;
+    pub const CMD_WRITE_MEMORY: u32 = 10;
+    pub const CMD_REMOTE_PLAY: u32 = 901;
+    // Type of packets followed by data
+    const TYPE_DATA: u32 = 1;
 
     pub fn heartbeat(seq: u32) -> Self {
-        // TODO incrementing sequence
-        NTRPacket {
-            seq,
-            typ: 0,
-            cmd: 0,
-            arg_priority: 0,
-            arg_quality: 0,
-            arg_qos: 0,
-            args_extra: vec![],
-            data_length: 0,
-        }
+        Self::command(seq, Self::CMD_HEARTBEAT, &[])
     }
 
-    pub fn init() -> Self {
+    pub fn init(seq: u32, settings: &NTRStreamSettings) -> Self {
         // Start Streaming Packet
-        // seq = 3000
         // type = 0
         // cmd = 901
-        // args[0] = top_screen<<8 | (priority%256)
+        // args[0] = screen<<8 | (priority%256), screen is 0 for top, 1 for bottom
         // args[1] = quality
         // args[2] = (qos*2)<<16
-        let arg_priority = 0 << 8 | (2 % 256);
-        let arg_quality = 20;
-        let arg_qos = (16 * 2) << 16;
+        let screen = if settings.top_priority { 0 } else { 1 };
+        let arg_priority = screen << 8 | (settings.priority_factor % 256);
+        let arg_quality = settings.quality;
+        let arg_qos = (settings.qos * 2) << 16;
+        Self::command(
+            seq,
+            Self::CMD_REMOTE_PLAY,
+            &[arg_priority, arg_quality, arg_qos],
+        )
+    }
+
+    // Command without any data, unused arguments are zero
+    pub fn command(seq: u32, cmd: u32, args: &[u32]) -> Self {
+        let mut all_args = [0; 16];
+        all_args[..args.len()].copy_from_slice(args);
         NTRPacket {
-            seq: 3000,
+            seq,
             typ: 0,
-            cmd: 901,
-            arg_priority,
-            arg_quality,
-            arg_qos,
-            args_extra: vec![],
+            cmd,
+            args: all_args,
             data_length: 0,
         }
     }
 
+    // Command followed by data_length bytes of data, which are sent after
+    // the header
+    pub fn data_command(seq: u32, cmd: u32, args: &[u32], data_length: u32) -> Self {
+        NTRPacket {
+            typ: Self::TYPE_DATA,
+            data_length,
+            ..Self::command(seq, cmd, args)
+        }
+    }
+
+    pub fn list_process(seq: u32) -> Self {
+        Self::command(seq, Self::CMD_LIST_PROCESS, &[])
+    }
+
+    pub fn read_memory(seq: u32, pid: u32, address: u32, length: u32) -> Self {
+        Self::command(seq, Self::CMD_READ_MEMORY, &[pid, address, length])
+    }
+
+    pub fn write_memory(seq: u32, pid: u32, address: u32, length: u32) -> Self {
+        Self::data_command(seq, Self::CMD_WRITE_MEMORY, &[pid, address, length], length)
+    }
+
     pub fn extra_len(&self) -> usize {
         self.data_length.try_into().unwrap()
     }
 
     pub fn from_wire(bytes: &[u8; Self::HDR_SIZE]) -> Option<Self> {
-        let magic = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
-        let seq = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
-        let typ = u32::from_be_bytes(bytes[8..12].try_into().unwrap());
-        let cmd = u32::from_be_bytes(bytes[12..16].try_into().unwrap());
-        let mut args: Vec<_> = Vec::with_capacity(16);
-        for i in 0..16 {
-            let start = 16 + (i * 4);
-            let end = start + 4;
-            let arg = u32::from_be_bytes(bytes[start..end].try_into().unwrap());
-            args.push(arg);
-        }
-        let extra_length = u32::from_le_bytes(bytes[80..84].try_into().unwrap());
-
-        if magic == 0x78563412 {
+        // Every field is little-endian, as written by to_wire and by NTR on the
+        // 3DS. Until the virtual NTR server needed to parse requests, all but
+        // the data length were read as big-endian. That went unnoticed because
+        // the magic was compared byte-swapped and only the data length of
+        // replies was used, but the sequence number and command are now
+        // needed to match memory read replies to their requests.
+        let word = |start: usize| u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+        let magic = word(0);
+        let seq = word(4);
+        let typ = word(8);
+        let cmd = word(12);
+        let args = std::array::from_fn(|i| word(16 + (i * 4)));
+        let extra_length = word(80);
+
+        if magic == Self::MAGIC {
             Some(Self {
                 seq,
                 typ,
                 cmd,
-                arg_priority: 0,
-                arg_quality: 0,
-                arg_qos: 0,
-                args_extra: args,
+                args,
                 data_length: extra_length,
             })
         } else {
@@ -87,14 +172,38 @@ impl NTRPacket {
         self.seq
     }
 
+    pub fn cmd(&self) -> u32 {
+        self.cmd
+    }
+
+    pub fn arg(&self, index: usize) -> u32 {
+        self.args[index]
+    }
+
+    pub fn is_heartbeat(&self) -> bool {
+        self.cmd == Self::CMD_HEARTBEAT
+    }
+
+    pub fn is_init(&self) -> bool {
+        self.cmd == Self::CMD_REMOTE_PLAY
+    }
+
+    // Settings requested by an init packet
+    pub fn stream_settings(&self) -> NTRStreamSettings {
+        NTRStreamSettings {
+            top_priority: (self.args[0] >> 8) & 0x1 == 0,
+            priority_factor: self.args[0] & 0xff,
+            quality: self.args[1],
+            qos: (self.args[2] >> 16) / 2,
+        }
+    }
+
+    // Header only, any data is sent separately
     pub fn to_wire(&self) -> Vec<u8> {
-        let mut buf = vec![];
+        let mut buf = Vec::with_capacity(Self::HDR_SIZE);
 
         // Magic number
-        buf.push(0x78);
-        buf.push(0x56);
-        buf.push(0x34);
-        buf.push(0x12);
+        buf.extend_from_slice(&Self::MAGIC.to_le_bytes());
 
         // Sequence
         buf.extend_from_slice(&self.seq.to_le_bytes());
@@ -102,24 +211,412 @@ impl NTRPacket {
         buf.extend_from_slice(&self.typ.to_le_bytes());
         // Command
         buf.extend_from_slice(&self.cmd.to_le_bytes());
-        // Arg[0]
-        buf.extend_from_slice(&self.arg_priority.to_le_bytes());
-        // Arg[1]
-        buf.extend_from_slice(&self.arg_quality.to_le_bytes());
-        // Arg[2]
-        buf.extend_from_slice(&self.arg_qos.to_le_bytes());
-        // Args 3..16 and rest of packet
-        while buf.len() < 84 {
-            buf.push(0x00);
+        // Args
+        for arg in self.args {
+            buf.extend_from_slice(&arg.to_le_bytes());
         }
+        // Length of data after the header
+        buf.extend_from_slice(&self.data_length.to_le_bytes());
+
+        buf
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NTRProcess {
+    pub pid: u32,
+    pub name: String,
+    pub title_id: u64,
+}
 
-        if self.args_extra.len() > 0 {
-            log::error!("Extra arguments unsupported when converting to wire format");
+impl NTRProcess {
+    // Parse a line of the process list printed by NTR, e.g.
+    // pid: 0x00000029, pname: niji_loc, tid: 0004000000175e00, kpobj: fff76c70
+    fn parse(line: &str) -> Option<Self> {
+        let mut pid = None;
+        let mut name = None;
+        let mut title_id = None;
+        for field in line.split(',') {
+            let (key, value) = field.split_once(':')?;
+            let value = value.trim();
+            match key.trim() {
+                "pid" => pid = u32::from_str_radix(value.trim_start_matches("0x"), 16).ok(),
+                "pname" => name = Some(value.to_string()),
+                "tid" => title_id = u64::from_str_radix(value, 16).ok(),
+                _ => {}
+            }
         }
-        if self.data_length != 0 {
-            log::error!("Extra data unsupported when converting to wire format");
+        Some(Self {
+            pid: pid?,
+            name: name?,
+            title_id: title_id?,
+        })
+    }
+}
+
+struct ClientInner {
+    write: tokio::sync::Mutex<OwnedWriteHalf>,
+    seq: Arc<AtomicU32>,
+    // Memory reads waiting for a reply, by sequence number
+    replies: std::sync::Mutex<HashMap<u32, oneshot::Sender<Vec<u8>>>>,
+    // Output printed by NTR, a line at a time
+    log: broadcast::Sender<String>,
+    // Process ids found so far, by name
+    pids: std::sync::Mutex<HashMap<String, u32>>,
+    can_heartbeat: Arc<AtomicBool>,
+}
+
+// Client for the NTR debugger, sharing the connection used to keep remote
+// play alive. NTR only allows a single debug connection.
+#[derive(Clone)]
+pub struct NTRDebugClient {
+    inner: Arc<ClientInner>,
+}
+
+impl NTRDebugClient {
+    const HEARTBEAT_PERIOD: Duration = Duration::from_millis(250);
+    // NTR doesn't reply to requests it can't complete
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+    // Largest reply accepted, anything bigger is a corrupt header
+    const MAX_DATA: usize = 0x100000;
+
+    // Take over a connected debug socket. Heartbeats are sent while
+    // can_heartbeat is set, which also collects NTR output to be logged. The
+    // returned task completes when the connection is lost. Sequence numbers
+    // come from seq, so they can carry on from earlier connections.
+    pub fn new(
+        stream: TcpStream,
+        can_heartbeat: Arc<AtomicBool>,
+        seq: Arc<AtomicU32>,
+    ) -> (Self, JoinHandle<()>) {
+        let (read, write) = stream.into_split();
+        let (log, _) = broadcast::channel(64);
+        let client = Self {
+            inner: Arc::new(ClientInner {
+                write: tokio::sync::Mutex::new(write),
+                seq,
+                replies: std::sync::Mutex::new(HashMap::new()),
+                log,
+                pids: std::sync::Mutex::new(HashMap::new()),
+                can_heartbeat,
+            }),
+        };
+        let reader = client.clone();
+        let heartbeat = client.clone();
+        let handle = tokio::spawn(async move {
+            tokio::select! {
+                _ = reader.read_task(read) => {}
+                _ = heartbeat.heartbeat_task() => {}
+            }
+        });
+        (client, handle)
+    }
+
+    fn next_seq(&self) -> u32 {
+        self.inner.seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn check_allowed(&self) -> io::Result<()> {
+        if self.inner.can_heartbeat.load(Ordering::Acquire) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "NTR debugger is paused",
+            ))
         }
+    }
 
-        buf
+    async fn send(&self, packet: &NTRPacket, data: &[u8]) -> io::Result<()> {
+        let mut write = self.inner.write.lock().await;
+        write.write_all(&packet.to_wire()).await?;
+        write.write_all(data).await
+    }
+
+    async fn heartbeat(&self) -> io::Result<()> {
+        self.send(&NTRPacket::heartbeat(self.next_seq()), &[]).await
+    }
+
+    // Returns when a heartbeat can't be sent
+    async fn heartbeat_task(&self) {
+        loop {
+            tokio::time::sleep(Self::HEARTBEAT_PERIOD).await;
+            if self.inner.can_heartbeat.load(Ordering::Acquire)
+                && let Err(e) = self.heartbeat().await
+            {
+                log::error!("Heartbeat send error: {:?}", e);
+                break;
+            }
+        }
+    }
+
+    // Returns when the connection is lost
+    async fn read_task(&self, mut read: OwnedReadHalf) {
+        let mut header = [0u8; NTRPacket::HDR_SIZE];
+        loop {
+            if let Err(e) = read.read_exact(&mut header).await {
+                log::warn!("NTR debug socket closed: {}", e);
+                break;
+            }
+            let Some(packet) = NTRPacket::from_wire(&header) else {
+                log::error!("Invalid packet on NTR debug socket");
+                break;
+            };
+            if packet.extra_len() > Self::MAX_DATA {
+                log::error!("NTR reply too long ({} bytes)", packet.extra_len());
+                break;
+            }
+            let mut data = vec![0u8; packet.extra_len()];
+            if let Err(e) = read.read_exact(&mut data).await {
+                log::warn!("NTR debug socket closed: {}", e);
+                break;
+            }
+
+            if packet.cmd() == NTRPacket::CMD_READ_MEMORY {
+                let reply = self.inner.replies.lock().unwrap().remove(&packet.seq());
+                match reply {
+                    Some(reply) => {
+                        let _ = reply.send(data);
+                    }
+                    None => log::debug!("Unexpected memory read reply (seq={})", packet.seq()),
+                }
+            } else {
+                for line in String::from_utf8_lossy(&data)
+                    .split('\n')
+                    .filter(|l| !l.is_empty())
+                {
+                    log::info!("[NTR({})] {}", packet.seq(), line);
+                    let _ = self.inner.log.send(line.to_string());
+                }
+            }
+        }
+        // Fails any reads still waiting
+        self.inner.replies.lock().unwrap().clear();
+    }
+
+    // Restart remote play with new settings
+    pub async fn remote_play(&self, settings: &NTRStreamSettings) -> io::Result<()> {
+        self.send(&NTRPacket::init(self.next_seq(), settings), &[])
+            .await
+    }
+
+    pub async fn list_processes(&self) -> io::Result<Vec<NTRProcess>> {
+        self.check_allowed()?;
+        let mut log = self.inner.log.subscribe();
+        self.send(&NTRPacket::list_process(self.next_seq()), &[])
+            .await?;
+
+        // The list is printed to the log, which is collected by heartbeats
+        let mut processes = Vec::new();
+        let collect = async {
+            loop {
+                tokio::select! {
+                    line = log.recv() => match line {
+                        Ok(line) if line.starts_with("end of process list") => return Ok(()),
+                        Ok(line) => processes.extend(NTRProcess::parse(&line)),
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            return Err(io::Error::other(format!("Missed {} lines of the process list", n)));
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            return Err(io::ErrorKind::ConnectionAborted.into());
+                        }
+                    },
+                    _ = tokio::time::sleep(Self::HEARTBEAT_PERIOD / 2) => self.heartbeat().await?,
+                }
+            }
+        };
+        tokio::time::timeout(Self::REPLY_TIMEOUT, collect)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No process list from NTR"))??;
+
+        let mut pids = self.inner.pids.lock().unwrap();
+        pids.clear();
+        for p in &processes {
+            pids.insert(p.name.clone(), p.pid);
+        }
+        Ok(processes)
+    }
+
+    // Process id for a process name, the process list is only requested if
+    // the process hasn't been seen before
+    pub async fn find_process(&self, name: &str) -> io::Result<u32> {
+        if let Some(pid) = self.inner.pids.lock().unwrap().get(name) {
+            return Ok(*pid);
+        }
+        self.list_processes()
+            .await?
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.pid)
+            .ok_or(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No process named {}", name),
+            ))
+    }
+
+    pub async fn read_memory(&self, pid: u32, address: u32, length: u32) -> io::Result<Vec<u8>> {
+        self.check_allowed()?;
+        let seq = self.next_seq();
+        let (tx, rx) = oneshot::channel();
+        self.inner.replies.lock().unwrap().insert(seq, tx);
+        let result = async {
+            self.send(&NTRPacket::read_memory(seq, pid, address, length), &[])
+                .await?;
+            match tokio::time::timeout(Self::REPLY_TIMEOUT, rx).await {
+                Ok(Ok(data)) if data.len() == length as usize => Ok(data),
+                Ok(Ok(data)) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Read {} bytes, expected {}", data.len(), length),
+                )),
+                Ok(Err(_)) => Err(io::ErrorKind::ConnectionAborted.into()),
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("No reply reading {:#010x} from process {}", address, pid),
+                )),
+            }
+        }
+        .await;
+        self.inner.replies.lock().unwrap().remove(&seq);
+        result
+    }
+
+    // NTR doesn't acknowledge writes, read back to check
+    pub async fn write_memory(&self, pid: u32, address: u32, data: &[u8]) -> io::Result<()> {
+        self.check_allowed()?;
+        let length = data
+            .len()
+            .try_into()
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.send(
+            &NTRPacket::write_memory(self.next_seq(), pid, address, length),
+            data,
+        )
+        .await?;
+        // Requests are handled in order, so the read sees the write
+        if self.read_memory(pid, address, length).await? == data {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Write to {:#010x} in process {} didn't stick", address, pid),
+            ))
+        }
+    }
+
+    // Read from a process by name. The process id is looked up again if the
+    // read fails, as it changes when the game restarts.
+    pub async fn read_process_memory(
+        &self,
+        name: &str,
+        address: u32,
+        length: u32,
+    ) -> io::Result<Vec<u8>> {
+        let pid = self.find_process(name).await?;
+        let result = self.read_memory(pid, address, length).await;
+        if result.is_err() {
+            self.inner.pids.lock().unwrap().remove(name);
+        }
+        result
+    }
+}
+
+// Memory read passed from NTRMemoryReader to the task holding the debug
+// connection
+pub struct MemoryRequest {
+    process: String,
+    address: u32,
+    length: u32,
+    reply: std::sync::mpsc::Sender<io::Result<Vec<u8>>>,
+}
+
+impl MemoryRequest {
+    pub async fn serve(self, client: NTRDebugClient) {
+        let result = client
+            .read_process_memory(&self.process, self.address, self.length)
+            .await;
+        let _ = self.reply.send(result);
+    }
+}
+
+struct PendingRead {
+    process: String,
+    address: u32,
+    length: u32,
+    sent: Instant,
+    rx: std::sync::mpsc::Receiver<io::Result<Vec<u8>>>,
+}
+
+// Reads memory through the NTR debug connection from outside the runtime,
+// without blocking, so that reads don't hold up frame processing or timers
+pub struct NTRMemoryReader {
+    tx: mpsc::Sender<MemoryRequest>,
+    pending: Option<PendingRead>,
+}
+
+impl NTRMemoryReader {
+    // Older results could be from before the state being checked
+    const MAX_AGE: Duration = Duration::from_millis(500);
+    const GIVE_UP: Duration = Duration::from_secs(5);
+
+    pub fn channel() -> (Self, mpsc::Receiver<MemoryRequest>) {
+        let (tx, rx) = mpsc::channel(4);
+        (Self { tx, pending: None }, rx)
+    }
+
+    // Returns the result of a recent read of the same memory, if any, and
+    // starts the next read if none is in progress
+    pub fn poll(
+        &mut self,
+        process: &str,
+        address: u32,
+        length: u32,
+    ) -> Option<io::Result<Vec<u8>>> {
+        let mut result = None;
+        if let Some(pending) = &self.pending {
+            match pending.rx.try_recv() {
+                Ok(r) => {
+                    if pending.process == process
+                        && pending.address == address
+                        && pending.length == length
+                        && pending.sent.elapsed() < Self::MAX_AGE
+                    {
+                        result = Some(r);
+                    }
+                    self.pending = None;
+                }
+                Err(std::sync::mpsc::TryRecvError::Empty) => {
+                    if pending.sent.elapsed() < Self::GIVE_UP {
+                        return None;
+                    }
+                    self.pending = None;
+                }
+                Err(std::sync::mpsc::TryRecvError::Disconnected) => self.pending = None,
+            }
+        }
+
+        let (reply, rx) = std::sync::mpsc::channel();
+        let request = MemoryRequest {
+            process: process.to_string(),
+            address,
+            length,
+            reply,
+        };
+        match self.tx.try_send(request) {
+            Ok(_) => {
+                self.pending = Some(PendingRead {
+                    process: process.to_string(),
+                    address,
+                    length,
+                    sent: Instant::now(),
+                    rx,
+                })
+            }
+            Err(_) => {
+                if result.is_none() {
+                    result = Some(Err(io::ErrorKind::NotConnected.into()));
+                }
+            }
+        }
+        result
     }
 }
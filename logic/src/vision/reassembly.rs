@@ -0,0 +1,197 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+// Number of frames that can be in progress at once, so fragments of a frame
+// can arrive after fragments of the next one
+const WINDOW: usize = 3;
+// Period the frame and drop rates are measured over
+const RATE_PERIOD: Duration = Duration::from_secs(1);
+// A fragment this far behind the newest frame means the stream has restarted
+const RESTART_DISTANCE: i8 = -64;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ScreenStats {
+    // Totals since the stream started
+    pub frames: u64,
+    pub dropped: u64,
+    pub fragments: u64,
+    pub reordered: u64,
+    pub duplicates: u64,
+    pub late: u64,
+    // Over the last period
+    pub fps: f64,
+    pub drop_rate: f64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StreamStats {
+    pub top: ScreenStats,
+    pub bottom: ScreenStats,
+}
+
+struct PartialFrame {
+    id: u8,
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    last: Option<u8>,
+}
+
+impl PartialFrame {
+    fn new(id: u8) -> Self {
+        Self {
+            id,
+            fragments: Vec::new(),
+            received: 0,
+            last: None,
+        }
+    }
+
+    fn complete(&self) -> bool {
+        self.last
+            .is_some_and(|l| self.received == usize::from(l) + 1)
+    }
+}
+
+// Reassembles the JPEG fragments of one screen from NTR, tolerating
+// fragments arriving out of order within a window of frames
+pub(crate) struct FrameReassembler {
+    // Oldest first
+    partial: VecDeque<PartialFrame>,
+    // Newest frame that was completed or abandoned, anything older is late
+    floor: Option<u8>,
+    stats: ScreenStats,
+    period_start: Instant,
+    period_frames: u64,
+    period_dropped: u64,
+}
+
+impl FrameReassembler {
+    pub(crate) fn new() -> Self {
+        Self {
+            partial: VecDeque::new(),
+            floor: None,
+            stats: ScreenStats::default(),
+            period_start: Instant::now(),
+            period_frames: 0,
+            period_dropped: 0,
+        }
+    }
+
+    // Frame ids wrap, so compare by distance
+    fn distance(a: u8, b: u8) -> i8 {
+        a.wrapping_sub(b) as i8
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn stats(&self) -> &ScreenStats {
+        &self.stats
+    }
+
+    fn drop_frame(&mut self, frame: PartialFrame) {
+        log::debug!(
+            "Dropped frame {} with {} fragments",
+            frame.id,
+            frame.received
+        );
+        self.stats.dropped += 1;
+        self.period_dropped += 1;
+        self.raise_floor(frame.id);
+    }
+
+    fn raise_floor(&mut self, id: u8) {
+        if self.floor.is_none_or(|f| Self::distance(id, f) > 0) {
+            self.floor = Some(id);
+        }
+    }
+
+    // Add a fragment, returns the frame if it is now complete
+    pub(crate) fn add(&mut self, id: u8, seq: u8, last: bool, data: &[u8]) -> Option<Vec<u8>> {
+        self.stats.fragments += 1;
+        if let Some(floor) = self.floor {
+            let distance = Self::distance(id, floor);
+            if distance < RESTART_DISTANCE {
+                log::info!("Frame {} is far behind {}, restarting stream", id, floor);
+                self.partial.clear();
+                self.floor = None;
+            } else if distance <= 0 {
+                self.stats.late += 1;
+                return None;
+            }
+        }
+
+        let index = match self.partial.iter().position(|p| p.id == id) {
+            Some(i) => i,
+            None => {
+                let i = self
+                    .partial
+                    .iter()
+                    .position(|p| Self::distance(p.id, id) > 0)
+                    .unwrap_or(self.partial.len());
+                self.partial.insert(i, PartialFrame::new(id));
+                i
+            }
+        };
+
+        let frame = &mut self.partial[index];
+        let seq_idx = usize::from(seq);
+        if seq_idx < frame.fragments.len() {
+            if frame.fragments[seq_idx].is_some() {
+                self.stats.duplicates += 1;
+                return None;
+            }
+            self.stats.reordered += 1;
+        } else {
+            frame.fragments.resize(seq_idx + 1, None);
+        }
+        frame.fragments[seq_idx] = Some(data.to_vec());
+        frame.received += 1;
+        if last {
+            frame.last = Some(seq);
+        }
+
+        if frame.complete() {
+            let frame = self.partial.remove(index).expect("Frame must be present");
+            // Older frames would go backwards if completed now
+            let older: Vec<_> = self.partial.drain(..index).collect();
+            for f in older {
+                self.drop_frame(f);
+            }
+            self.raise_floor(frame.id);
+            self.stats.frames += 1;
+            self.period_frames += 1;
+            Some(frame.fragments.into_iter().flatten().flatten().collect())
+        } else {
+            while self.partial.len() > WINDOW {
+                let oldest = self.partial.pop_front().expect("Window can't be empty");
+                self.drop_frame(oldest);
+            }
+            None
+        }
+    }
+
+    // Update the frame and drop rates, returns true at the end of each period
+    pub(crate) fn update_rates(&mut self) -> bool {
+        let elapsed = self.period_start.elapsed();
+        if elapsed < RATE_PERIOD {
+            return false;
+        }
+        let total = self.period_frames + self.period_dropped;
+        self.stats.fps = self.period_frames as f64 / elapsed.as_secs_f64();
+        self.stats.drop_rate = if total > 0 {
+            self.period_dropped as f64 / total as f64
+        } else {
+            0.0
+        };
+        self.period_start = Instant::now();
+        self.period_frames = 0;
+        self.period_dropped = 0;
+        true
+    }
+}
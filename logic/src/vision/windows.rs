@@ -0,0 +1,98 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use opencv::{
+    core::{Mat, Vector},
+    highgui,
+};
+
+pub(crate) struct WinInfo {
+    pub(crate) name: &'static str,
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    pub(crate) scale: i32,
+    // Size before scaling, None to fit the image
+    pub(crate) size: Option<(i32, i32)>,
+    pub(crate) flags: i32,
+}
+
+// Debug images, drawn in highgui windows when there is a display or kept for
+// the web API when headless. They are only encoded as PNGs when requested, as
+// the vision loop shows them every frame
+#[derive(Clone, Default)]
+pub struct DebugWindows {
+    headless: bool,
+    created: Arc<Mutex<HashSet<&'static str>>>,
+    images: Arc<Mutex<HashMap<&'static str, Mat>>>,
+}
+
+impl DebugWindows {
+    pub fn new(headless: bool) -> Self {
+        Self {
+            headless,
+            ..Default::default()
+        }
+    }
+
+    pub fn headless(&self) -> bool {
+        self.headless
+    }
+
+    fn transform(win: &WinInfo) {
+        highgui::move_window(win.name, win.x, win.y)
+            .unwrap_or_else(|_| panic!("Failed to move '{}' window", win.name));
+        if let Some((w, h)) = win.size {
+            highgui::resize_window(win.name, w * win.scale, h * win.scale)
+                .unwrap_or_else(|_| panic!("Failed to resize '{}' window", win.name));
+        }
+    }
+
+    // Open the window now rather than when it is first shown
+    pub(crate) fn create(&self, win: &WinInfo) {
+        if self.headless || !self.created.lock().unwrap().insert(win.name) {
+            return;
+        }
+        highgui::named_window(win.name, win.flags)
+            .unwrap_or_else(|_| panic!("Failed to create '{}' window", win.name));
+        Self::transform(win);
+    }
+
+    pub(crate) fn show(&self, win: &WinInfo, mat: &Mat) {
+        if self.headless {
+            self.images.lock().unwrap().insert(win.name, mat.clone());
+        } else {
+            self.create(win);
+            highgui::imshow(win.name, mat)
+                .unwrap_or_else(|_| panic!("Failed to show '{}' window", win.name));
+            Self::transform(win);
+        }
+    }
+
+    // Let highgui draw, does nothing when headless
+    pub fn wait(&self) {
+        if !self.headless {
+            highgui::wait_key(1).expect("Event loop failed");
+        }
+    }
+
+    // Images shown so far when headless
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names: Vec<_> = self.images.lock().unwrap().keys().copied().collect();
+        names.sort();
+        names
+    }
+
+    pub fn image(&self, name: &str) -> Option<Vec<u8>> {
+        let mat = self.images.lock().unwrap().get(name)?.clone();
+        let mut encoded = Vector::new();
+        match opencv::imgcodecs::imencode(".png", &mat, &mut encoded, &Vector::new()) {
+            Ok(_) => Some(encoded.to_vec()),
+            Err(e) => {
+                log::warn!("Failed to encode '{}' image: {}", name, e);
+                None
+            }
+        }
+    }
+}